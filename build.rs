@@ -1,8 +1,15 @@
-use cmake::Config;
-use std::env;
-use std::path::PathBuf;
-
 fn main() {
+    // Only the `ffi` backend needs the C++ library, `native-linux` is pure Rust
+    #[cfg(feature = "ffi")]
+    build_freewili_finder();
+}
+
+#[cfg(feature = "ffi")]
+fn build_freewili_finder() {
+    use cmake::Config;
+    use std::env;
+    use std::path::PathBuf;
+
     let mut config = Config::new("freewili-finder");
     let profile = std::env::var("PROFILE").unwrap();
     let cmake_profile = match profile.as_str() {
@@ -7,7 +7,7 @@
  *
  * This helps understand the physical USB topology and device positioning.
  */
-use freewili_finder_rs::{FreeWiliDevice, FreeWiliError};
+use freewili_finder_rs::{FreeWiliDevice, FreeWiliError, NodeKind, USBDevice, UsbTopology};
 
 fn main() -> Result<(), FreeWiliError> {
     println!("Testing Port Chain Analysis");
@@ -112,27 +112,23 @@ fn analyze_port_chain(port_chain: &[u32], location: u32) {
 }
 
 /// Analyze the overall USB topology for a device
-fn analyze_device_topology(usb_devices: &[freewili_finder_rs::USBDevice]) {
-    if usb_devices.is_empty() {
+fn analyze_device_topology(usb_devices: &[USBDevice]) {
+    let topology = UsbTopology::from_usb_devices(usb_devices.iter().cloned());
+    if topology.is_empty() {
         return;
     }
 
     println!("  Topology Summary:");
 
-    // Find the maximum depth
-    let max_depth = usb_devices
-        .iter()
-        .map(|dev| dev.port_chain.len())
+    // Count nodes at each depth below the roots, including hubs no device was reported for
+    let max_depth = topology
+        .nodes()
+        .map(|id| topology.node(id).depth())
         .max()
         .unwrap_or(0);
-
-    // Count devices at each depth level
     let mut depth_counts = vec![0; max_depth + 1];
-    for device in usb_devices {
-        let depth = device.port_chain.len();
-        if depth < depth_counts.len() {
-            depth_counts[depth] += 1;
-        }
+    for id in topology.nodes() {
+        depth_counts[topology.node(id).depth()] += 1;
     }
 
     println!("    Maximum hub depth: {}", max_depth);
@@ -140,48 +136,50 @@ fn analyze_device_topology(usb_devices: &[freewili_finder_rs::USBDevice]) {
     for (depth, count) in depth_counts.iter().enumerate() {
         if *count > 0 {
             let level_desc = match depth {
-                0 => "Direct connections".to_string(),
+                0 => "Roots".to_string(),
                 1 => "1 hub level".to_string(),
                 n => format!("{} hub levels", n),
             };
-            println!("    {}: {} device(s)", level_desc, count);
+            println!("    {}: {} node(s)", level_desc, count);
         }
     }
 
-    // Look for potential hub devices
-    let potential_hubs: Vec<_> = usb_devices
-        .iter()
-        .filter(|dev| dev.kind_name.to_lowercase().contains("hub"))
-        .collect();
-
-    if !potential_hubs.is_empty() {
-        println!("    Hub devices detected: {}", potential_hubs.len());
-        for hub in potential_hubs {
-            println!(
-                "      - {} at chain depth {}",
-                hub.name,
-                hub.port_chain.len()
-            );
-        }
+    // Walk the tree, showing each hub with the ports behind it
+    for id in topology.nodes() {
+        let node = topology.node(id);
+        let label = match (node.kind(), node.usb_device()) {
+            (NodeKind::RootHub, _) => "Root hub".to_string(),
+            (NodeKind::Hub, None) => "Unreported hub".to_string(),
+            (_, Some(usb_device)) => format!("{} ({})", usb_device.name, usb_device.kind_name),
+            (NodeKind::Device, None) => unreachable!(),
+        };
+        let position = match (node.depth(), node.bus()) {
+            (0, Some(bus)) => format!("bus {bus}"),
+            (0, None) => "unknown bus".to_string(),
+            _ => format!("port {}", node.port()),
+        };
+        println!(
+            "    {}└─ {}: {}",
+            "  ".repeat(node.depth()),
+            position,
+            label
+        );
     }
 
     // Check for devices sharing the same port chain (shouldn't happen but useful to detect)
-    let mut port_chain_map: std::collections::HashMap<Vec<u32>, Vec<&str>> =
-        std::collections::HashMap::new();
-    for device in usb_devices {
-        port_chain_map
-            .entry(device.port_chain.clone())
-            .or_default()
-            .push(&device.name);
-    }
-
-    for (chain, devices) in port_chain_map {
-        if devices.len() > 1 {
+    for id in topology.nodes() {
+        let node = topology.node(id);
+        if node.usb_devices().len() > 1 {
+            let names: Vec<_> = node.usb_devices().iter().map(|d| d.name.as_str()).collect();
             println!(
                 "    ⚠️  Multiple devices share port chain {:?}: {}",
-                chain,
-                devices.join(", ")
+                node.port_chain(),
+                names.join(", ")
             );
         }
     }
+
+    for usb_device in topology.unplaced() {
+        println!("    ⚠️  {} has no port chain", usb_device.name);
+    }
 }
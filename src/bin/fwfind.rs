@@ -0,0 +1,642 @@
+//! `fwfind` - list and inspect connected FreeWili devices.
+//!
+//! Exit codes: 0 on success, 1 when no device matched, 2 on invalid usage and 3 when
+//! enumeration failed.
+
+use std::process::ExitCode;
+
+use freewili_finder_rs::capture::Capture;
+use freewili_finder_rs::{DeviceSelector, DeviceSnapshot, FreeWiliDevice, USBDevice, UsbTopology};
+
+const USAGE: &str = "\
+Usage: fwfind [--format text|json|csv] [--replay FILE] [COMMAND]
+
+Commands:
+  list              List all connected FreeWili devices (default)
+  show <SELECTOR>   Show every detail of the devices matching SELECTOR, either a
+                    query like 'serial=FW4037 or vid:pid=093c:2058' or a
+                    serial number, unique ID or part of the device name
+  tree              Show the USB devices of each FreeWili ordered by port chain
+  ports             List the serial ports of each FreeWili
+  topology [STYLE]  Draw the USB tree of all FreeWilis as an ascii tree like
+                    'lsusb -t' (default), a Graphviz 'dot' graph or 'mermaid',
+                    only with the text format
+  capture [FILE]    Record every device to a JSON capture, written to FILE or
+                    printed, for replaying with --replay or FREEWILI_REPLAY
+
+Options:
+  -f, --format <FORMAT>  Output format: text, json or csv [default: text]
+  -r, --replay <FILE>    Read the devices from a capture instead of the system
+  -h, --help             Print this help
+  -V, --version          Print the version";
+
+const EXIT_NO_MATCH: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_ERROR: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TopologyStyle {
+    Ascii,
+    Dot,
+    Mermaid,
+}
+
+#[derive(Debug)]
+enum Command {
+    List,
+    Show(DeviceSelector),
+    Tree,
+    Ports,
+    Topology(TopologyStyle),
+    Capture(Option<String>),
+    Help,
+    Version,
+}
+
+#[derive(Debug)]
+struct Args {
+    format: Format,
+    replay: Option<String>,
+    command: Command,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut format = Format::Text;
+        let mut replay = None;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            match flag.as_str() {
+                "-h" | "--help" => {
+                    return Ok(Args {
+                        format,
+                        replay,
+                        command: Command::Help,
+                    });
+                }
+                "-V" | "--version" => {
+                    return Ok(Args {
+                        format,
+                        replay,
+                        command: Command::Version,
+                    });
+                }
+                "-f" | "--format" => {
+                    let value = match inline_value {
+                        Some(value) => value.to_string(),
+                        None => args.next().ok_or("--format requires a value")?,
+                    };
+                    format = match value.as_str() {
+                        "text" => Format::Text,
+                        "json" => Format::Json,
+                        "csv" => Format::Csv,
+                        other => return Err(format!("unknown format '{other}'")),
+                    };
+                }
+                "-r" | "--replay" => {
+                    replay = Some(match inline_value {
+                        Some(value) => value.to_string(),
+                        None => args.next().ok_or("--replay requires a value")?,
+                    });
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("unknown option '{arg}'"));
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let command = match positional.next().as_deref() {
+            None | Some("list") => Command::List,
+            Some("show") => Command::Show(parse_selector(
+                &positional
+                    .next()
+                    .ok_or("show requires a SELECTOR argument")?,
+            )?),
+            Some("tree") => Command::Tree,
+            Some("ports") => Command::Ports,
+            Some("topology") => Command::Topology(match positional.next().as_deref() {
+                None | Some("ascii") => TopologyStyle::Ascii,
+                Some("dot") => TopologyStyle::Dot,
+                Some("mermaid") => TopologyStyle::Mermaid,
+                Some(other) => return Err(format!("unknown topology style '{other}'")),
+            }),
+            Some("capture") => Command::Capture(positional.next()),
+            Some(other) => return Err(format!("unknown command '{other}'")),
+        };
+        if let Some(extra) = positional.next() {
+            return Err(format!("unexpected argument '{extra}'"));
+        }
+        if matches!(command, Command::Topology(_)) && format != Format::Text {
+            return Err("topology only supports the text format, pick a STYLE instead".into());
+        }
+
+        Ok(Args {
+            format,
+            replay,
+            command,
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("fwfind: {e}\n\n{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let devices = match args.command {
+        Command::Help => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Command::Version => {
+            println!("fwfind {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        _ => match find_all(args.replay.as_deref()) {
+            Ok(devices) => devices,
+            Err(e) => {
+                eprintln!("fwfind: failed to find devices: {e}");
+                return ExitCode::from(EXIT_ERROR);
+            }
+        },
+    };
+
+    if let Command::Capture(path) = &args.command {
+        // An empty capture is still a useful answer to "why is my device not found"
+        let capture = Capture::from_snapshots(devices);
+        return match path {
+            Some(path) => match capture.save(path) {
+                Ok(()) => {
+                    eprintln!(
+                        "fwfind: recorded {} device(s) to {path}",
+                        capture.devices.len()
+                    );
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    eprintln!("fwfind: {e}");
+                    ExitCode::from(EXIT_ERROR)
+                }
+            },
+            None => {
+                println!("{}", capture.to_json());
+                ExitCode::SUCCESS
+            }
+        };
+    }
+
+    let devices = match &args.command {
+        Command::Show(selector) => devices
+            .into_iter()
+            .filter(|device| selector.matches(device))
+            .collect(),
+        _ => devices,
+    };
+    if devices.is_empty() {
+        match &args.command {
+            Command::Show(selector) => eprintln!("fwfind: no device matches '{selector}'"),
+            _ => eprintln!("fwfind: no FreeWili devices found"),
+        }
+        return ExitCode::from(EXIT_NO_MATCH);
+    }
+
+    let output = match (&args.command, args.format) {
+        (Command::List, Format::Text) => list_text(&devices),
+        (Command::List, Format::Json) => to_json(&devices),
+        (Command::List, Format::Csv) => list_csv(&devices),
+        (Command::Show(_), Format::Text) => show_text(&devices),
+        (Command::Show(_), Format::Json) => to_json(&devices),
+        (Command::Show(_), Format::Csv) => usb_devices_csv(&devices, false),
+        (Command::Tree, Format::Text) => tree_text(&devices),
+        (Command::Tree, Format::Json) => tree_json(&devices),
+        (Command::Tree, Format::Csv) => usb_devices_csv(&devices, true),
+        (Command::Ports, Format::Text) => ports_text(&devices),
+        (Command::Ports, Format::Json) => to_json(&port_rows(&devices)),
+        (Command::Ports, Format::Csv) => ports_csv(&devices),
+        (Command::Topology(style), Format::Text) => {
+            let topology = UsbTopology::from_snapshots(devices);
+            match style {
+                TopologyStyle::Ascii => topology.to_ascii_tree(),
+                TopologyStyle::Dot => topology.to_dot(),
+                TopologyStyle::Mermaid => topology.to_mermaid(),
+            }
+        }
+        (Command::Topology(_), Format::Json | Format::Csv)
+        | (Command::Capture(_) | Command::Help | Command::Version, _) => unreachable!(),
+    };
+    print!("{output}");
+
+    ExitCode::SUCCESS
+}
+
+/// Finds the devices of the system, or of a capture file when replaying.
+fn find_all(replay: Option<&str>) -> Result<Vec<DeviceSnapshot>, String> {
+    match replay {
+        Some(path) => Capture::load(path)
+            .map(|capture| capture.devices)
+            .map_err(|e| format!("{path}: {e}")),
+        None => FreeWiliDevice::find_all_snapshots().map_err(|e| e.to_string()),
+    }
+}
+
+/// Parses a [`DeviceSelector`] query, or matches a bare word against the serial number,
+/// unique ID and name.
+fn parse_selector(selector: &str) -> Result<DeviceSelector, String> {
+    if selector.contains('=') {
+        return selector.parse().map_err(|e| format!("{e}"));
+    }
+    let mut any = DeviceSelector::Serial(selector.to_string())
+        .or(DeviceSelector::NameContains(selector.to_string()));
+    if let Ok(unique_id) = selector.parse() {
+        any = any.or(DeviceSelector::UniqueId(unique_id));
+    }
+    Ok(any)
+}
+
+fn sorted_by_port_chain(device: &DeviceSnapshot) -> Vec<&USBDevice> {
+    let mut usb_devices: Vec<_> = device.usb_devices.iter().collect();
+    usb_devices.sort_by(|a, b| a.port_chain.cmp(&b.port_chain));
+    usb_devices
+}
+
+fn format_port_chain(port_chain: &[u32]) -> String {
+    port_chain
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Serial ports of a device, labelled with the CPU or chip they belong to.
+fn serial_ports(device: &DeviceSnapshot) -> Vec<(&'static str, &USBDevice)> {
+    [
+        ("main", &device.main),
+        ("display", &device.display),
+        ("fpga", &device.fpga),
+    ]
+    .into_iter()
+    .filter_map(|(label, usb_device)| {
+        usb_device
+            .as_ref()
+            .filter(|usb_device| usb_device.port.is_some())
+            .map(|usb_device| (label, usb_device))
+    })
+    .collect()
+}
+
+fn to_json<T: serde::Serialize + ?Sized>(value: &T) -> String {
+    let mut json = serde_json::to_string_pretty(value).expect("serializing to a string");
+    json.push('\n');
+    json
+}
+
+fn csv_row<I: IntoIterator<Item = String>>(fields: I) -> String {
+    let mut row = fields
+        .into_iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    row.push('\n');
+    row
+}
+
+fn list_text(devices: &[DeviceSnapshot]) -> String {
+    let mut output = format!("Found {} FreeWili(s)\n", devices.len());
+    for (i, device) in devices.iter().enumerate() {
+        output += &format!(
+            "{}. {} ({}, unique ID {})\n",
+            i + 1,
+            device,
+            device.device_type_name,
+            device.unique_id
+        );
+        for (j, usb_device) in device.usb_devices.iter().enumerate() {
+            output += &format!("\t{}. {}\n", j + 1, usb_device);
+        }
+    }
+    output
+}
+
+fn list_csv(devices: &[DeviceSnapshot]) -> String {
+    let port = |usb_device: &Option<USBDevice>| {
+        usb_device
+            .as_ref()
+            .and_then(|usb_device| usb_device.port.clone())
+            .unwrap_or_default()
+    };
+    let mut output = csv_row(
+        [
+            "unique_id",
+            "device_type",
+            "name",
+            "serial",
+            "standalone",
+            "main_port",
+            "display_port",
+            "fpga_port",
+            "usb_device_count",
+        ]
+        .map(String::from),
+    );
+    for device in devices {
+        output += &csv_row([
+            device.unique_id.to_string(),
+            device.device_type_name.clone(),
+            device.name.clone(),
+            device.serial.clone(),
+            device.standalone.to_string(),
+            port(&device.main),
+            port(&device.display),
+            port(&device.fpga),
+            device.usb_devices.len().to_string(),
+        ]);
+    }
+    output
+}
+
+fn show_text(devices: &[DeviceSnapshot]) -> String {
+    let mut output = String::new();
+    for device in devices {
+        output += &format!("{device}\n");
+        output += &format!("\ttype: {:?}\n", device.device_type);
+        output += &format!("\ttype name: {}\n", device.device_type_name);
+        output += &format!("\tname: {}\n", device.name);
+        output += &format!("\tserial: {}\n", device.serial);
+        output += &format!("\tunique ID: {}\n", device.unique_id);
+        output += &format!("\tstandalone: {}\n", device.standalone);
+        output += &format!("\tUSB devices ({}):\n", device.usb_devices.len());
+        for (i, usb_device) in device.usb_devices.iter().enumerate() {
+            output += &format!("\t\t{}: {}\n", i + 1, usb_device.kind_name);
+            output += &format!("\t\t\tname: {}\n", usb_device.name);
+            output += &format!("\t\t\tserial: {}\n", usb_device.serial);
+            output += &format!(
+                "\t\t\tVID: {:04x} PID: {:04x}\n",
+                usb_device.vid, usb_device.pid
+            );
+            output += &format!("\t\t\tlocation: {}\n", usb_device.location);
+            output += &format!(
+                "\t\t\tport chain: {}\n",
+                format_port_chain(&usb_device.port_chain)
+            );
+            if let Some(path) = &usb_device.path {
+                output += &format!("\t\t\tpath: {path}\n");
+            }
+            if let Ok(info) = usb_device.bootloader_info() {
+                output += &format!("\t\t\tbootloader: UF2 Bootloader {}\n", info.version);
+                if let Some(model) = &info.model {
+                    output += &format!("\t\t\tbootloader model: {model}\n");
+                }
+                if let Some(board_id) = &info.board_id {
+                    output += &format!("\t\t\tboard ID: {board_id}\n");
+                }
+            }
+            if let Some(port) = &usb_device.port {
+                output += &format!("\t\t\tport: {port}\n");
+            }
+            if let Some(port) = usb_device.port_by_id() {
+                output += &format!("\t\t\tport by ID: {port}\n");
+            }
+            if let Some(port) = usb_device.port_by_path() {
+                output += &format!("\t\t\tport by path: {port}\n");
+            }
+        }
+        for (label, usb_device) in [
+            ("Main", &device.main),
+            ("Display", &device.display),
+            ("FPGA", &device.fpga),
+            ("HUB", &device.hub),
+        ] {
+            match usb_device {
+                Some(usb_device) => {
+                    output += &format!("\t{label} USB device: {}\n", usb_device.kind_name)
+                }
+                None => output += &format!("\tNo {label} USB device found\n"),
+            }
+        }
+    }
+    output
+}
+
+fn usb_devices_csv(devices: &[DeviceSnapshot], sorted: bool) -> String {
+    let mut output = csv_row(
+        [
+            "unique_id",
+            "device_serial",
+            "kind",
+            "kind_name",
+            "vid",
+            "pid",
+            "name",
+            "serial",
+            "location",
+            "port_chain",
+            "port",
+            "path",
+        ]
+        .map(String::from),
+    );
+    for device in devices {
+        let usb_devices = if sorted {
+            sorted_by_port_chain(device)
+        } else {
+            device.usb_devices.iter().collect()
+        };
+        for usb_device in usb_devices {
+            output += &csv_row([
+                device.unique_id.to_string(),
+                device.serial.clone(),
+                format!("{:?}", usb_device.kind),
+                usb_device.kind_name.clone(),
+                format!("{:04x}", usb_device.vid),
+                format!("{:04x}", usb_device.pid),
+                usb_device.name.clone(),
+                usb_device.serial.clone(),
+                usb_device.location.to_string(),
+                format_port_chain(&usb_device.port_chain),
+                usb_device.port.clone().unwrap_or_default(),
+                usb_device.path.clone().unwrap_or_default(),
+            ]);
+        }
+    }
+    output
+}
+
+fn tree_text(devices: &[DeviceSnapshot]) -> String {
+    let mut output = String::new();
+    for device in devices {
+        output += &format!("{} [unique ID {}]\n", device, device.unique_id);
+        let usb_devices = sorted_by_port_chain(device);
+        let root_depth = usb_devices
+            .iter()
+            .map(|usb_device| usb_device.port_chain.len())
+            .min()
+            .unwrap_or(0);
+        for usb_device in usb_devices {
+            let depth = usb_device.port_chain.len() - root_depth;
+            output += &format!(
+                "{}└─ {} {:04x}:{:04x} [{}]",
+                "   ".repeat(depth),
+                usb_device.kind_name,
+                usb_device.vid,
+                usb_device.pid,
+                format_port_chain(&usb_device.port_chain)
+            );
+            if let Some(target) = usb_device.port.as_ref().or(usb_device.path.as_ref()) {
+                output += &format!(" {target}");
+            }
+            output.push('\n');
+        }
+    }
+    output
+}
+
+fn tree_json(devices: &[DeviceSnapshot]) -> String {
+    let trees: Vec<_> = devices
+        .iter()
+        .map(|device| {
+            serde_json::json!({
+                "unique_id": device.unique_id,
+                "name": device.name,
+                "serial": device.serial,
+                "usb_devices": sorted_by_port_chain(device),
+            })
+        })
+        .collect();
+    to_json(&trees)
+}
+
+fn port_rows(devices: &[DeviceSnapshot]) -> Vec<serde_json::Value> {
+    devices
+        .iter()
+        .flat_map(|device| {
+            serial_ports(device)
+                .into_iter()
+                .map(move |(label, usb_device)| {
+                    serde_json::json!({
+                        "unique_id": device.unique_id,
+                        "serial": device.serial,
+                        "interface": label,
+                        "kind": usb_device.kind,
+                        "port": usb_device.port,
+                    })
+                })
+        })
+        .collect()
+}
+
+fn ports_text(devices: &[DeviceSnapshot]) -> String {
+    let mut output = String::new();
+    for device in devices {
+        output += &format!("{device}\n");
+        for (label, usb_device) in serial_ports(device) {
+            output += &format!(
+                "\t{label:<8}{}\n",
+                usb_device.port.as_deref().unwrap_or_default()
+            );
+        }
+    }
+    output
+}
+
+fn ports_csv(devices: &[DeviceSnapshot]) -> String {
+    let mut output = csv_row(["unique_id", "serial", "interface", "port"].map(String::from));
+    for device in devices {
+        for (label, usb_device) in serial_ports(device) {
+            output += &csv_row([
+                device.unique_id.to_string(),
+                device.serial.clone(),
+                label.to_string(),
+                usb_device.port.clone().unwrap_or_default(),
+            ]);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&[]).unwrap();
+        assert!(matches!(args.command, Command::List));
+        assert_eq!(args.format, Format::Text);
+
+        let args = parse(&["--format=json", "show", "FW4037"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Show(DeviceSelector::Or(ref selectors)) if selectors.len() == 2
+        ));
+        assert_eq!(args.format, Format::Json);
+
+        let args = parse(&["show", "serial=FW4037 and ready=main"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Show(DeviceSelector::And(_))
+        ));
+
+        let args = parse(&["ports", "-f", "csv"]).unwrap();
+        assert!(matches!(args.command, Command::Ports));
+        assert_eq!(args.format, Format::Csv);
+
+        let args = parse(&["--replay", "capture.json", "capture"]).unwrap();
+        assert!(matches!(args.command, Command::Capture(None)));
+        assert_eq!(args.replay.as_deref(), Some("capture.json"));
+
+        let args = parse(&["topology", "dot"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Topology(TopologyStyle::Dot)
+        ));
+        assert!(parse(&["topology", "svg"]).is_err());
+        assert!(parse(&["--format", "json", "topology"]).is_err());
+        assert!(parse(&["topology", "mermaid", "-f", "csv"]).is_err());
+        assert!(parse(&["-f", "text", "topology"]).is_ok());
+
+        let args = parse(&["capture", "out.json"]).unwrap();
+        assert!(matches!(args.command, Command::Capture(Some(ref path)) if path == "out.json"));
+
+        assert!(parse(&["show"]).is_err());
+        assert!(parse(&["show", "color=red"]).is_err());
+        assert!(parse(&["--format", "xml"]).is_err());
+        assert!(parse(&["tree", "extra"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_csv_row_quoting() {
+        assert_eq!(
+            csv_row(["plain", "a,b", "say \"hi\""].map(String::from)),
+            "plain,\"a,b\",\"say \"\"hi\"\"\"\n"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Rebooting a FreeWili CPU into its UF2 bootloader.
+//!
+//! Like other RP2040 and RP2350 boards, the CPUs reboot into the bootloader when their
+//! serial port is opened at 1200 baud and closed again. The bootloader then enumerates as
+//! a mass storage device on the same USB port:
+//!
+//! ```no_run
+//! use freewili_finder_rs::FreeWiliDevice;
+//! use freewili_finder_rs::bootloader::Cpu;
+//!
+//! for device in FreeWiliDevice::find_all()? {
+//!     let bootloader = device.enter_bootloader(Cpu::Display)?;
+//!     println!("{}", bootloader.get_display_usb_device()?);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::serial::{self, SerialError, SerialSettings};
+use crate::{
+    DeviceSnapshot, DeviceSource, FreeWiliDevice, FreeWiliError, Interface, SystemDevices,
+    USBDevice, UsbDeviceType,
+};
+
+/// Baud rate that makes the CPUs reboot into the bootloader.
+const TOUCH_BAUD_RATE: u32 = 1200;
+/// How long [`FreeWiliDevice::enter_bootloader`] waits for the bootloader to enumerate.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between looking for the bootloader while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// CPU of a FreeWili that runs UF2 firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cpu {
+    /// Main CPU, or the only CPU of standalone devices
+    Main,
+    /// Display CPU
+    Display,
+}
+
+impl Cpu {
+    /// The CPU's USB device in `device`.
+    pub(crate) fn usb_device(self, device: &DeviceSnapshot) -> Result<USBDevice, SerialError> {
+        Interface::from(self)
+            .usb_device(device)
+            .cloned()
+            .ok_or(SerialError::MissingInterface(self.into()))
+    }
+}
+
+impl From<Cpu> for Interface {
+    fn from(cpu: Cpu) -> Self {
+        match cpu {
+            Cpu::Main => Interface::Main,
+            Cpu::Display => Interface::Display,
+        }
+    }
+}
+
+/// Errors returned when rebooting a CPU into the bootloader.
+#[derive(Error, Debug)]
+pub enum BootloaderError {
+    /// The CPU's serial port is missing or couldn't be opened
+    #[error(transparent)]
+    Serial(#[from] SerialError),
+    /// Reading the device or looking for the bootloader failed
+    #[error("Failed to find the bootloader: {0}")]
+    Device(#[from] FreeWiliError),
+    /// No bootloader appeared on the CPU's USB port in time
+    #[error("No UF2 bootloader appeared at port chain {port_chain:?} within {timeout:?}")]
+    Timeout {
+        /// Port chain of the CPU
+        port_chain: Vec<u32>,
+        /// How long was waited
+        timeout: Duration,
+    },
+}
+
+impl FreeWiliDevice {
+    /// Reboots `cpu` into its UF2 bootloader and returns the device the bootloader
+    /// enumerated as, waiting up to [`DEFAULT_TIMEOUT`].
+    ///
+    /// For a Free-WiLi that is the same Free-WiLi with a
+    /// [`MassStorage`](UsbDeviceType::MassStorage) device in place of the CPU's serial port,
+    /// for standalone devices a [`Uf2`](crate::DeviceType::Uf2) device. A CPU that already
+    /// is in the bootloader is returned as found again without touching anything.
+    pub fn enter_bootloader(&self, cpu: Cpu) -> Result<FreeWiliDevice, BootloaderError> {
+        self.enter_bootloader_with(cpu, &SystemDevices, DEFAULT_TIMEOUT)
+    }
+
+    /// Like [`enter_bootloader`](Self::enter_bootloader), but reads this device from and
+    /// looks for the bootloader in `source`, and waits up to `timeout`.
+    pub fn enter_bootloader_with(
+        &self,
+        cpu: Cpu,
+        source: &impl DeviceSource,
+        timeout: Duration,
+    ) -> Result<FreeWiliDevice, BootloaderError> {
+        let usb_device = cpu.usb_device(&source.snapshot(self)?)?;
+        if usb_device.kind != UsbDeviceType::MassStorage {
+            touch(cpu, &usb_device)?;
+        }
+        let is_bootloader = |usb_device: &USBDevice| usb_device.kind == UsbDeviceType::MassStorage;
+        wait_for_port(source, &usb_device.port_chain, timeout, is_bootloader)?.ok_or(
+            BootloaderError::Timeout {
+                port_chain: usb_device.port_chain,
+                timeout,
+            },
+        )
+    }
+}
+
+/// Opens the CPU's serial port at 1200 baud and closes it again.
+fn touch(cpu: Cpu, usb_device: &USBDevice) -> Result<(), SerialError> {
+    let settings = SerialSettings::new(TOUCH_BAUD_RATE).with_timeout(Duration::from_millis(100));
+    let mut port = serial::open_port(cpu.into(), usb_device, &settings)?;
+    // The CPU may already be gone, failures past this point don't matter
+    let _ = port.write_data_terminal_ready(false);
+    Ok(())
+}
+
+/// Waits up to `timeout` for a device with a USB device at `port_chain` that is
+/// `accepted`, returning `None` if none appears.
+pub(crate) fn wait_for_port(
+    source: &impl DeviceSource,
+    port_chain: &[u32],
+    timeout: Duration,
+    accept: impl Fn(&USBDevice) -> bool,
+) -> Result<Option<FreeWiliDevice>, FreeWiliError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let found = source.find_all()?.into_iter().find(|device| {
+            source.get_usb_devices(device).is_ok_and(|usb_devices| {
+                usb_devices
+                    .iter()
+                    .any(|usb_device| usb_device.port_chain == port_chain && accept(usb_device))
+            })
+        });
+        if found.is_some() {
+            return Ok(found);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+    use crate::testing::{FakeFreeWili, FakeSource};
+
+    #[test]
+    fn test_errors() {
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::new("FW4037")
+                .with_main("/nonexistent/ttyACM0")
+                .with_port_chain([3, 4]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+        let timeout = Duration::from_millis(50);
+
+        assert!(matches!(
+            device.enter_bootloader_with(Cpu::Display, &source, timeout),
+            Err(BootloaderError::Serial(SerialError::MissingInterface(
+                Interface::Display
+            )))
+        ));
+        assert!(matches!(
+            device.enter_bootloader_with(Cpu::Main, &source, timeout),
+            Err(BootloaderError::Serial(SerialError::Open { .. }))
+        ));
+
+        // Errors injected into the source and unplugged devices are seen before the touch
+        source.set_error("FW4037", Some(FreeWiliError::MemoryError));
+        assert!(matches!(
+            device.enter_bootloader_with(Cpu::Main, &source, timeout),
+            Err(BootloaderError::Device(FreeWiliError::MemoryError))
+        ));
+        source.set_error("FW4037", None);
+        let detached = FakeSource::new().with_device(
+            FakeFreeWili::new("FW4037")
+                .with_main("/nonexistent/ttyACM0")
+                .with_port_chain([3, 4]),
+        );
+        let gone = detached.find_all().unwrap().remove(0);
+        detached.detach("FW4037");
+        assert!(matches!(
+            gone.enter_bootloader_with(Cpu::Main, &detached, timeout),
+            Err(BootloaderError::Device(FreeWiliError::InvalidDevice))
+        ));
+
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::new("FW4037")
+                .with_main_uf2("/media/RPI-RP2")
+                .with_port_chain([3, 4]),
+        );
+        let uf2 = source.find_all().unwrap().remove(0);
+        source.fail_next_find_all(FreeWiliError::InternalError(None));
+        assert!(matches!(
+            uf2.enter_bootloader_with(Cpu::Main, &source, timeout),
+            Err(BootloaderError::Device(FreeWiliError::InternalError(None)))
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timeout() {
+        // The port takes the touch, but no bootloader ever shows up
+        let (_master, _slave, slave_path) = serial::open_pty();
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::new("FW4037")
+                .with_main(slave_path.to_string_lossy())
+                .with_port_chain([3, 4]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+        assert!(matches!(
+            device.enter_bootloader_with(Cpu::Main, &source, Duration::from_millis(50)),
+            Err(BootloaderError::Timeout { ref port_chain, .. }) if port_chain == &[3, 4, 1]
+        ));
+    }
+
+    #[test]
+    fn test_already_in_bootloader() {
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::standalone(DeviceType::Uf2, "E0C9125B0D9B")
+                .with_main_uf2("/media/RP2350")
+                .with_port_chain([1, 2]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+        let bootloader = device
+            .enter_bootloader_with(Cpu::Main, &source, Duration::ZERO)
+            .unwrap();
+        assert_eq!(bootloader.device_type().unwrap(), DeviceType::Uf2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_touch_pty() {
+        let (_master, slave, slave_path) = serial::open_pty();
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::new("FW4037")
+                .with_main("/dev/ttyACM0")
+                .with_display(slave_path.to_string_lossy())
+                .with_port_chain([3, 4]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+
+        // Stand-in for the display CPU: reboot once the port is set to 1200 baud
+        let cpu = std::thread::spawn({
+            let source = source.clone();
+            move || {
+                if !serial::wait_for_baud_rate(&slave, TOUCH_BAUD_RATE, Duration::from_secs(5)) {
+                    return false;
+                }
+                source.detach("FW4037");
+                source.attach(
+                    FakeFreeWili::new("FW4037")
+                        .with_main("/dev/ttyACM0")
+                        .with_display_uf2("/media/RPI-RP2")
+                        .with_port_chain([3, 4]),
+                );
+                true
+            }
+        });
+
+        let bootloader = device
+            .enter_bootloader_with(Cpu::Display, &source, Duration::from_secs(5))
+            .unwrap();
+        assert!(cpu.join().unwrap(), "port was never set to 1200 baud");
+        let display = bootloader.get_display_usb_device().unwrap();
+        assert_eq!(display.kind, UsbDeviceType::MassStorage);
+        assert_eq!(display.port_chain, [3, 4, 2]);
+        assert_eq!(display.path.as_deref(), Some("/media/RPI-RP2"));
+    }
+}
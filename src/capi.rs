@@ -0,0 +1,597 @@
+//! Backend calling into the freewili-finder C library.
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, c_char};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::ffi::_fw_devicetype_t::*;
+use crate::ffi::_fw_inttype_t::*;
+use crate::ffi::_fw_stringtype_t::*;
+use crate::ffi::{self, fw_error_t, fw_freewili_device_t, fw_stringtype_t};
+use crate::{
+    DeviceSnapshot, DeviceType, FreeWiliDevice, FreeWiliError, Result, USBDevice, UsbDeviceType,
+};
+
+impl From<ffi::_fw_error_t> for FreeWiliError {
+    fn from(error: ffi::_fw_error_t) -> Self {
+        match error {
+            ffi::_fw_error_t::fw_error_success => FreeWiliError::None,
+            ffi::_fw_error_t::fw_error_invalid_parameter => FreeWiliError::InvalidParameter,
+            ffi::_fw_error_t::fw_error_invalid_device => FreeWiliError::InvalidDevice,
+            ffi::_fw_error_t::fw_error_internal_error => FreeWiliError::InternalError(None),
+            ffi::_fw_error_t::fw_error_memory => FreeWiliError::MemoryError,
+            ffi::_fw_error_t::fw_error_no_more_devices => FreeWiliError::NoMoreDevices,
+            ffi::_fw_error_t::fw_error_none => FreeWiliError::None,
+            ffi::_fw_error_t::fw_error__maxvalue => FreeWiliError::InternalError(None),
+        }
+    }
+}
+
+impl From<fw_error_t> for FreeWiliError {
+    fn from(error_code: fw_error_t) -> Self {
+        match error_code {
+            x if x == ffi::_fw_error_t::fw_error_success as u32 => FreeWiliError::None,
+            x if x == ffi::_fw_error_t::fw_error_invalid_parameter as u32 => {
+                FreeWiliError::InvalidParameter
+            }
+            x if x == ffi::_fw_error_t::fw_error_invalid_device as u32 => {
+                FreeWiliError::InvalidDevice
+            }
+            x if x == ffi::_fw_error_t::fw_error_internal_error as u32 => {
+                FreeWiliError::InternalError(None)
+            }
+            x if x == ffi::_fw_error_t::fw_error_memory as u32 => FreeWiliError::MemoryError,
+            x if x == ffi::_fw_error_t::fw_error_no_more_devices as u32 => {
+                FreeWiliError::NoMoreDevices
+            }
+            x if x == ffi::_fw_error_t::fw_error_none as u32 => FreeWiliError::None,
+            x if x == ffi::_fw_error_t::fw_error__maxvalue as u32 => {
+                FreeWiliError::InternalError(None)
+            }
+            _ => FreeWiliError::InternalError(Some(format!("Unknown error code: {error_code}"))),
+        }
+    }
+}
+
+impl From<ffi::_fw_usbdevicetype_t> for UsbDeviceType {
+    fn from(device_type: ffi::_fw_usbdevicetype_t) -> Self {
+        match device_type {
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_hub => UsbDeviceType::Hub,
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serial => UsbDeviceType::Serial,
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serialmain => UsbDeviceType::SerialMain,
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serialdisplay => {
+                UsbDeviceType::SerialDisplay
+            }
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_massstorage => UsbDeviceType::MassStorage,
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_esp32 => UsbDeviceType::Esp32,
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_ftdi => UsbDeviceType::Ftdi,
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_other => UsbDeviceType::Other,
+            ffi::_fw_usbdevicetype_t::fw_usbdevicetype__maxvalue => UsbDeviceType::_MaxValue,
+        }
+    }
+}
+
+impl From<ffi::fw_usbdevicetype_t> for UsbDeviceType {
+    fn from(device_type: ffi::fw_usbdevicetype_t) -> Self {
+        match device_type {
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_hub as u32 => UsbDeviceType::Hub,
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serial as u32 => {
+                UsbDeviceType::Serial
+            }
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serialmain as u32 => {
+                UsbDeviceType::SerialMain
+            }
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serialdisplay as u32 => {
+                UsbDeviceType::SerialDisplay
+            }
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_massstorage as u32 => {
+                UsbDeviceType::MassStorage
+            }
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_esp32 as u32 => {
+                UsbDeviceType::Esp32
+            }
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_ftdi as u32 => UsbDeviceType::Ftdi,
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_other as u32 => {
+                UsbDeviceType::Other
+            }
+            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype__maxvalue as u32 => {
+                UsbDeviceType::_MaxValue
+            }
+            _ => UsbDeviceType::Other, // Default fallback
+        }
+    }
+}
+
+impl From<ffi::_fw_devicetype_t> for DeviceType {
+    fn from(device_type: ffi::_fw_devicetype_t) -> Self {
+        match device_type {
+            ffi::_fw_devicetype_t::fw_devicetype_unknown => DeviceType::Unknown,
+            ffi::_fw_devicetype_t::fw_devicetype_freewili => DeviceType::Freewili,
+            ffi::_fw_devicetype_t::fw_devicetype_defcon2024badge => DeviceType::Defcon2024Badge,
+            ffi::_fw_devicetype_t::fw_devicetype_defcon2025fwbadge => DeviceType::Defcon2025FwBadge,
+            ffi::_fw_devicetype_t::fw_devicetype_uf2 => DeviceType::Uf2,
+            ffi::_fw_devicetype_t::fw_devicetype_winky => DeviceType::Winky,
+        }
+    }
+}
+
+impl From<ffi::fw_devicetype_t> for DeviceType {
+    fn from(device_type: ffi::fw_devicetype_t) -> Self {
+        match device_type {
+            x if x == ffi::_fw_devicetype_t::fw_devicetype_unknown as u32 => DeviceType::Unknown,
+            x if x == ffi::_fw_devicetype_t::fw_devicetype_freewili as u32 => DeviceType::Freewili,
+            x if x == ffi::_fw_devicetype_t::fw_devicetype_defcon2024badge as u32 => {
+                DeviceType::Defcon2024Badge
+            }
+            x if x == ffi::_fw_devicetype_t::fw_devicetype_defcon2025fwbadge as u32 => {
+                DeviceType::Defcon2025FwBadge
+            }
+            x if x == ffi::_fw_devicetype_t::fw_devicetype_uf2 as u32 => DeviceType::Uf2,
+            x if x == ffi::_fw_devicetype_t::fw_devicetype_winky as u32 => DeviceType::Winky,
+            _ => DeviceType::Unknown, // Default fallback
+        }
+    }
+}
+
+/// Size of the first buffer passed to the C library for strings.
+const INITIAL_STRING_SIZE: usize = 256;
+/// Largest string buffer tried before giving up with [`FreeWiliError::MemoryError`].
+const MAX_STRING_SIZE: usize = 1 << 20;
+/// Size of the first buffer passed to the C library for port chains.
+const INITIAL_PORT_CHAIN_SIZE: usize = 8;
+/// Largest port chain buffer tried before giving up with [`FreeWiliError::MemoryError`].
+const MAX_PORT_CHAIN_SIZE: usize = 1024;
+/// Number of device handles requested from the C library at first.
+const INITIAL_DEVICE_COUNT: usize = 32;
+
+/// Reads a string from the C library, growing the buffer until the string fits.
+///
+/// `call` receives a buffer and its size and returns the C result, `copied` tells from
+/// that result whether the string was written. The C library truncates strings that
+/// don't fit without reporting it and sets the size to the copied length plus the
+/// terminator, so a string filling the whole buffer may be cut off and is read again
+/// with a bigger buffer.
+fn read_string(
+    copied: impl Fn(fw_error_t) -> bool,
+    mut call: impl FnMut(*mut c_char, &mut u32) -> fw_error_t,
+) -> Result<(fw_error_t, String)> {
+    let mut capacity = INITIAL_STRING_SIZE;
+    loop {
+        let mut buffer = vec![0u8; capacity];
+        let mut size = capacity as u32;
+        let res = call(buffer.as_mut_ptr() as *mut c_char, &mut size);
+        if !copied(res) {
+            return Ok((res, String::new()));
+        }
+        if (size as usize) < capacity {
+            let string = CStr::from_bytes_until_nul(&buffer)
+                .map_err(|_| FreeWiliError::InternalError(Some("Unterminated string".into())))?
+                .to_string_lossy()
+                .into_owned();
+            return Ok((res, string));
+        }
+        if capacity >= MAX_STRING_SIZE {
+            return Err(FreeWiliError::MemoryError);
+        }
+        capacity *= 4;
+    }
+}
+
+/// [`read_string`] for calls that only write the string on success.
+fn read_string_on_success(call: impl FnMut(*mut c_char, &mut u32) -> fw_error_t) -> Result<String> {
+    let (res, string) = read_string(
+        |res| res == ffi::_fw_error_t::fw_error_success as ffi::fw_error_t,
+        call,
+    )?;
+    if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
+        return Err(res.into());
+    }
+    Ok(string)
+}
+
+impl USBDevice {
+    /// # Safety
+    ///
+    /// The `device` pointer must be a valid pointer to a `fw_freewili_device_t` that is properly initialized
+    /// and has not been freed. The caller must ensure the device remains valid for the duration of this call.
+    pub unsafe fn from_device(device: *mut ffi::fw_freewili_device_t) -> Result<Self> {
+        let mut usb_device_type: ffi::fw_usbdevicetype_t =
+            fw_devicetype_unknown as ffi::fw_usbdevicetype_t;
+        let res = unsafe { ffi::fw_usb_device_get_type(device, &mut usb_device_type) };
+        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
+            return Err(res.into());
+        }
+
+        let usb_device_type_name = read_string_on_success(|buffer, size| unsafe {
+            ffi::fw_usb_device_get_type_name(
+                usb_device_type as ffi::fw_usbdevicetype_t,
+                buffer,
+                size,
+            )
+        })?;
+
+        let mut vid: u32 = 0;
+        let res = unsafe {
+            ffi::fw_usb_device_get_int(device, fw_inttype_vid as u32, &mut vid as *mut u32)
+        };
+        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
+            return Err(res.into());
+        }
+
+        let mut pid: u32 = 0;
+        let res = unsafe {
+            ffi::fw_usb_device_get_int(device, fw_inttype_pid as u32, &mut pid as *mut u32)
+        };
+        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
+            return Err(res.into());
+        }
+
+        let mut location: u32 = 0;
+        let res = unsafe {
+            ffi::fw_usb_device_get_int(
+                device,
+                fw_inttype_location as u32,
+                &mut location as *mut u32,
+            )
+        };
+        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
+            return Err(res.into());
+        }
+
+        let name = read_string_on_success(|buffer, size| unsafe {
+            ffi::fw_usb_device_get_str(device, fw_stringtype_name as fw_stringtype_t, buffer, size)
+        })?;
+
+        let serial = read_string_on_success(|buffer, size| unsafe {
+            ffi::fw_usb_device_get_str(
+                device,
+                fw_stringtype_serial as fw_stringtype_t,
+                buffer,
+                size,
+            )
+        })?;
+
+        // fw_error_none means the USB device has no port or path
+        let optional_string = |string_type: ffi::_fw_stringtype_t| -> Result<Option<String>> {
+            let (res, string) = read_string(
+                |res| res == ffi::_fw_error_t::fw_error_success as ffi::fw_error_t,
+                |buffer, size| unsafe {
+                    ffi::fw_usb_device_get_str(device, string_type as fw_stringtype_t, buffer, size)
+                },
+            )?;
+            match res {
+                x if x == ffi::_fw_error_t::fw_error_success as ffi::fw_error_t => {
+                    Ok(Some(string).filter(|string| !string.is_empty()))
+                }
+                x if x == ffi::_fw_error_t::fw_error_none as ffi::fw_error_t => Ok(None),
+                _ => Err(res.into()),
+            }
+        };
+        let port = optional_string(fw_stringtype_port)?;
+        let path = optional_string(fw_stringtype_path)?;
+
+        // The C library needs room for more entries than the chain has and doesn't
+        // report the required size, so grow until it fits
+        let mut capacity = INITIAL_PORT_CHAIN_SIZE;
+        let port_chain = loop {
+            let mut port_chain: Vec<u32> = vec![0u32; capacity];
+            let mut port_chain_size: u32 = port_chain.len() as u32;
+            let res = unsafe {
+                ffi::fw_usb_device_get_port_chain(
+                    device,
+                    port_chain.as_mut_ptr(),
+                    &mut port_chain_size,
+                )
+            };
+            if res == ffi::_fw_error_t::fw_error_memory as ffi::fw_error_t
+                && capacity < MAX_PORT_CHAIN_SIZE
+            {
+                capacity *= 2;
+                continue;
+            }
+            if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
+                return Err(res.into());
+            }
+            port_chain.truncate(port_chain_size as usize);
+            break port_chain;
+        };
+
+        let usb_device = USBDevice {
+            kind: usb_device_type.into(),
+            kind_name: usb_device_type_name,
+            vid: vid as u16,
+            pid: pid as u16,
+            name,
+            serial,
+            location,
+            port_chain,
+            port,
+            path,
+        };
+
+        Ok(usb_device)
+    }
+}
+
+/// Owner of a device pointer returned by `fw_device_find_all`.
+#[derive(Debug)]
+pub(crate) struct FreeWiliDeviceHandle {
+    ptr: *mut fw_freewili_device_t,
+}
+
+// SAFETY: The C device object isn't tied to the thread that created it, and the pointer
+// is only reachable through `FreeWiliDeviceHandle::lock`, which serializes every access.
+unsafe impl Send for FreeWiliDeviceHandle {}
+unsafe impl Sync for FreeWiliDeviceHandle {}
+
+/// Serializes every call into the C library.
+///
+/// The library keeps a process-wide device list without any locking of its own, and each
+/// device carries a single USB device iterator that `fw_usb_device_begin/next/set` mutate.
+/// The map counts the Rust owners of each device pointer: `fw_device_find_all` returns the
+/// same pointer for a device on every call, and it must only be freed once the last owner
+/// is gone.
+static FFI: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+fn ffi_lock() -> MutexGuard<'static, BTreeMap<usize, usize>> {
+    FFI.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Exclusive access to a device's C handle, held for a whole sequence of FFI calls.
+pub(crate) struct LockedHandle<'a> {
+    handle: &'a FreeWiliDeviceHandle,
+    _ffi: MutexGuard<'static, BTreeMap<usize, usize>>,
+}
+
+impl LockedHandle<'_> {
+    fn ptr(&self) -> *mut fw_freewili_device_t {
+        self.handle.ptr
+    }
+}
+
+impl FreeWiliDeviceHandle {
+    /// Registers a new owner of `ptr`.
+    fn new(owners: &mut BTreeMap<usize, usize>, ptr: *mut fw_freewili_device_t) -> Self {
+        *owners.entry(ptr as usize).or_default() += 1;
+        FreeWiliDeviceHandle { ptr }
+    }
+
+    pub(crate) fn lock(&self) -> LockedHandle<'_> {
+        LockedHandle {
+            handle: self,
+            _ffi: ffi_lock(),
+        }
+    }
+}
+
+impl Drop for FreeWiliDeviceHandle {
+    fn drop(&mut self) {
+        let mut owners = ffi_lock();
+        if let Some(count) = owners.get_mut(&(self.ptr as usize)) {
+            *count -= 1;
+            if *count > 0 {
+                return;
+            }
+            owners.remove(&(self.ptr as usize));
+        }
+        let _res: ffi::fw_error_t = unsafe { ffi::fw_device_free(&mut self.ptr, 1) };
+        if _res != ffi::_fw_error_t::fw_error_success as u32 {
+            eprintln!("Failed to free FreeWili device handle: {_res:?}");
+        }
+    }
+}
+
+/// Finds all connected devices through `fw_device_find_all`.
+pub(crate) fn find_all() -> Result<Vec<FreeWiliDevice>> {
+    let mut owners = ffi_lock();
+    let mut capacity = INITIAL_DEVICE_COUNT;
+    let devices = loop {
+        let mut devices: Vec<*mut fw_freewili_device_t> = vec![std::ptr::null_mut(); capacity];
+        let mut device_count: u32 = capacity as u32;
+        let (res, error_msg) = read_string(
+            |res| res == ffi::_fw_error_t::fw_error_internal_error as ffi::fw_error_t,
+            |error_msg, error_size| unsafe {
+                ffi::fw_device_find_all(
+                    devices.as_mut_ptr(),
+                    &mut device_count,
+                    error_msg,
+                    error_size,
+                )
+            },
+        )?;
+        match res {
+            x if x == ffi::_fw_error_t::fw_error_success as ffi::fw_error_t => {}
+            x if x == ffi::_fw_error_t::fw_error_internal_error as ffi::fw_error_t => {
+                return Err(FreeWiliError::InternalError(Some(error_msg)));
+            }
+            _ => return Err(res.into()),
+        }
+
+        // The C library fills at most `capacity` entries, so a full list may be missing some
+        if (device_count as usize) < capacity {
+            devices.truncate(device_count as usize);
+            break devices;
+        }
+        capacity *= 2;
+    };
+
+    let device_handles = devices
+        .into_iter()
+        .map(|device| {
+            FreeWiliDevice::from_handle(Arc::new(FreeWiliDeviceHandle::new(&mut owners, device)))
+        })
+        .collect();
+    Ok(device_handles)
+}
+
+pub(crate) fn device_type_name(device_type: DeviceType) -> Result<String> {
+    let _ffi = ffi_lock();
+    device_type_name_locked(device_type)
+}
+
+/// [`device_type_name`] for callers already holding [`ffi_lock`].
+fn device_type_name_locked(device_type: DeviceType) -> Result<String> {
+    read_string_on_success(|buffer, size| unsafe {
+        ffi::fw_device_get_type_name(device_type as ffi::fw_devicetype_t, buffer, size)
+    })
+}
+
+impl LockedHandle<'_> {
+    /// Reads the complete state of the device while the lock is held.
+    pub(crate) fn snapshot(&self) -> Result<DeviceSnapshot> {
+        let device = self;
+        let device_type = device.device_type()?;
+        let optional = |usb_device: Result<USBDevice>| match usb_device {
+            Ok(usb_device) => Ok(Some(usb_device)),
+            Err(FreeWiliError::NoMoreDevices) => Ok(None),
+            Err(e) => Err(e),
+        };
+
+        Ok(DeviceSnapshot {
+            unique_id: device.unique_id()?,
+            device_type,
+            device_type_name: device_type_name_locked(device_type)?,
+            name: device.device_string(ffi::_fw_stringtype_t::fw_stringtype_name)?,
+            serial: device.device_string(ffi::_fw_stringtype_t::fw_stringtype_serial)?,
+            standalone: device.standalone()?,
+            usb_devices: device.usb_devices()?,
+            main: optional(
+                device.usb_device(ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_main),
+            )?,
+            display: optional(
+                device.usb_device(ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_display),
+            )?,
+            fpga: optional(
+                device.usb_device(ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_fpga),
+            )?,
+            hub: optional(device.usb_device(ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_hub))?,
+        })
+    }
+
+    pub(crate) fn device_type(&self) -> Result<DeviceType> {
+        let mut device_type: ffi::fw_devicetype_t = 0;
+        let res = unsafe { ffi::fw_device_get_type(self.ptr(), &mut device_type) };
+        if res != ffi::_fw_error_t::fw_error_success as u32 {
+            return Err(res.into());
+        }
+
+        Ok(device_type.into())
+    }
+
+    pub(crate) fn device_string(&self, string_type: ffi::_fw_stringtype_t) -> Result<String> {
+        read_string_on_success(|buffer, size| unsafe {
+            ffi::fw_device_get_str(self.ptr(), string_type as u32, buffer, size)
+        })
+    }
+
+    pub(crate) fn unique_id(&self) -> Result<u64> {
+        let mut unique_id: u64 = 0;
+        let res = unsafe { ffi::fw_device_unique_id(self.ptr(), &mut unique_id as *mut u64) };
+        if res != ffi::_fw_error_t::fw_error_success as u32 {
+            return Err(res.into());
+        }
+        Ok(unique_id)
+    }
+
+    pub(crate) fn standalone(&self) -> Result<bool> {
+        let mut is_standalone: bool = false;
+        let res =
+            unsafe { ffi::fw_device_is_standalone(self.ptr(), &mut is_standalone as *mut bool) };
+        if res != ffi::_fw_error_t::fw_error_success as u32 {
+            return Err(res.into());
+        }
+        Ok(is_standalone)
+    }
+
+    pub(crate) fn usb_device_string(&self, string_type: ffi::_fw_stringtype_t) -> Result<String> {
+        read_string_on_success(|buffer, size| unsafe {
+            ffi::fw_usb_device_get_str(self.ptr(), string_type as u32, buffer, size)
+        })
+    }
+
+    pub(crate) fn usb_devices(&self) -> Result<Vec<USBDevice>> {
+        let res = unsafe { ffi::fw_usb_device_begin(self.ptr()) };
+        if res != ffi::_fw_error_t::fw_error_success as u32 {
+            return Err(res.into());
+        }
+
+        let mut devices = Vec::new();
+        loop {
+            let usb_device = unsafe { USBDevice::from_device(self.ptr())? };
+            devices.push(usb_device);
+
+            let res = unsafe { ffi::fw_usb_device_next(self.ptr()) };
+            if res != ffi::_fw_error_t::fw_error_success as u32 {
+                break;
+            }
+        }
+        Ok(devices)
+    }
+
+    pub(crate) fn usb_device(&self, iter_set: ffi::_fw_usbdevice_iter_set_t) -> Result<USBDevice> {
+        let (res, error_msg) = read_string(
+            |res| res == ffi::_fw_error_t::fw_error_internal_error as fw_error_t,
+            |error_msg, error_size| unsafe {
+                ffi::fw_usb_device_set(
+                    self.ptr(),
+                    iter_set as ffi::fw_usbdevice_iter_set_t,
+                    error_msg,
+                    error_size,
+                )
+            },
+        )?;
+        if res == ffi::_fw_error_t::fw_error_internal_error as fw_error_t {
+            return Err(FreeWiliError::InternalError(Some(error_msg)));
+        }
+        if res != ffi::_fw_error_t::fw_error_success as fw_error_t {
+            return Err(res.into());
+        }
+        unsafe { USBDevice::from_device(self.ptr()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Same truncating copy as `fixedStringCopy` in the C library.
+    fn fixed_string_copy(src: &str, dest: *mut c_char, dest_size: &mut u32) -> fw_error_t {
+        let dest = unsafe { std::slice::from_raw_parts_mut(dest as *mut u8, *dest_size as usize) };
+        let size = src.len().min(dest.len() - 1);
+        dest.fill(0);
+        dest[..size].copy_from_slice(&src.as_bytes()[..size]);
+        *dest_size = size as u32 + 1;
+        ffi::_fw_error_t::fw_error_success as fw_error_t
+    }
+
+    #[test]
+    fn test_read_string_grows() -> Result<()> {
+        for len in [0, 1, INITIAL_STRING_SIZE - 1, INITIAL_STRING_SIZE, 100_000] {
+            let src = "x".repeat(len);
+            let mut calls = 0;
+            let string = read_string_on_success(|buffer, size| {
+                calls += 1;
+                fixed_string_copy(&src, buffer, size)
+            })?;
+            assert_eq!(string, src);
+            assert_eq!(calls > 1, len >= INITIAL_STRING_SIZE - 1, "length {len}");
+        }
+
+        let src = "x".repeat(MAX_STRING_SIZE);
+        assert_eq!(
+            read_string_on_success(|buffer, size| fixed_string_copy(&src, buffer, size)),
+            Err(FreeWiliError::MemoryError)
+        );
+
+        // Failed calls leave the size untouched and must not be retried
+        let mut calls = 0;
+        let res = read_string_on_success(|_, _| {
+            calls += 1;
+            ffi::_fw_error_t::fw_error_invalid_device as fw_error_t
+        });
+        assert_eq!(res, Err(FreeWiliError::InvalidDevice));
+        assert_eq!(calls, 1);
+
+        Ok(())
+    }
+}
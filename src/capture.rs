@@ -0,0 +1,302 @@
+//! Record enumeration results to versioned JSON and replay them later.
+//!
+//! A [`Capture`] holds every device found by one enumeration, including all of its USB
+//! devices and the resolved main, display, FPGA and hub interfaces. [`ReplaySource`] serves
+//! a capture back as [`FreeWiliDevice`]s, and setting `FREEWILI_REPLAY` to a capture file
+//! makes [`FreeWiliDevice::find_all`] do the same, so a capture sent in with a bug report can
+//! be run against the rest of the crate without the hardware:
+//!
+//! ```no_run
+//! use freewili_finder_rs::DeviceSource;
+//! use freewili_finder_rs::capture::{Capture, ReplaySource};
+//!
+//! Capture::record()?.save("capture.json")?;
+//!
+//! let source = ReplaySource::load("capture.json")?;
+//! for device in source.find_all()? {
+//!     println!("{device}");
+//! }
+//! # Ok::<(), freewili_finder_rs::capture::CaptureError>(())
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::{DeviceSnapshot, DeviceSource, FreeWiliDevice, FreeWiliError, Result, USBDevice};
+
+/// Version of the capture format written by [`Capture::to_json`].
+///
+/// Bumped whenever a change would make older readers misinterpret a capture. Captures
+/// with a newer version are rejected instead of being read partially.
+pub const CAPTURE_VERSION: u32 = 1;
+
+/// Environment variable naming a capture file that [`FreeWiliDevice::find_all`] replays
+/// instead of enumerating.
+pub const REPLAY_ENV: &str = "FREEWILI_REPLAY";
+
+/// Errors returned while recording, reading or writing a [`Capture`].
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    /// Enumerating the devices to record failed
+    #[error("Failed to find devices: {0}")]
+    Device(#[from] FreeWiliError),
+    /// Reading or writing the capture file failed
+    #[error("Failed to access capture file: {0}")]
+    Io(#[from] io::Error),
+    /// The capture isn't valid JSON or is missing fields
+    #[error("Invalid capture: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The capture was written by a newer version of this crate
+    #[error("Unsupported capture version {found}, at most {CAPTURE_VERSION} is supported")]
+    UnsupportedVersion {
+        /// Version found in the capture
+        found: u32,
+    },
+}
+
+/// The result of one enumeration together with where it was recorded.
+///
+/// Serializes as a JSON object with the field names below. `version` is always written
+/// first, see [`CAPTURE_VERSION`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Capture {
+    /// Capture format version
+    pub version: u32,
+    /// Version of this crate that recorded the capture
+    pub crate_version: String,
+    /// Backend that found the devices: `ffi`, `sysfs` or `replay`
+    pub backend: String,
+    /// Operating system the capture was recorded on, as in [`std::env::consts::OS`]
+    pub os: String,
+    /// Every device found, in enumeration order
+    pub devices: Vec<DeviceSnapshot>,
+}
+
+/// Just enough of a capture to check its version before reading the rest.
+#[derive(serde::Deserialize)]
+struct CaptureHeader {
+    version: u32,
+}
+
+impl Capture {
+    /// Records the devices connected to this machine.
+    pub fn record() -> std::result::Result<Capture, CaptureError> {
+        Ok(Self::from_devices(&FreeWiliDevice::find_all()?)?)
+    }
+
+    /// Records the devices found by `source`, read with [`DeviceSource::snapshot`].
+    pub fn record_from(source: &impl DeviceSource) -> std::result::Result<Capture, CaptureError> {
+        let devices = source
+            .find_all()?
+            .iter()
+            .map(|device| source.snapshot(device))
+            .collect::<Result<_>>()?;
+        Ok(Self::from_snapshots(devices))
+    }
+
+    /// Creates a capture of already found devices.
+    pub fn from_devices(devices: &[FreeWiliDevice]) -> Result<Capture> {
+        Ok(Self::from_snapshots(
+            devices
+                .iter()
+                .map(FreeWiliDevice::snapshot)
+                .collect::<Result<_>>()?,
+        ))
+    }
+
+    /// Creates a capture of device snapshots.
+    pub fn from_snapshots(devices: Vec<DeviceSnapshot>) -> Capture {
+        Capture {
+            version: CAPTURE_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            backend: backend_name().to_string(),
+            os: std::env::consts::OS.to_string(),
+            devices,
+        }
+    }
+
+    /// Serializes the capture as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serializing to a string")
+    }
+
+    /// Reads a capture from JSON, rejecting versions newer than [`CAPTURE_VERSION`].
+    pub fn from_json(json: &str) -> std::result::Result<Capture, CaptureError> {
+        let header: CaptureHeader = serde_json::from_str(json)?;
+        if header.version > CAPTURE_VERSION {
+            return Err(CaptureError::UnsupportedVersion {
+                found: header.version,
+            });
+        }
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Writes the capture to a file as JSON.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::result::Result<(), CaptureError> {
+        let mut json = self.to_json();
+        json.push('\n');
+        Ok(fs::write(path, json)?)
+    }
+
+    /// Reads a capture written by [`save`](Self::save).
+    pub fn load(path: impl AsRef<Path>) -> std::result::Result<Capture, CaptureError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+/// Name of the backend [`FreeWiliDevice::find_all`] currently uses.
+fn backend_name() -> &'static str {
+    if std::env::var_os(REPLAY_ENV).is_some_and(|path| !path.is_empty()) {
+        return "replay";
+    }
+    #[cfg(all(feature = "ffi", feature = "native-linux", target_os = "linux"))]
+    if std::env::var_os(crate::sysfs::ROOT_ENV).is_some_and(|root| !root.is_empty()) {
+        return "sysfs";
+    }
+    if cfg!(feature = "ffi") {
+        "ffi"
+    } else {
+        "sysfs"
+    }
+}
+
+/// Serves the devices of a [`Capture`].
+///
+/// Every [`find_all`](DeviceSource::find_all) returns fresh handles to the same recorded
+/// devices. Like devices found by the `native-linux` backend, they hold plain data and
+/// `usb_device_get_string` isn't available.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplaySource {
+    capture: Capture,
+}
+
+impl ReplaySource {
+    /// Creates a source serving the devices of `capture`.
+    pub fn new(capture: Capture) -> Self {
+        ReplaySource { capture }
+    }
+
+    /// Creates a source serving the devices of a capture file.
+    pub fn load(path: impl AsRef<Path>) -> std::result::Result<Self, CaptureError> {
+        Ok(Self::new(Capture::load(path)?))
+    }
+
+    /// The capture being replayed.
+    pub fn capture(&self) -> &Capture {
+        &self.capture
+    }
+}
+
+impl DeviceSource for ReplaySource {
+    fn find_all(&self) -> Result<Vec<FreeWiliDevice>> {
+        Ok(self
+            .capture
+            .devices
+            .iter()
+            .cloned()
+            .map(FreeWiliDevice::from_snapshot)
+            .collect())
+    }
+
+    fn get_usb_devices(&self, device: &FreeWiliDevice) -> Result<Vec<USBDevice>> {
+        device.get_usb_devices()
+    }
+}
+
+/// Replays the capture named by `FREEWILI_REPLAY`, if it is set.
+pub(crate) fn find_all_from_env() -> Option<Result<Vec<FreeWiliDevice>>> {
+    let path = std::env::var_os(REPLAY_ENV).filter(|path| !path.is_empty())?;
+    Some(
+        ReplaySource::load(&path)
+            .map_err(|e| {
+                FreeWiliError::InternalError(Some(format!(
+                    "Failed to replay {}: {e}",
+                    Path::new(&path).display()
+                )))
+            })
+            .and_then(|source| source.find_all()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeFreeWili, FakeSource};
+    use crate::{DeviceSelector, DeviceType, UsbDeviceType};
+
+    fn fake_source() -> FakeSource {
+        FakeSource::new()
+            .with_device(
+                FakeFreeWili::new("FW4037")
+                    .with_main("/dev/ttyACM0")
+                    .with_display("/dev/ttyACM1")
+                    .with_fpga("/dev/ttyUSB0"),
+            )
+            .with_device(FakeFreeWili::standalone(DeviceType::Winky, "WK0042").with_main("COM7"))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let capture = Capture::record_from(&fake_source()).unwrap();
+        assert_eq!(capture.version, CAPTURE_VERSION);
+        assert_eq!(capture.devices.len(), 2);
+
+        let json = capture.to_json();
+        assert!(json.trim_start().starts_with("{\n  \"version\": 1,"));
+        assert_eq!(Capture::from_json(&json).unwrap(), capture);
+
+        let path =
+            std::env::temp_dir().join(format!("freewili-capture-{}.json", std::process::id()));
+        capture.save(&path).unwrap();
+        let loaded = Capture::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), capture);
+    }
+
+    #[test]
+    fn test_replay() {
+        let capture = Capture::record_from(&fake_source()).unwrap();
+        let source = ReplaySource::new(capture.clone());
+
+        let devices = source.find_all().unwrap();
+        let snapshots: Vec<_> = devices.iter().map(|d| d.snapshot().unwrap()).collect();
+        assert_eq!(snapshots, capture.devices);
+
+        let main = devices[0].get_main_usb_device().unwrap();
+        assert_eq!(main.kind, UsbDeviceType::SerialMain);
+        assert_eq!(main.port.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(
+            source.get_usb_devices(&devices[1]).unwrap(),
+            capture.devices[1].usb_devices
+        );
+
+        let winky = source
+            .find(&DeviceSelector::DeviceType(DeviceType::Winky))
+            .unwrap();
+        assert_eq!(winky.len(), 1);
+        assert_eq!(winky[0].serial().unwrap(), "WK0042");
+    }
+
+    #[test]
+    fn test_from_json_errors() {
+        let newer = format!(
+            "{{\"version\": {}, \"devices\": \"from the future\"}}",
+            CAPTURE_VERSION + 1
+        );
+        assert!(matches!(
+            Capture::from_json(&newer),
+            Err(CaptureError::UnsupportedVersion { found }) if found == CAPTURE_VERSION + 1
+        ));
+        assert!(matches!(
+            Capture::from_json("{\"version\": 1}"),
+            Err(CaptureError::Json(_))
+        ));
+        assert!(matches!(
+            Capture::from_json("not json"),
+            Err(CaptureError::Json(_))
+        ));
+    }
+}
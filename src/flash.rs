@@ -0,0 +1,681 @@
+//! Flashing UF2 firmware onto a FreeWili CPU.
+//!
+//! [`FreeWiliDevice::flash_uf2`] does everything a technician would do by hand: it
+//! reboots the CPU into its bootloader, copies the image onto the bootloader's volume and
+//! waits for the device to come back running the new firmware:
+//!
+//! ```no_run
+//! use freewili_finder_rs::FreeWiliDevice;
+//! use freewili_finder_rs::bootloader::Cpu;
+//! use freewili_finder_rs::flash::FlashProgress;
+//!
+//! for device in FreeWiliDevice::find_all()? {
+//!     device.flash_uf2(Cpu::Main, "FreeWili.uf2", |progress| match progress {
+//!         FlashProgress::Phase(phase) => println!("{phase}"),
+//!         FlashProgress::Copy { written, total } => println!("{written}/{total} bytes"),
+//!     })?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::bootloader::{self, BootloaderError, Cpu};
+use crate::uf2::{self, Family, Uf2Error, Uf2Image};
+use crate::{
+    DeviceSource, DeviceType, FreeWiliDevice, FreeWiliError, SystemDevices, USBDevice,
+    UsbDeviceType, usbdef,
+};
+
+/// Bytes copied between progress reports, a whole number of UF2 blocks.
+const COPY_CHUNK_SIZE: usize = 64 * uf2::BLOCK_SIZE;
+/// File name used on the volume if the image path has none.
+const DEFAULT_FILE_NAME: &str = "firmware.uf2";
+
+/// Step of [`FreeWiliDevice::flash_uf2`], in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FlashPhase {
+    /// Rebooting the CPU into its bootloader
+    Reboot,
+    /// Copying the image onto the bootloader's volume
+    Copy,
+    /// Waiting for the CPU to restart with the new firmware
+    Wait,
+    /// Checking that the restarted device is the one that was flashed
+    Verify,
+}
+
+impl fmt::Display for FlashPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlashPhase::Reboot => write!(f, "Rebooting into the bootloader"),
+            FlashPhase::Copy => write!(f, "Copying the image"),
+            FlashPhase::Wait => write!(f, "Waiting for the device to restart"),
+            FlashPhase::Verify => write!(f, "Verifying the device"),
+        }
+    }
+}
+
+/// Progress reported by [`FreeWiliDevice::flash_uf2`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashProgress {
+    /// A phase started
+    Phase(FlashPhase),
+    /// Part of the image was copied, reported after every chunk
+    Copy {
+        /// Bytes copied so far
+        written: u64,
+        /// Size of the image
+        total: u64,
+    },
+}
+
+/// Errors returned when flashing a UF2 image.
+#[derive(Error, Debug)]
+pub enum FlashError {
+    /// The image is corrupt, found before the CPU was touched
+    #[error("Invalid UF2 image: {0}")]
+    Image(#[from] Uf2Error),
+    /// The image isn't for the chip the bootloader reports, and the CPU stays in it
+    #[error("UF2 image doesn't fit the bootloader: {source}")]
+    WrongImage {
+        /// Device the CPU's bootloader enumerated as, to flash another image onto or reset
+        bootloader: FreeWiliDevice,
+        /// Why the image doesn't fit
+        #[source]
+        source: Uf2Error,
+    },
+    /// The CPU couldn't be rebooted into its bootloader
+    #[error(transparent)]
+    Bootloader(#[from] BootloaderError),
+    /// The bootloader's volume isn't mounted
+    #[error("UF2 bootloader at port chain {port_chain:?} has no mounted volume")]
+    NoVolume {
+        /// Port chain of the bootloader
+        port_chain: Vec<u32>,
+    },
+    /// Copying the image onto the volume failed
+    #[error("Failed to copy UF2 image to {}: {source}", .path.display())]
+    Copy {
+        /// Path of the copy on the volume
+        path: PathBuf,
+        /// Error that occurred
+        #[source]
+        source: std::io::Error,
+    },
+    /// Syncing the copied image to the volume failed, so it may not have been written
+    #[error("Failed to sync UF2 image to {}: {source}", .path.display())]
+    Io {
+        /// Path of the copy on the volume
+        path: PathBuf,
+        /// Error that occurred
+        #[source]
+        source: std::io::Error,
+    },
+    /// Looking for the restarted device failed
+    #[error("Failed to find the restarted device: {0}")]
+    Device(#[from] FreeWiliError),
+    /// The CPU didn't restart with the new firmware in time
+    #[error("Device at port chain {port_chain:?} didn't restart within {timeout:?}")]
+    Timeout {
+        /// Port chain of the CPU
+        port_chain: Vec<u32>,
+        /// How long was waited
+        timeout: Duration,
+    },
+    /// A device restarted on the CPU's port, but with a different serial number
+    #[error("Restarted device has serial number {found}, expected {expected}")]
+    SerialMismatch {
+        /// Serial number before flashing
+        expected: String,
+        /// Serial number after flashing
+        found: String,
+    },
+}
+
+impl FreeWiliDevice {
+    /// Flashes the UF2 image at `image_path` onto `cpu` and returns the restarted device.
+    ///
+    /// The image is checked before anything else happens, and again against the chip the
+    /// bootloader reports before it is copied. By then the CPU is in its bootloader and
+    /// stays there, so that error is a [`FlashError::WrongImage`] carrying the bootloader's
+    /// device. After copying, this waits for the CPU's
+    /// serial port to return on the same USB port and checks that the device still has the
+    /// same serial number. A standalone device that is already in its bootloader only has
+    /// the bootloader's serial number, so for it this only checks that it restarted. Each
+    /// phase and the copy progress are reported to `progress`.
+    /// Each wait takes up to [`bootloader::DEFAULT_TIMEOUT`].
+    pub fn flash_uf2(
+        &self,
+        cpu: Cpu,
+        image_path: impl AsRef<Path>,
+        progress: impl FnMut(FlashProgress),
+    ) -> Result<FreeWiliDevice, FlashError> {
+        self.flash_uf2_with(
+            cpu,
+            image_path,
+            &SystemDevices,
+            bootloader::DEFAULT_TIMEOUT,
+            progress,
+        )
+    }
+
+    /// Like [`flash_uf2`](Self::flash_uf2), but reads the devices from and looks for the
+    /// bootloader and the restarted device in `source`, and waits up to `timeout` for each.
+    pub fn flash_uf2_with(
+        &self,
+        cpu: Cpu,
+        image_path: impl AsRef<Path>,
+        source: &impl DeviceSource,
+        timeout: Duration,
+        mut progress: impl FnMut(FlashProgress),
+    ) -> Result<FreeWiliDevice, FlashError> {
+        let image_path = image_path.as_ref();
+        let bytes = std::fs::read(image_path).map_err(Uf2Error::Io)?;
+        let image = Uf2Image::parse(&bytes)?;
+        // A standalone bootloader reports its own serial number, not the firmware's
+        let device = source.snapshot(self)?;
+        let expected_serial = match device.device_type {
+            DeviceType::Uf2 => None,
+            _ => Some(device.serial),
+        };
+
+        progress(FlashProgress::Phase(FlashPhase::Reboot));
+        let bootloader_device = self.enter_bootloader_with(cpu, source, timeout)?;
+        let bootloader = source
+            .snapshot(&bootloader_device)
+            .map_err(BootloaderError::from)?;
+        let volume = cpu.usb_device(&bootloader).map_err(BootloaderError::from)?;
+        validate_for_bootloader(&image, &volume).map_err(|source| FlashError::WrongImage {
+            bootloader: bootloader_device,
+            source,
+        })?;
+        let volume_path = volume.path.as_ref().ok_or_else(|| FlashError::NoVolume {
+            port_chain: volume.port_chain.clone(),
+        })?;
+
+        progress(FlashProgress::Phase(FlashPhase::Copy));
+        let file_name = image_path.file_name().unwrap_or(DEFAULT_FILE_NAME.as_ref());
+        let destination = Path::new(volume_path).join(file_name);
+        let mut file = File::create(&destination).map_err(|source| FlashError::Copy {
+            path: destination.clone(),
+            source,
+        })?;
+        copy(&bytes, &mut file, &destination, &mut progress)?;
+
+        progress(FlashProgress::Phase(FlashPhase::Wait));
+        let is_running = |usb_device: &USBDevice| {
+            usb_device.kind != UsbDeviceType::MassStorage && usb_device.port.is_some()
+        };
+        let restarted = bootloader::wait_for_port(source, &volume.port_chain, timeout, is_running)?
+            .ok_or_else(|| FlashError::Timeout {
+                port_chain: volume.port_chain.clone(),
+                timeout,
+            })?;
+
+        progress(FlashProgress::Phase(FlashPhase::Verify));
+        if let Some(expected) = expected_serial {
+            let found = restarted.serial()?;
+            if found != expected {
+                return Err(FlashError::SerialMismatch { expected, found });
+            }
+        }
+        Ok(restarted)
+    }
+}
+
+/// Chips a bootloader can flash, from its USB product ID, or `None` if unknown.
+fn bootloader_families(volume: &USBDevice) -> Option<&'static [Family]> {
+    match (volume.vid, volume.pid) {
+        (usbdef::VID_RPI, usbdef::PID_RPI_2040_UF2) => Some(&[Family::Rp2040]),
+        (usbdef::VID_RPI, usbdef::PID_RPI_2350_UF2) => Some(&[
+            Family::Rp2350ArmSecure,
+            Family::Rp2350RiscV,
+            Family::Rp2350ArmNonSecure,
+        ]),
+        _ => None,
+    }
+}
+
+/// Checks that the bootloader of `volume` accepts `image`, going by the board ID in its
+/// `INFO_UF2.TXT` or else by its product ID.
+fn validate_for_bootloader(image: &Uf2Image, volume: &USBDevice) -> Result<(), Uf2Error> {
+    let families = volume
+        .bootloader_info()
+        .ok()
+        .and_then(|info| info.families())
+        .or_else(|| bootloader_families(volume));
+    let Some(families) = families else {
+        return Ok(());
+    };
+    let family = families
+        .iter()
+        .find(|family| image.is_for(**family))
+        .unwrap_or(&families[0]);
+    image.validate_for(*family)
+}
+
+/// File the image is copied into.
+trait SyncWrite: Write {
+    /// Flushes the written data to the device, like [`File::sync_all`].
+    fn sync_all(&self) -> std::io::Result<()>;
+}
+
+impl SyncWrite for File {
+    fn sync_all(&self) -> std::io::Result<()> {
+        File::sync_all(self)
+    }
+}
+
+/// Writes `bytes` to `file` at `destination` in chunks and syncs it.
+fn copy(
+    bytes: &[u8],
+    file: &mut impl SyncWrite,
+    destination: &Path,
+    progress: &mut impl FnMut(FlashProgress),
+) -> Result<(), FlashError> {
+    let total = bytes.len() as u64;
+    let mut written = 0;
+    for chunk in bytes.chunks(COPY_CHUNK_SIZE) {
+        file.write_all(chunk).map_err(|source| FlashError::Copy {
+            path: destination.to_path_buf(),
+            source,
+        })?;
+        written += chunk.len() as u64;
+        progress(FlashProgress::Copy { written, total });
+    }
+    // A failed sync is the only sign that the FAT volume didn't take the image
+    file.sync_all().map_err(|source| FlashError::Io {
+        path: destination.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeFreeWili, FakeSource};
+    use crate::uf2::Block;
+
+    /// Encodes an image of `count` pages for `family` starting at the beginning of flash.
+    fn image(family: Family, count: u32) -> Vec<u8> {
+        (0..count)
+            .flat_map(|i| {
+                Block {
+                    flags: uf2::FLAG_FAMILY_ID_PRESENT,
+                    target_address: 0x1000_0000 + i * 256,
+                    block_number: i,
+                    block_count: count,
+                    family_id: family.id(),
+                    data: vec![i as u8; 256],
+                }
+                .to_bytes()
+            })
+            .collect()
+    }
+
+    /// Empty directory standing in for a bootloader volume.
+    fn volume(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("freewili-flash-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Waits for `path` to have `len` bytes, like a bootloader waiting for the last block.
+    fn wait_for_file(path: &Path, len: usize) -> bool {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while std::time::Instant::now() < deadline {
+            if std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == len as u64) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_invalid_image() {
+        let dir = volume("invalid");
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::new("FW4037")
+                .with_main("/nonexistent/ttyACM0")
+                .with_port_chain([3, 4]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+        let timeout = Duration::from_millis(50);
+        let mut phases = Vec::new();
+
+        let corrupt = dir.join("corrupt.uf2");
+        std::fs::write(&corrupt, &image(Family::Rp2040, 2)[..700]).unwrap();
+        let result =
+            device.flash_uf2_with(Cpu::Main, &corrupt, &source, timeout, |p| phases.push(p));
+        assert!(matches!(
+            result,
+            Err(FlashError::Image(Uf2Error::Length(700)))
+        ));
+        let result =
+            device.flash_uf2_with(Cpu::Main, dir.join("missing.uf2"), &source, timeout, |p| {
+                phases.push(p)
+            });
+        assert!(matches!(result, Err(FlashError::Image(Uf2Error::Io(_)))));
+
+        // So is the device, through the source
+        let rp2350 = dir.join("rp2350.uf2");
+        std::fs::write(&rp2350, image(Family::Rp2350ArmSecure, 2)).unwrap();
+        source.set_error("FW4037", Some(FreeWiliError::MemoryError));
+        let result =
+            device.flash_uf2_with(Cpu::Main, &rp2350, &source, timeout, |p| phases.push(p));
+        assert!(matches!(
+            result,
+            Err(FlashError::Device(FreeWiliError::MemoryError))
+        ));
+        // The image and device are checked before the CPU is touched
+        assert!(phases.is_empty());
+
+        // The RP2040 bootloader doesn't take RP2350 images
+        let bootloader = FakeFreeWili::new("FW4037")
+            .with_main_uf2(dir.to_string_lossy())
+            .with_port_chain([3, 4]);
+        let source = FakeSource::new().with_device(bootloader);
+        let device = source.find_all().unwrap().remove(0);
+        let result =
+            device.flash_uf2_with(Cpu::Main, &rp2350, &source, timeout, |p| phases.push(p));
+        let entries = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let Err(FlashError::WrongImage { bootloader, source }) = result else {
+            panic!("expected WrongImage, got {result:?}");
+        };
+        assert!(matches!(
+            source,
+            Uf2Error::WrongFamily {
+                expected: Family::Rp2040,
+                ..
+            }
+        ));
+        // The CPU stays in the bootloader that is returned
+        assert_eq!(
+            bootloader.get_main_usb_device().unwrap().kind,
+            UsbDeviceType::MassStorage
+        );
+        assert_eq!(phases, [FlashProgress::Phase(FlashPhase::Reboot)]);
+        assert_eq!(entries, 2, "image was copied");
+    }
+
+    #[test]
+    fn test_sync_error() {
+        /// Volume that takes every write but fails to sync.
+        struct FailingSync(Vec<u8>);
+
+        impl Write for FailingSync {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl SyncWrite for FailingSync {
+            fn sync_all(&self) -> std::io::Result<()> {
+                Err(std::io::Error::other("I/O error"))
+            }
+        }
+
+        let bytes = image(Family::Rp2040, 2);
+        let mut file = FailingSync(Vec::new());
+        let mut progress = Vec::new();
+        let result = copy(
+            &bytes,
+            &mut file,
+            Path::new("/media/RPI-RP2/fw.uf2"),
+            &mut |p| progress.push(p),
+        );
+        assert!(matches!(
+            result,
+            Err(FlashError::Io { ref path, .. }) if path == Path::new("/media/RPI-RP2/fw.uf2")
+        ));
+        assert_eq!(file.0, bytes);
+        assert_eq!(
+            progress.last(),
+            Some(&FlashProgress::Copy {
+                written: bytes.len() as u64,
+                total: bytes.len() as u64
+            })
+        );
+    }
+
+    #[test]
+    fn test_board_id() {
+        // INFO_UF2.TXT wins over the product ID
+        let dir = volume("board-id");
+        std::fs::write(
+            dir.join(uf2::INFO_FILE),
+            "UF2 Bootloader v3.0\nModel: Raspberry Pi RP2\nBoard-ID: RPI-RP2\n",
+        )
+        .unwrap();
+        let image_path = dir.join("rp2350.uf2");
+        std::fs::write(&image_path, image(Family::Rp2350ArmSecure, 2)).unwrap();
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::standalone(DeviceType::Uf2, "E0C9125B0D9B")
+                .with_main_uf2(dir.to_string_lossy())
+                .with_port_chain([1, 2]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+        let result = device.flash_uf2_with(Cpu::Main, &image_path, &source, Duration::ZERO, |_| {});
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            result,
+            Err(FlashError::WrongImage {
+                source: Uf2Error::WrongFamily {
+                    expected: Family::Rp2040,
+                    ..
+                },
+                ..
+            })
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_flash() {
+        let (_master, slave, slave_path) = crate::serial::open_pty();
+        let dir = volume("display");
+        let image_path =
+            std::env::temp_dir().join(format!("freewili-flash-display-{}.uf2", std::process::id()));
+        let bytes = image(Family::Rp2040, 100);
+        std::fs::write(&image_path, &bytes).unwrap();
+        let copied = dir.join(image_path.file_name().unwrap());
+
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::new("FW4037")
+                .with_main("/dev/ttyACM0")
+                .with_display(slave_path.to_string_lossy())
+                .with_port_chain([3, 4]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+
+        // Stand-in for the display CPU: enter the bootloader on the 1200 baud touch and
+        // restart once the whole image arrived
+        let cpu = std::thread::spawn({
+            let source = source.clone();
+            let dir = dir.clone();
+            let copied = copied.clone();
+            let len = bytes.len();
+            move || {
+                if !crate::serial::wait_for_baud_rate(&slave, 1200, Duration::from_secs(5)) {
+                    return false;
+                }
+                source.detach("FW4037");
+                source.attach(
+                    FakeFreeWili::new("FW4037")
+                        .with_main("/dev/ttyACM0")
+                        .with_display_uf2(dir.to_string_lossy())
+                        .with_port_chain([3, 4]),
+                );
+                if !wait_for_file(&copied, len) {
+                    return false;
+                }
+                source.detach("FW4037");
+                source.attach(
+                    FakeFreeWili::new("FW4037")
+                        .with_main("/dev/ttyACM0")
+                        .with_display("/dev/ttyACM1")
+                        .with_port_chain([3, 4]),
+                );
+                true
+            }
+        });
+
+        let mut progress = Vec::new();
+        let result = device.flash_uf2_with(
+            Cpu::Display,
+            &image_path,
+            &source,
+            Duration::from_secs(5),
+            |p| progress.push(p),
+        );
+        let cpu_done = cpu.join().unwrap();
+        let copied_bytes = std::fs::read(&copied);
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&image_path).unwrap();
+
+        let restarted = result.unwrap();
+        assert!(cpu_done);
+        assert_eq!(copied_bytes.unwrap(), bytes);
+        let display = restarted.get_display_usb_device().unwrap();
+        assert_eq!(display.kind, UsbDeviceType::SerialDisplay);
+        assert_eq!(display.port.as_deref(), Some("/dev/ttyACM1"));
+
+        let total = bytes.len() as u64;
+        let chunk = COPY_CHUNK_SIZE as u64;
+        assert_eq!(
+            progress,
+            [
+                FlashProgress::Phase(FlashPhase::Reboot),
+                FlashProgress::Phase(FlashPhase::Copy),
+                FlashProgress::Copy {
+                    written: chunk,
+                    total
+                },
+                FlashProgress::Copy {
+                    written: total,
+                    total
+                },
+                FlashProgress::Phase(FlashPhase::Wait),
+                FlashProgress::Phase(FlashPhase::Verify),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_bootloader() {
+        let dir = volume("standalone");
+        let image_path = dir.join("winky.uf2");
+        std::fs::create_dir_all(dir.join("volume")).unwrap();
+        std::fs::write(&image_path, image(Family::Rp2350RiscV, 2)).unwrap();
+        let copied = dir.join("volume/winky.uf2");
+
+        // The bootloader's serial number isn't the firmware's, only the restart is checked
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::standalone(DeviceType::Uf2, "E0C9125B0D9B")
+                .with_main_uf2(dir.join("volume").to_string_lossy())
+                .with_port_chain([1, 2]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+        let cpu = std::thread::spawn({
+            let source = source.clone();
+            move || {
+                assert!(wait_for_file(&copied, 2 * uf2::BLOCK_SIZE));
+                source.detach("E0C9125B0D9B");
+                source.attach(
+                    FakeFreeWili::standalone(DeviceType::Winky, "WK0042")
+                        .with_main("/dev/ttyACM0")
+                        .with_port_chain([1, 2]),
+                );
+            }
+        });
+
+        let result = device.flash_uf2_with(
+            Cpu::Main,
+            &image_path,
+            &source,
+            Duration::from_secs(5),
+            |_| {},
+        );
+        cpu.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let restarted = result.unwrap();
+        assert_eq!(restarted.device_type().unwrap(), DeviceType::Winky);
+        assert_eq!(restarted.serial().unwrap(), "WK0042");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_serial_mismatch() {
+        let dir = volume("mismatch");
+        let image_path = dir.join("winky.uf2");
+        std::fs::create_dir_all(dir.join("volume")).unwrap();
+        std::fs::write(&image_path, image(Family::Rp2350RiscV, 2)).unwrap();
+        let copied = dir.join("volume/winky.uf2");
+
+        let (_master, slave, slave_path) = crate::serial::open_pty();
+        let source = FakeSource::new().with_device(
+            FakeFreeWili::standalone(DeviceType::Winky, "WK0042")
+                .with_main(slave_path.to_string_lossy())
+                .with_port_chain([1, 2]),
+        );
+        let device = source.find_all().unwrap().remove(0);
+
+        // A different Winky shows up on the port after flashing
+        let cpu = std::thread::spawn({
+            let source = source.clone();
+            let volume = dir.join("volume");
+            move || {
+                if !crate::serial::wait_for_baud_rate(&slave, 1200, Duration::from_secs(5)) {
+                    return false;
+                }
+                source.detach("WK0042");
+                source.attach(
+                    FakeFreeWili::standalone(DeviceType::Uf2, "E0C9125B0D9B")
+                        .with_main_uf2(volume.to_string_lossy())
+                        .with_port_chain([1, 2]),
+                );
+                if !wait_for_file(&copied, 2 * uf2::BLOCK_SIZE) {
+                    return false;
+                }
+                source.detach("E0C9125B0D9B");
+                source.attach(
+                    FakeFreeWili::standalone(DeviceType::Winky, "WK0043")
+                        .with_main("/dev/ttyACM0")
+                        .with_port_chain([1, 2]),
+                );
+                true
+            }
+        });
+
+        let result = device.flash_uf2_with(
+            Cpu::Main,
+            &image_path,
+            &source,
+            Duration::from_secs(5),
+            |_| {},
+        );
+        let cpu_done = cpu.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(cpu_done);
+        assert!(matches!(
+            result,
+            Err(FlashError::SerialMismatch { ref expected, ref found })
+                if expected == "WK0042" && found == "WK0043"
+        ));
+    }
+}
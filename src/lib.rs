@@ -3,22 +3,63 @@
 //! This library provides safe Rust bindings for the FreeWili Finder C/C++ library,
 //! making it easy to discover and interface with FreeWili devices from Rust applications.
 //!
+#[cfg(feature = "serialport")]
+pub mod bootloader;
+#[cfg(feature = "ffi")]
+mod capi;
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(feature = "ffi")]
 mod ffi;
+#[cfg(feature = "serialport")]
+pub mod flash;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(feature = "tokio")]
+pub mod nonblocking;
+mod port_path;
+mod selector;
+#[cfg(feature = "serialport")]
+pub mod serial;
+mod serial_links;
+mod snapshot;
+mod source;
+#[cfg(target_os = "linux")]
+pub mod storage;
+#[cfg(all(feature = "native-linux", target_os = "linux"))]
+pub mod sysfs;
+pub mod testing;
+mod topology;
+#[cfg(target_os = "linux")]
+mod uevent;
+pub mod uf2;
+mod usbdef;
+mod watcher;
+
+pub use port_path::{ParsePortPathError, PortPath};
+pub use selector::{DeviceSelector, Interface, ParseSelectorError};
+pub use snapshot::DeviceSnapshot;
+pub use source::{DeviceSource, SystemDevices};
+pub use topology::{NodeId, NodeKind, TopologyNode, UsbTopology};
+pub use watcher::{DeviceEvent, FreeWiliWatcher};
 
-use ffi::fw_error_t;
-use ffi::fw_freewili_device_t;
-use std::ffi::{CStr, c_char};
 use std::fmt;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex, PoisonError};
 use thiserror::Error;
 
-use ffi::_fw_devicetype_t::*;
-use ffi::_fw_inttype_t::*;
-use ffi::_fw_stringtype_t::*;
-
-use crate::ffi::fw_stringtype_t;
-
-#[derive(Error, Debug)]
+#[cfg(not(any(feature = "ffi", feature = "native-linux")))]
+compile_error!("Enable the `ffi` or `native-linux` feature to select a device backend");
+
+/// Errors returned by this crate.
+///
+/// With the `serde` feature this serializes as `{"kind": "<snake_case variant>"}`, plus a
+/// `"message"` field for [`InternalError`](FreeWiliError::InternalError).
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "kind", content = "message", rename_all = "snake_case")
+)]
 pub enum FreeWiliError {
     /// Invalid parameter passed to a function
     #[error("Invalid Parameter")]
@@ -42,48 +83,20 @@ pub enum FreeWiliError {
 
 pub type Result<T> = std::result::Result<T, FreeWiliError>;
 
-impl From<ffi::_fw_error_t> for FreeWiliError {
-    fn from(error: ffi::_fw_error_t) -> Self {
-        match error {
-            ffi::_fw_error_t::fw_error_success => FreeWiliError::None,
-            ffi::_fw_error_t::fw_error_invalid_parameter => FreeWiliError::InvalidParameter,
-            ffi::_fw_error_t::fw_error_invalid_device => FreeWiliError::InvalidDevice,
-            ffi::_fw_error_t::fw_error_internal_error => FreeWiliError::InternalError(None),
-            ffi::_fw_error_t::fw_error_memory => FreeWiliError::MemoryError,
-            ffi::_fw_error_t::fw_error_no_more_devices => FreeWiliError::NoMoreDevices,
-            ffi::_fw_error_t::fw_error_none => FreeWiliError::None,
-            ffi::_fw_error_t::fw_error__maxvalue => FreeWiliError::InternalError(None),
-        }
-    }
-}
-
-impl From<fw_error_t> for FreeWiliError {
-    fn from(error_code: fw_error_t) -> Self {
-        match error_code {
-            x if x == ffi::_fw_error_t::fw_error_success as u32 => FreeWiliError::None,
-            x if x == ffi::_fw_error_t::fw_error_invalid_parameter as u32 => {
-                FreeWiliError::InvalidParameter
-            }
-            x if x == ffi::_fw_error_t::fw_error_invalid_device as u32 => {
-                FreeWiliError::InvalidDevice
-            }
-            x if x == ffi::_fw_error_t::fw_error_internal_error as u32 => {
-                FreeWiliError::InternalError(None)
-            }
-            x if x == ffi::_fw_error_t::fw_error_memory as u32 => FreeWiliError::MemoryError,
-            x if x == ffi::_fw_error_t::fw_error_no_more_devices as u32 => {
-                FreeWiliError::NoMoreDevices
-            }
-            x if x == ffi::_fw_error_t::fw_error_none as u32 => FreeWiliError::None,
-            x if x == ffi::_fw_error_t::fw_error__maxvalue as u32 => {
-                FreeWiliError::InternalError(None)
-            }
-            _ => FreeWiliError::InternalError(Some(format!("Unknown error code: {error_code}"))),
-        }
-    }
-}
+/// Environment variable naming the directory the `native-linux` backend reads `sys`, `dev`
+/// and `proc` from.
+const SYSFS_ROOT_ENV: &str = "FREEWILI_SYSFS_ROOT";
 
+/// Kind of USB device belonging to a FreeWili.
+///
+/// With the `serde` feature variants serialize as lowercase strings matching the C API
+/// names, e.g. `"serialmain"` or `"massstorage"`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum UsbDeviceType {
     /// USB Hub
     Hub,
@@ -105,56 +118,33 @@ pub enum UsbDeviceType {
     _MaxValue,
 }
 
-impl From<ffi::_fw_usbdevicetype_t> for UsbDeviceType {
-    fn from(device_type: ffi::_fw_usbdevicetype_t) -> Self {
-        match device_type {
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_hub => UsbDeviceType::Hub,
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serial => UsbDeviceType::Serial,
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serialmain => UsbDeviceType::SerialMain,
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serialdisplay => {
-                UsbDeviceType::SerialDisplay
-            }
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_massstorage => UsbDeviceType::MassStorage,
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_esp32 => UsbDeviceType::Esp32,
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_ftdi => UsbDeviceType::Ftdi,
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype_other => UsbDeviceType::Other,
-            ffi::_fw_usbdevicetype_t::fw_usbdevicetype__maxvalue => UsbDeviceType::_MaxValue,
-        }
-    }
-}
-
-impl From<ffi::fw_usbdevicetype_t> for UsbDeviceType {
-    fn from(device_type: ffi::fw_usbdevicetype_t) -> Self {
-        match device_type {
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_hub as u32 => UsbDeviceType::Hub,
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serial as u32 => {
-                UsbDeviceType::Serial
-            }
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serialmain as u32 => {
-                UsbDeviceType::SerialMain
-            }
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_serialdisplay as u32 => {
-                UsbDeviceType::SerialDisplay
-            }
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_massstorage as u32 => {
-                UsbDeviceType::MassStorage
-            }
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_esp32 as u32 => {
-                UsbDeviceType::Esp32
-            }
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_ftdi as u32 => UsbDeviceType::Ftdi,
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype_other as u32 => {
-                UsbDeviceType::Other
-            }
-            x if x == ffi::_fw_usbdevicetype_t::fw_usbdevicetype__maxvalue as u32 => {
-                UsbDeviceType::_MaxValue
-            }
-            _ => UsbDeviceType::Other, // Default fallback
+impl UsbDeviceType {
+    /// Same name as `fw_usb_device_get_type_name` returns.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            UsbDeviceType::Hub => "Hub",
+            UsbDeviceType::Serial => "Serial",
+            UsbDeviceType::SerialMain => "Serial Main",
+            UsbDeviceType::SerialDisplay => "Serial Display",
+            UsbDeviceType::MassStorage => "Mass Storage",
+            UsbDeviceType::Esp32 => "ESP32",
+            UsbDeviceType::Ftdi => "FTDI",
+            UsbDeviceType::Other => "Other",
+            UsbDeviceType::_MaxValue => "_MaxValue",
         }
     }
 }
 
+/// Kind of FreeWili device.
+///
+/// With the `serde` feature variants serialize as lowercase strings matching the C API
+/// names, e.g. `"freewili"` or `"defcon2025fwbadge"`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum DeviceType {
     /// USB Hub (parent device)
     Unknown,
@@ -170,41 +160,29 @@ pub enum DeviceType {
     Winky,
 }
 
-impl From<ffi::_fw_devicetype_t> for DeviceType {
-    fn from(device_type: ffi::_fw_devicetype_t) -> Self {
-        match device_type {
-            ffi::_fw_devicetype_t::fw_devicetype_unknown => DeviceType::Unknown,
-            ffi::_fw_devicetype_t::fw_devicetype_freewili => DeviceType::Freewili,
-            ffi::_fw_devicetype_t::fw_devicetype_defcon2024badge => DeviceType::Defcon2024Badge,
-            ffi::_fw_devicetype_t::fw_devicetype_defcon2025fwbadge => DeviceType::Defcon2025FwBadge,
-            ffi::_fw_devicetype_t::fw_devicetype_uf2 => DeviceType::Uf2,
-            ffi::_fw_devicetype_t::fw_devicetype_winky => DeviceType::Winky,
+impl DeviceType {
+    /// Same name as `fw_device_get_type_name` returns.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            DeviceType::Unknown => "Unknown",
+            DeviceType::Freewili => "Free-WiLi",
+            DeviceType::Defcon2024Badge => "DEFCON 2024 Badge",
+            DeviceType::Defcon2025FwBadge => "DEFCON 2025 Badge",
+            DeviceType::Uf2 => "UF2",
+            DeviceType::Winky => "Winky",
         }
     }
 }
 
-impl From<ffi::fw_devicetype_t> for DeviceType {
-    fn from(device_type: ffi::fw_devicetype_t) -> Self {
-        match device_type {
-            x if x == ffi::_fw_devicetype_t::fw_devicetype_unknown as u32 => DeviceType::Unknown,
-            x if x == ffi::_fw_devicetype_t::fw_devicetype_freewili as u32 => DeviceType::Freewili,
-            x if x == ffi::_fw_devicetype_t::fw_devicetype_defcon2024badge as u32 => {
-                DeviceType::Defcon2024Badge
-            }
-            x if x == ffi::_fw_devicetype_t::fw_devicetype_defcon2025fwbadge as u32 => {
-                DeviceType::Defcon2025FwBadge
-            }
-            x if x == ffi::_fw_devicetype_t::fw_devicetype_uf2 as u32 => DeviceType::Uf2,
-            x if x == ffi::_fw_devicetype_t::fw_devicetype_winky as u32 => DeviceType::Winky,
-            _ => DeviceType::Unknown, // Default fallback
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
+/// A single USB device (hub, serial port, mass storage, ...) belonging to a FreeWili.
+///
+/// With the `serde` feature this serializes with the field names below, which are stable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct USBDevice {
     /// The type of USB device
     pub kind: UsbDeviceType,
+    /// Human-readable name of the USB device type
     pub kind_name: String,
 
     /// USB Vendor ID
@@ -217,7 +195,7 @@ pub struct USBDevice {
     pub serial: String,
     /// USB location identifier
     pub location: u32,
-    /// USB Port chain
+    /// USB Port chain from the root hub, without the bus, see [`USBDevice::port_path`]
     pub port_chain: Vec<u32>,
     /// Serial port path (for serial devices like /dev/ttyUSB0, COM1)
     pub port: Option<String>,
@@ -226,159 +204,31 @@ pub struct USBDevice {
 }
 
 impl USBDevice {
-    /// # Safety
+    /// `/dev/serial/by-id` link to the serial port, which stays the same across reboots
+    /// and re-plugs as long as the device reports the same serial number (Linux only).
     ///
-    /// The `device` pointer must be a valid pointer to a `fw_freewili_device_t` that is properly initialized
-    /// and has not been freed. The caller must ensure the device remains valid for the duration of this call.
-    pub unsafe fn from_device(device: *mut ffi::fw_freewili_device_t) -> Result<Self> {
-        let mut usb_device_type: ffi::fw_usbdevicetype_t =
-            fw_devicetype_unknown as ffi::fw_usbdevicetype_t;
-        let res = unsafe { ffi::fw_usb_device_get_type(device, &mut usb_device_type) };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
-        }
-
-        let mut usb_device_type_name = vec![0i8; 1024];
-        let mut usb_device_type_name_size: u32 = usb_device_type_name.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_get_type_name(
-                usb_device_type as ffi::fw_usbdevicetype_t,
-                usb_device_type_name.as_mut_ptr(),
-                &mut usb_device_type_name_size,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
-        }
-        let usb_device_type_name =
-            unsafe { CStr::from_ptr(usb_device_type_name.as_ptr() as *const c_char) }
-                .to_string_lossy()
-                .into_owned();
-
-        let mut vid: u32 = 0;
-        let res = unsafe {
-            ffi::fw_usb_device_get_int(device, fw_inttype_vid as u32, &mut vid as *mut u32)
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
-        }
-
-        let mut pid: u32 = 0;
-        let res = unsafe {
-            ffi::fw_usb_device_get_int(device, fw_inttype_pid as u32, &mut pid as *mut u32)
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
-        }
-
-        let mut location: u32 = 0;
-        let res = unsafe {
-            ffi::fw_usb_device_get_int(
-                device,
-                fw_inttype_location as u32,
-                &mut location as *mut u32,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
-        }
-
-        let mut name = vec![0i8; 1024];
-        let mut name_size: u32 = name.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_get_str(
-                device,
-                fw_stringtype_name as fw_stringtype_t,
-                name.as_mut_ptr(),
-                &mut name_size,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
-        }
-        let name = unsafe { CStr::from_ptr(name.as_ptr() as *const c_char) }
-            .to_string_lossy()
-            .into_owned();
-
-        let mut serial = vec![0i8; 1024];
-        let mut serial_size: u32 = serial.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_get_str(
-                device,
-                fw_stringtype_serial as fw_stringtype_t,
-                serial.as_mut_ptr(),
-                &mut serial_size,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
-        }
-        let serial = unsafe { CStr::from_ptr(serial.as_ptr() as *const c_char) }
-            .to_string_lossy()
-            .into_owned();
-
-        let mut port = vec![0i8; 1024];
-        let mut port_size: u32 = port.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_get_str(
-                device,
-                fw_stringtype_port as fw_stringtype_t,
-                port.as_mut_ptr(),
-                &mut port_size,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t
-            && res != ffi::_fw_error_t::fw_error_none as ffi::fw_error_t
-        {
-            return Err(res.into());
-        }
-        let port = unsafe { CStr::from_ptr(port.as_ptr() as *const c_char) }
-            .to_string_lossy()
-            .into_owned();
-
-        let mut path = vec![0i8; 1024];
-        let mut path_size: u32 = path.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_get_str(
-                device,
-                fw_stringtype_path as fw_stringtype_t,
-                path.as_mut_ptr(),
-                &mut path_size,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t
-            && res != ffi::_fw_error_t::fw_error_none as ffi::fw_error_t
-        {
-            return Err(res.into());
-        }
-        let path = unsafe { CStr::from_ptr(path.as_ptr() as *const c_char) }
-            .to_string_lossy()
-            .into_owned();
-
-        let mut port_chain: Vec<u32> = vec![0u32; 10];
-        let mut port_chain_size: u32 = port_chain.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_get_port_chain(device, port_chain.as_mut_ptr(), &mut port_chain_size)
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
-        }
-        port_chain.resize(port_chain_size as usize, 0);
+    /// Looked up when called, next to [`port`](Self::port).
+    pub fn port_by_id(&self) -> Option<String> {
+        serial_links::find(self, "by-id")
+    }
 
-        let usb_device = USBDevice {
-            kind: usb_device_type.into(),
-            kind_name: usb_device_type_name,
-            vid: vid as u16,
-            pid: pid as u16,
-            name,
-            serial,
-            location,
-            port_chain,
-            port: if port.is_empty() { None } else { Some(port) },
-            path: if path.is_empty() { None } else { Some(path) },
-        };
+    /// `/dev/serial/by-path` link to the serial port, which stays the same while the
+    /// device stays on the same physical USB port (Linux only).
+    ///
+    /// Looked up when called, next to [`port`](Self::port).
+    pub fn port_by_path(&self) -> Option<String> {
+        serial_links::find(self, "by-path")
+    }
 
-        Ok(usb_device)
+    /// Most stable name of the serial port: [`port_by_id`](Self::port_by_id), then
+    /// [`port_by_path`](Self::port_by_path), then [`port`](Self::port).
+    ///
+    /// Reopen this after a re-enumeration to reach the same interface again, even if the
+    /// kernel gave it a different `/dev/ttyACM*` name.
+    pub fn stable_port(&self) -> Option<String> {
+        self.port_by_id()
+            .or_else(|| self.port_by_path())
+            .or_else(|| self.port.clone())
     }
 }
 
@@ -410,287 +260,284 @@ impl fmt::Display for USBDevice {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Handle to a FreeWili device found by [`FreeWiliDevice::find_all`].
+///
+/// Cloning is cheap and clones share the same underlying handle. The device is
+/// `Send + Sync`: calls into the C library are serialized internally, so clones can be
+/// used from multiple threads without interfering with each other. Devices found by the
+/// `native-linux` backend carry everything read while enumerating and never touch the
+/// system again.
+#[derive(Debug, Clone, Default)]
 pub struct FreeWiliDevice {
+    handle: DeviceHandle,
+}
+
+#[derive(Debug, Clone, Default)]
+enum DeviceHandle {
     /// Raw handle to the C library device structure
-    handle: Rc<FreeWiliDeviceHandle>,
+    #[cfg(feature = "ffi")]
+    Ffi(Arc<capi::FreeWiliDeviceHandle>),
+    /// Device read completely while enumerating, by a backend without handles or a fake
+    Snapshot(SnapshotHandle),
+    /// Placeholder created by `Default`, every getter fails with `InvalidDevice`
+    #[default]
+    Invalid,
 }
 
-#[derive(Debug)]
-struct FreeWiliDeviceHandle {
-    ptr: *mut fw_freewili_device_t,
+/// Error every handle to a fake device returns, shared with its [`testing::FakeSource`].
+///
+/// `None` while the device works, the injected error, or
+/// [`FreeWiliError::InvalidDevice`] once it is detached.
+type FakeStatus = Arc<Mutex<Option<FreeWiliError>>>;
+
+/// Plain-data device, which a [`testing::FakeSource`] can unplug or break.
+#[derive(Debug, Clone)]
+struct SnapshotHandle {
+    device: Arc<DeviceSnapshot>,
+    /// `None` for devices that can't fail
+    status: Option<FakeStatus>,
 }
 
-impl Drop for FreeWiliDeviceHandle {
-    fn drop(&mut self) {
-        let _res: ffi::fw_error_t = unsafe { ffi::fw_device_free(&mut self.ptr, 1) };
-        if _res != ffi::_fw_error_t::fw_error_success as u32 {
-            eprintln!("Failed to free FreeWili device handle: {_res:?}");
+impl SnapshotHandle {
+    /// The device, or the error its fake source set for it.
+    fn get(&self) -> Result<&DeviceSnapshot> {
+        let error = self.status.as_ref().and_then(|status| {
+            status
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
+        });
+        match error {
+            Some(error) => Err(error),
+            None => Ok(&self.device),
         }
     }
 }
 
-impl Default for FreeWiliDevice {
-    fn default() -> Self {
+impl FreeWiliDevice {
+    #[cfg(feature = "ffi")]
+    fn from_handle(handle: Arc<capi::FreeWiliDeviceHandle>) -> Self {
+        FreeWiliDevice {
+            handle: DeviceHandle::Ffi(handle),
+        }
+    }
+
+    fn from_snapshot(snapshot: DeviceSnapshot) -> Self {
         FreeWiliDevice {
-            handle: Rc::new(FreeWiliDeviceHandle {
-                ptr: std::ptr::null_mut(),
+            handle: DeviceHandle::Snapshot(SnapshotHandle {
+                device: Arc::new(snapshot),
+                status: None,
+            }),
+        }
+    }
+
+    /// A device whose getters fail with the error in `status` while it is set.
+    fn from_fake(snapshot: DeviceSnapshot, status: FakeStatus) -> Self {
+        FreeWiliDevice {
+            handle: DeviceHandle::Snapshot(SnapshotHandle {
+                device: Arc::new(snapshot),
+                status: Some(status),
             }),
         }
     }
-}
 
-impl FreeWiliDevice {
     /// Find all connected FreeWili devices.
+    ///
+    /// Uses the C library with the `ffi` feature, and otherwise the pure-Rust sysfs
+    /// backend of the `native-linux` feature, see `sysfs::find_all`. With both enabled,
+    /// setting `FREEWILI_SYSFS_ROOT` selects the sysfs backend reading that directory.
+    /// Builds without the `native-linux` backend can't read that directory and fail with
+    /// [`FreeWiliError::InternalError`] while it is set, instead of silently enumerating
+    /// the real devices. With the `capture` feature, setting `FREEWILI_REPLAY` to a capture
+    /// file replays it instead, see the `capture` module.
     pub fn find_all() -> Result<Vec<FreeWiliDevice>> {
-        const MAX_DEVICE_COUNT: u32 = 255;
-        let mut device_count: u32 = MAX_DEVICE_COUNT;
-        let mut devices: [*mut fw_freewili_device_t; MAX_DEVICE_COUNT as usize] =
-            [std::ptr::null_mut(); MAX_DEVICE_COUNT as usize];
-        let mut error_msg = vec![0u8; 1024];
-        let mut error_size: u32 = error_msg.len() as u32;
-
-        let res: fw_error_t = unsafe {
-            ffi::fw_device_find_all(
-                devices.as_mut_ptr(),
-                &mut device_count,
-                error_msg.as_mut_ptr() as *mut i8,
-                &mut error_size,
-            )
-        };
-        match res {
-            x if x == ffi::_fw_error_t::fw_error_success as ffi::fw_error_t => {}
-            x if x == ffi::_fw_error_t::fw_error_internal_error as ffi::fw_error_t => {
-                let error_str = unsafe { CStr::from_ptr(error_msg.as_ptr() as *const c_char) }
-                    .to_string_lossy()
-                    .into_owned();
-                return Err(FreeWiliError::InternalError(Some(error_str)));
-            }
-            _ => return Err(res.into()),
+        #[cfg(feature = "capture")]
+        if let Some(devices) = capture::find_all_from_env() {
+            return devices;
+        }
+        if std::env::var_os(SYSFS_ROOT_ENV).is_some_and(|root| !root.is_empty()) {
+            #[cfg(all(feature = "native-linux", target_os = "linux"))]
+            return sysfs::find_all();
+            #[cfg(not(all(feature = "native-linux", target_os = "linux")))]
+            return Err(FreeWiliError::InternalError(Some(format!(
+                "{SYSFS_ROOT_ENV} is set, but only the `native-linux` backend on Linux can read it"
+            ))));
+        }
+        #[cfg(feature = "ffi")]
+        {
+            capi::find_all()
         }
-
-        let mut device_handles = Vec::with_capacity(device_count as usize);
-        for i in 0..device_count {
-            device_handles.push(FreeWiliDevice {
-                handle: Rc::new(FreeWiliDeviceHandle {
-                    ptr: devices[i as usize],
-                }),
-            });
+        #[cfg(all(not(feature = "ffi"), feature = "native-linux", target_os = "linux"))]
+        {
+            sysfs::find_all()
+        }
+        #[cfg(not(any(feature = "ffi", all(feature = "native-linux", target_os = "linux"))))]
+        {
+            Err(FreeWiliError::InternalError(Some(
+                "No device backend, enable the `ffi` or `native-linux` feature".to_string(),
+            )))
         }
-        Ok(device_handles)
     }
 
     pub fn device_type(&self) -> Result<DeviceType> {
-        let mut device_type: ffi::fw_devicetype_t = 0;
-        let res = unsafe { ffi::fw_device_get_type(self.handle.ptr, &mut device_type) };
-        if res != ffi::_fw_error_t::fw_error_success as u32 {
-            return Err(res.into());
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle.lock().device_type(),
+            DeviceHandle::Snapshot(handle) => Ok(handle.get()?.device_type),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-
-        Ok(device_type.into())
     }
 
     pub fn device_type_name(&self) -> Result<String> {
-        let device_type = self.device_type()? as ffi::fw_devicetype_t;
-
-        let mut device_type_name = vec![0i8; 1024];
-        let mut device_type_name_size: u32 = device_type_name.len() as u32;
-        let res = unsafe {
-            ffi::fw_device_get_type_name(
-                device_type,
-                device_type_name.as_mut_ptr(),
-                &mut device_type_name_size,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as ffi::fw_error_t {
-            return Err(res.into());
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(_) => capi::device_type_name(self.device_type()?),
+            DeviceHandle::Snapshot(handle) => Ok(handle.get()?.device_type_name.clone()),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-
-        let cstr = unsafe { CStr::from_ptr(device_type_name.as_ptr() as *const c_char) };
-        Ok(cstr.to_string_lossy().into_owned())
-    }
-
-    fn get_device_string(&self, string_type: ffi::_fw_stringtype_t) -> Result<String> {
-        let mut buffer = vec![0u8; 1024];
-        let mut buffer_size = buffer.len() as u32;
-
-        let res: fw_error_t = unsafe {
-            ffi::fw_device_get_str(
-                self.handle.ptr,
-                string_type as u32,
-                buffer.as_mut_ptr() as *mut c_char,
-                &mut buffer_size,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as u32 {
-            return Err(res.into());
-        }
-
-        let cstr = unsafe { CStr::from_ptr(buffer.as_ptr() as *const c_char) };
-        Ok(cstr.to_string_lossy().into_owned())
     }
 
     pub fn name(&self) -> Result<String> {
-        self.get_device_string(ffi::_fw_stringtype_t::fw_stringtype_name)
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle
+                .lock()
+                .device_string(ffi::_fw_stringtype_t::fw_stringtype_name),
+            DeviceHandle::Snapshot(handle) => Ok(handle.get()?.name.clone()),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
+        }
     }
 
     pub fn serial(&self) -> Result<String> {
-        self.get_device_string(ffi::_fw_stringtype_t::fw_stringtype_serial)
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle
+                .lock()
+                .device_string(ffi::_fw_stringtype_t::fw_stringtype_serial),
+            DeviceHandle::Snapshot(handle) => Ok(handle.get()?.serial.clone()),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
+        }
     }
 
     pub fn unique_id(&self) -> Result<u64> {
-        let mut unique_id: u64 = 0;
-        let res = unsafe { ffi::fw_device_unique_id(self.handle.ptr, &mut unique_id as *mut u64) };
-        if res != ffi::_fw_error_t::fw_error_success as u32 {
-            return Err(res.into());
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle.lock().unique_id(),
+            DeviceHandle::Snapshot(handle) => Ok(handle.get()?.unique_id),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-        Ok(unique_id)
     }
 
     pub fn standalone(&self) -> Result<bool> {
-        let mut is_standalone: bool = false;
-        let res = unsafe {
-            ffi::fw_device_is_standalone(self.handle.ptr, &mut is_standalone as *mut bool)
-        };
-        if res != ffi::_fw_error_t::fw_error_success as u32 {
-            return Err(res.into());
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle.lock().standalone(),
+            DeviceHandle::Snapshot(handle) => Ok(handle.get()?.standalone),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-        Ok(is_standalone)
     }
 
+    /// Reads a string of the USB device the C library's iterator currently points at.
+    ///
+    /// Devices without a C handle have no iterator and return
+    /// [`FreeWiliError::NoMoreDevices`].
+    #[cfg(feature = "ffi")]
     pub fn usb_device_get_string(&self, string_type: ffi::_fw_stringtype_t) -> Result<String> {
-        let mut buffer = vec![0u8; 1024];
-        let mut buffer_size = buffer.len() as u32;
-
-        let res: fw_error_t = unsafe {
-            ffi::fw_usb_device_get_str(
-                self.handle.ptr,
-                string_type as u32,
-                buffer.as_mut_ptr() as *mut c_char,
-                &mut buffer_size,
-            )
-        };
-        if res != ffi::_fw_error_t::fw_error_success as u32 {
-            return Err(res.into());
+        match &self.handle {
+            DeviceHandle::Ffi(handle) => handle.lock().usb_device_string(string_type),
+            DeviceHandle::Snapshot(handle) => handle.get().and(Err(FreeWiliError::NoMoreDevices)),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-
-        let cstr = unsafe { CStr::from_ptr(buffer.as_ptr() as *const c_char) };
-        Ok(cstr.to_string_lossy().into_owned())
     }
 
     pub fn get_usb_devices(&self) -> Result<Vec<USBDevice>> {
-        let res = unsafe { ffi::fw_usb_device_begin(self.handle.ptr) };
-        if res != ffi::_fw_error_t::fw_error_success as u32 {
-            return Err(res.into());
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle.lock().usb_devices(),
+            DeviceHandle::Snapshot(handle) => Ok(handle.get()?.usb_devices.clone()),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-
-        let mut devices = Vec::new();
-        loop {
-            let usb_device = unsafe { USBDevice::from_device(self.handle.ptr)? };
-            devices.push(usb_device);
-
-            let res = unsafe { ffi::fw_usb_device_next(self.handle.ptr) };
-            if res != ffi::_fw_error_t::fw_error_success as u32 {
-                break;
-            }
-        }
-        Ok(devices)
     }
 
     pub fn get_main_usb_device(&self) -> Result<USBDevice> {
-        let mut error_msg = vec![0u8; 1024];
-        let mut error_size: u32 = error_msg.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_set(
-                self.handle.ptr,
-                ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_main
-                    as ffi::fw_usbdevice_iter_set_t,
-                error_msg.as_mut_ptr() as *mut i8,
-                &mut error_size,
-            )
-        };
-        if res == ffi::_fw_error_t::fw_error_internal_error as fw_error_t {
-            let error_str = unsafe { CStr::from_ptr(error_msg.as_ptr() as *const c_char) }
-                .to_string_lossy()
-                .into_owned();
-            return Err(FreeWiliError::InternalError(Some(error_str)));
-        }
-        if res != ffi::_fw_error_t::fw_error_success as fw_error_t {
-            return Err(res.into());
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle
+                .lock()
+                .usb_device(ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_main),
+            DeviceHandle::Snapshot(handle) => handle
+                .get()?
+                .main
+                .clone()
+                .ok_or(FreeWiliError::NoMoreDevices),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-        unsafe { USBDevice::from_device(self.handle.ptr) }
     }
 
     pub fn get_display_usb_device(&self) -> Result<USBDevice> {
-        let mut error_msg = vec![0u8; 1024];
-        let mut error_size: u32 = error_msg.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_set(
-                self.handle.ptr,
-                ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_display
-                    as ffi::fw_usbdevice_iter_set_t,
-                error_msg.as_mut_ptr() as *mut i8,
-                &mut error_size,
-            )
-        };
-        if res == ffi::_fw_error_t::fw_error_internal_error as fw_error_t {
-            let error_str = unsafe { CStr::from_ptr(error_msg.as_ptr() as *const c_char) }
-                .to_string_lossy()
-                .into_owned();
-            return Err(FreeWiliError::InternalError(Some(error_str)));
-        }
-        if res != ffi::_fw_error_t::fw_error_success as fw_error_t {
-            return Err(res.into());
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle
+                .lock()
+                .usb_device(ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_display),
+            DeviceHandle::Snapshot(handle) => handle
+                .get()?
+                .display
+                .clone()
+                .ok_or(FreeWiliError::NoMoreDevices),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-        unsafe { USBDevice::from_device(self.handle.ptr) }
     }
 
     pub fn get_fpga_usb_device(&self) -> Result<USBDevice> {
-        let mut error_msg = vec![0u8; 1024];
-        let mut error_size: u32 = error_msg.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_set(
-                self.handle.ptr,
-                ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_fpga
-                    as ffi::fw_usbdevice_iter_set_t,
-                error_msg.as_mut_ptr() as *mut i8,
-                &mut error_size,
-            )
-        };
-        if res == ffi::_fw_error_t::fw_error_internal_error as fw_error_t {
-            let error_str = unsafe { CStr::from_ptr(error_msg.as_ptr() as *const c_char) }
-                .to_string_lossy()
-                .into_owned();
-            return Err(FreeWiliError::InternalError(Some(error_str)));
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle
+                .lock()
+                .usb_device(ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_fpga),
+            DeviceHandle::Snapshot(handle) => handle
+                .get()?
+                .fpga
+                .clone()
+                .ok_or(FreeWiliError::NoMoreDevices),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-        if res != ffi::_fw_error_t::fw_error_success as fw_error_t {
-            return Err(res.into());
-        }
-        unsafe { USBDevice::from_device(self.handle.ptr) }
     }
 
     pub fn get_hub_usb_device(&self) -> Result<USBDevice> {
-        let mut error_msg = vec![0u8; 1024];
-        let mut error_size: u32 = error_msg.len() as u32;
-        let res = unsafe {
-            ffi::fw_usb_device_set(
-                self.handle.ptr,
-                ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_hub
-                    as ffi::fw_usbdevice_iter_set_t,
-                error_msg.as_mut_ptr() as *mut i8,
-                &mut error_size,
-            )
-        };
-        if res == ffi::_fw_error_t::fw_error_internal_error as fw_error_t {
-            let error_str = unsafe { CStr::from_ptr(error_msg.as_ptr() as *const c_char) }
-                .to_string_lossy()
-                .into_owned();
-            return Err(FreeWiliError::InternalError(Some(error_str)));
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle
+                .lock()
+                .usb_device(ffi::_fw_usbdevice_iter_set_t::fw_usbdevice_iter_hub),
+            DeviceHandle::Snapshot(handle) => handle
+                .get()?
+                .hub
+                .clone()
+                .ok_or(FreeWiliError::NoMoreDevices),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-        if res != ffi::_fw_error_t::fw_error_success as fw_error_t {
-            return Err(res.into());
+    }
+
+    /// Reads the complete state of the device in one consistent pass.
+    ///
+    /// The C handle stays locked while reading, so no other thread can move the USB
+    /// device iterator in between, and the result no longer depends on the handle.
+    pub fn snapshot(&self) -> Result<DeviceSnapshot> {
+        match &self.handle {
+            #[cfg(feature = "ffi")]
+            DeviceHandle::Ffi(handle) => handle.lock().snapshot(),
+            DeviceHandle::Snapshot(handle) => Ok(handle.get()?.clone()),
+            DeviceHandle::Invalid => Err(FreeWiliError::InvalidDevice),
         }
-        unsafe { USBDevice::from_device(self.handle.ptr) }
+    }
+
+    /// Find all connected FreeWili devices and read their state with [`snapshot`](Self::snapshot).
+    pub fn find_all_snapshots() -> Result<Vec<DeviceSnapshot>> {
+        Self::find_all()?
+            .iter()
+            .map(FreeWiliDevice::snapshot)
+            .collect()
     }
 }
 
@@ -775,6 +622,114 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_concurrent_clones() -> Result<()> {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<FreeWiliDevice>();
+
+        let devices = FreeWiliDevice::find_all()?;
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = devices
+                .iter()
+                .flat_map(|device| std::iter::repeat_n(device, 4))
+                .map(|device| {
+                    let device = device.clone();
+                    scope.spawn(move || -> Result<()> {
+                        let expected = device.get_usb_devices()?;
+                        for _ in 0..100 {
+                            // Interleave with fw_usb_device_set() from the other threads
+                            let _ = device.get_main_usb_device();
+                            assert_eq!(device.get_usb_devices()?, expected);
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("worker panicked"))
+        })
+    }
+
+    #[test]
+    fn test_snapshot_matches_getters() -> Result<()> {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DeviceSnapshot>();
+
+        for device in FreeWiliDevice::find_all()? {
+            let snapshot = device.snapshot()?;
+            assert_eq!(snapshot.unique_id, device.unique_id()?);
+            assert_eq!(snapshot.device_type, device.device_type()?);
+            assert_eq!(snapshot.device_type_name, device.device_type_name()?);
+            assert_eq!(snapshot.name, device.name()?);
+            assert_eq!(snapshot.serial, device.serial()?);
+            assert_eq!(snapshot.standalone, device.standalone()?);
+            assert_eq!(snapshot.usb_devices, device.get_usb_devices()?);
+            assert_eq!(snapshot.main, device.get_main_usb_device().ok());
+            assert_eq!(snapshot.display, device.get_display_usb_device().ok());
+            assert_eq!(snapshot.fpga, device.get_fpga_usb_device().ok());
+            assert_eq!(snapshot.hub, device.get_hub_usb_device().ok());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_representation() {
+        let usb_device = USBDevice {
+            kind: UsbDeviceType::SerialMain,
+            kind_name: "Serial Main".to_string(),
+            vid: 0x093c,
+            pid: 0x2054,
+            name: "FreeWili MainCPU v73".to_string(),
+            serial: "E463A8574B551838".to_string(),
+            location: 1,
+            port_chain: vec![3, 4, 1],
+            port: Some("/dev/ttyACM0".to_string()),
+            path: None,
+        };
+        let json = serde_json::to_value(&usb_device).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "kind": "serialmain",
+                "kind_name": "Serial Main",
+                "vid": 0x093c,
+                "pid": 0x2054,
+                "name": "FreeWili MainCPU v73",
+                "serial": "E463A8574B551838",
+                "location": 1,
+                "port_chain": [3, 4, 1],
+                "port": "/dev/ttyACM0",
+                "path": null,
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<USBDevice>(json).unwrap(),
+            usb_device
+        );
+
+        assert_eq!(
+            serde_json::to_value(DeviceType::Defcon2025FwBadge).unwrap(),
+            "defcon2025fwbadge"
+        );
+        for error in [
+            FreeWiliError::InvalidDevice,
+            FreeWiliError::InternalError(Some("udev failed".to_string())),
+        ] {
+            let json = serde_json::to_string(&error).unwrap();
+            assert_eq!(serde_json::from_str::<FreeWiliError>(&json).unwrap(), error);
+        }
+        assert_eq!(
+            serde_json::to_value(FreeWiliError::InternalError(Some(
+                "udev failed".to_string()
+            )))
+            .unwrap(),
+            serde_json::json!({"kind": "internal_error", "message": "udev failed"})
+        );
+    }
+
     #[test]
     fn test_handle_copy() -> Result<()> {
         let devices = FreeWiliDevice::find_all()?;
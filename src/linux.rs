@@ -0,0 +1,215 @@
+//! Reading sysfs and procfs, shared by the sysfs backend and the storage module.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{PortPath, USBDevice};
+
+/// Environment variable naming the directory to read `sys`, `dev` and `proc` from.
+pub const ROOT_ENV: &str = crate::SYSFS_ROOT_ENV;
+
+/// The root named by [`ROOT_ENV`] if it is set, or `/` otherwise.
+pub(crate) fn root_from_env() -> PathBuf {
+    std::env::var_os(ROOT_ENV)
+        .filter(|root| !root.is_empty())
+        .map_or_else(|| PathBuf::from("/"), PathBuf::from)
+}
+
+/// The checked-in `tests/fixtures/sysfs` tree, or `None` if it is missing.
+///
+/// Its file names contain `:`, so it isn't checked out on Windows CI runners or packaged
+/// into the published crate, and tests reading it skip there.
+#[cfg(test)]
+pub(crate) fn fixture_root() -> Option<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sysfs");
+    if root.is_dir() {
+        Some(root)
+    } else {
+        println!("No sysfs fixture at {}, skipping.", root.display());
+        None
+    }
+}
+
+/// Lists `/sys/class/<class>` entries that belong to a USB device, sorted by name,
+/// together with the name of that USB device.
+pub(crate) fn class_devices(sys: &Path, class: &str) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(sys.join("class").join(class)) else {
+        return Vec::new();
+    };
+    // Only look at directories below sysfs for the USB device, not the root itself
+    let sys = fs::canonicalize(sys).unwrap_or_else(|_| sys.to_path_buf());
+    let mut devices: Vec<(String, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = fs::canonicalize(entry.path()).ok()?;
+            Some((usb_ancestor(path.strip_prefix(&sys).ok()?)?, path))
+        })
+        .collect();
+    devices.sort_by(|(_, a), (_, b)| a.file_name().cmp(&b.file_name()));
+    devices
+}
+
+/// Name of the nearest USB device in a sysfs device path.
+pub(crate) fn usb_ancestor(path: &Path) -> Option<String> {
+    Some(PortPath::from_sysfs_path(path)?.to_string())
+}
+
+/// Position of `usb_device` in `<root>/sys/bus/usb/devices`, the one entry with its hub
+/// ports, VID, PID and, if it has one, serial number.
+///
+/// Port chains don't include the bus, so the same ports can exist on several buses.
+/// Returns `None` rather than guessing when more than one entry matches.
+pub(crate) fn find_port_path(usb_device: &USBDevice, root: &Path) -> Option<PortPath> {
+    let entries = fs::read_dir(root.join("sys/bus/usb/devices")).ok()?;
+    let mut matches = entries.flatten().filter_map(|entry| {
+        let port_path = PortPath::from_kernel_name(entry.file_name().to_str()?)?;
+        let path = entry.path();
+        let matches = port_path.ports() == usb_device.port_chain
+            && read_hex(&path.join("idVendor")) == Some(usb_device.vid)
+            && read_hex(&path.join("idProduct")) == Some(usb_device.pid)
+            && (usb_device.serial.is_empty()
+                || read_attr(&path.join("serial")) == usb_device.serial);
+        matches.then_some(port_path)
+    });
+    let port_path = matches.next()?;
+    matches.next().is_none().then_some(port_path)
+}
+
+/// Device node of a class device relative to `/dev`, from `DEVNAME` in its uevent.
+pub(crate) fn dev_name(class_path: &Path, uevent: &BTreeMap<String, String>) -> PathBuf {
+    match uevent.get("DEVNAME") {
+        Some(name) => PathBuf::from(name),
+        None => PathBuf::from(class_path.file_name().unwrap_or_default()),
+    }
+}
+
+/// Partitions of a disk, the subdirectories with a `partition` attribute.
+pub(crate) fn partitions(disk: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(disk) else {
+        return Vec::new();
+    };
+    let mut partitions: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join("partition").is_file())
+        .collect();
+    partitions.sort();
+    partitions
+}
+
+/// Reads a sysfs attribute without its trailing newline, or an empty string.
+pub(crate) fn read_attr(path: &Path) -> String {
+    fs::read_to_string(path)
+        .map(|value| value.trim_end_matches('\n').to_string())
+        .unwrap_or_default()
+}
+
+/// Reads a hexadecimal sysfs attribute like `idVendor`.
+pub(crate) fn read_hex(path: &Path) -> Option<u16> {
+    u16::from_str_radix(read_attr(path).trim(), 16).ok()
+}
+
+/// Reads the `KEY=value` lines of a uevent file.
+pub(crate) fn read_uevent(path: &Path) -> BTreeMap<String, String> {
+    read_attr(path)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Decodes the octal escapes (`\040` for a space) used in `/proc/mounts`.
+pub(crate) fn unescape_mount(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                unescaped.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usb_ancestor() {
+        assert_eq!(
+            usb_ancestor(Path::new(
+                "/sys/devices/pci0000:00/0000:00:14.0/usb3/3-4/3-4.1/3-4.1:1.0/tty/ttyACM0"
+            ))
+            .as_deref(),
+            Some("3-4.1")
+        );
+        assert_eq!(
+            usb_ancestor(Path::new("/sys/devices/virtual/tty/tty0")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_port_path() {
+        let Some(root) = fixture_root() else {
+            return;
+        };
+        let usb_device = |pid: u16, port_chain: Vec<u32>, serial: &str| USBDevice {
+            serial: serial.to_string(),
+            ..crate::usbdef::usb_device(0x093c, pid, port_chain)
+        };
+        let main = usb_device(0x2054, vec![4, 1], "E463A8574B551838");
+        assert_eq!(
+            find_port_path(&main, &root).map(|path| path.to_string()),
+            Some("3-4.1".to_string())
+        );
+        let winky = usb_device(0x2056, vec![2], "");
+        assert_eq!(find_port_path(&winky, &root), Some(PortPath::new(1, [2])));
+        // Same ports, but not the same device
+        assert_eq!(
+            find_port_path(&usb_device(0x2055, vec![4, 1], ""), &root),
+            None
+        );
+        assert_eq!(
+            find_port_path(&usb_device(0x2054, vec![4, 1], "X"), &root),
+            None
+        );
+        assert_eq!(find_port_path(&main, &root.join("missing")), None);
+    }
+
+    #[test]
+    fn test_find_port_path_ambiguous() {
+        // A Winky on port 2 of two buses
+        let root = std::env::temp_dir().join(format!("freewili-port-path-{}", std::process::id()));
+        for name in ["1-2", "3-2"] {
+            let dir = root.join("sys/bus/usb/devices").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("idVendor"), "093c\n").unwrap();
+            fs::write(dir.join("idProduct"), "2056\n").unwrap();
+        }
+        let winky = crate::usbdef::usb_device(0x093c, 0x2056, vec![2]);
+        let ambiguous = find_port_path(&winky, &root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(ambiguous, None);
+    }
+
+    #[test]
+    fn test_unescape_mount() {
+        assert_eq!(unescape_mount("/media/user/RPI-RP2"), "/media/user/RPI-RP2");
+        assert_eq!(unescape_mount("/media/my\\040disk"), "/media/my disk");
+        assert_eq!(unescape_mount("trailing\\"), "trailing\\");
+    }
+}
@@ -0,0 +1,134 @@
+//! Async wrappers around the blocking enumeration APIs, for use with tokio.
+//!
+//! The C library does its USB enumeration synchronously, so these run it on tokio's
+//! blocking thread pool instead of stalling the runtime.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::{
+    DeviceEvent, DeviceSelector, FreeWiliDevice, FreeWiliError, FreeWiliWatcher, Result, USBDevice,
+};
+
+/// How often the watcher thread checks whether its stream was dropped.
+const CLOSED_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+async fn spawn_blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| FreeWiliError::InternalError(Some(e.to_string())))?
+}
+
+/// Async version of [`FreeWiliDevice::find_all`].
+pub async fn find_all() -> Result<Vec<FreeWiliDevice>> {
+    spawn_blocking(FreeWiliDevice::find_all).await
+}
+
+/// Async version of [`FreeWiliDevice::get_usb_devices`].
+pub async fn get_usb_devices(device: &FreeWiliDevice) -> Result<Vec<USBDevice>> {
+    let device = device.clone();
+    spawn_blocking(move || device.get_usb_devices()).await
+}
+
+/// Async version of [`FreeWiliDevice::wait_for`].
+pub async fn wait_for(
+    selector: &DeviceSelector,
+    timeout: Duration,
+) -> Result<Option<FreeWiliDevice>> {
+    let selector = selector.clone();
+    spawn_blocking(move || FreeWiliDevice::wait_for(&selector, timeout)).await
+}
+
+/// Watches for hotplug events with the default [`FreeWiliWatcher`] settings.
+///
+/// ```no_run
+/// # async fn run() -> freewili_finder_rs::Result<()> {
+/// let mut events = freewili_finder_rs::nonblocking::watch()?;
+/// while let Some(event) = events.next().await {
+///     println!("{event:?}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn watch() -> Result<DeviceEventStream> {
+    FreeWiliWatcher::new().into_stream()
+}
+
+/// Stream of hotplug events produced by a [`FreeWiliWatcher`].
+///
+/// The watcher runs on a dedicated thread, which exits shortly after the stream is dropped.
+#[derive(Debug)]
+pub struct DeviceEventStream {
+    receiver: mpsc::Receiver<DeviceEvent>,
+}
+
+impl DeviceEventStream {
+    /// Waits for the next event, returns `None` if the watcher thread stopped.
+    pub async fn next(&mut self) -> Option<DeviceEvent> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for DeviceEventStream {
+    type Item = DeviceEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl FreeWiliWatcher {
+    /// Moves the watcher to a background thread and returns its events as a [`Stream`].
+    ///
+    /// Fails only if the thread can't be spawned.
+    pub fn into_stream(self) -> Result<DeviceEventStream> {
+        let (sender, receiver) = mpsc::channel(16);
+        std::thread::Builder::new()
+            .name("freewili-watcher".to_string())
+            .spawn(move || forward_events(self, sender))
+            .map_err(|e| {
+                FreeWiliError::InternalError(Some(format!("Failed to spawn watcher thread: {e}")))
+            })?;
+        Ok(DeviceEventStream { receiver })
+    }
+}
+
+fn forward_events(mut watcher: FreeWiliWatcher, sender: mpsc::Sender<DeviceEvent>) {
+    while !sender.is_closed() {
+        let Some(event) = watcher.poll(CLOSED_CHECK_INTERVAL) else {
+            continue;
+        };
+        if sender.blocking_send(event).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[tokio::test]
+    async fn test_find_all_async() -> Result<()> {
+        let find = find_all();
+        assert_send(&find);
+
+        for device in find.await? {
+            assert_send(&get_usb_devices(&device));
+            let usb_devices = get_usb_devices(&device).await?;
+            assert_eq!(usb_devices, device.get_usb_devices()?);
+        }
+
+        Ok(())
+    }
+}
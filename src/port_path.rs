@@ -0,0 +1,305 @@
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::USBDevice;
+
+/// Physical position of a USB device: its bus and the hub ports leading to it.
+///
+/// Formats and parses the Linux kernel's device names, `usb3` for the root hub of bus 3
+/// and `3-4.1` for port 1 of the hub on port 4 of that root hub, which are the entries of
+/// `/sys/bus/usb/devices` and the last components of udev `DEVPATH`s. Paths order by
+/// physical position: by bus, then port by port, with a hub before the devices behind it.
+///
+/// ```
+/// use freewili_finder_rs::PortPath;
+///
+/// let main: PortPath = "3-4.1".parse()?;
+/// assert_eq!(main.bus(), 3);
+/// assert_eq!(main.ports(), [4, 1]);
+///
+/// let hub = main.parent().unwrap();
+/// assert_eq!(hub.to_string(), "3-4");
+/// assert!(hub.is_ancestor_of(&main));
+/// assert!(hub < main && main < "3-4.2".parse()?);
+///
+/// let devpath = "/devices/pci0000:00/0000:00:14.0/usb3/3-4/3-4.1/3-4.1:1.0/tty/ttyACM0";
+/// assert_eq!(PortPath::from_sysfs_path(devpath), Some(main));
+/// # Ok::<(), freewili_finder_rs::ParsePortPathError>(())
+/// ```
+///
+/// With the `serde` feature this serializes as the kernel name string.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PortPath {
+    bus: u32,
+    ports: Vec<u32>,
+}
+
+/// Error returned when parsing a [`PortPath`] fails.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid USB port path '{0}'")]
+pub struct ParsePortPathError(String);
+
+impl PortPath {
+    /// Path of the device behind `ports` on bus `bus`.
+    pub fn new(bus: u32, ports: impl Into<Vec<u32>>) -> Self {
+        PortPath {
+            bus,
+            ports: ports.into(),
+        }
+    }
+
+    /// Path of the root hub of bus `bus`.
+    pub fn root_hub(bus: u32) -> Self {
+        Self::new(bus, [])
+    }
+
+    /// Path of the last USB device in a sysfs path or udev `DEVPATH`.
+    ///
+    /// Interfaces and class devices below it, such as `3-4.1:1.0/tty/ttyACM0`, are
+    /// skipped. Returns `None` if the path contains no USB device.
+    pub fn from_sysfs_path(path: impl AsRef<std::path::Path>) -> Option<Self> {
+        path.as_ref()
+            .ancestors()
+            .filter_map(|ancestor| ancestor.file_name()?.to_str())
+            .find_map(Self::from_kernel_name)
+    }
+
+    /// Parses a kernel name, without accepting a bare bus number like [`FromStr`] does.
+    pub(crate) fn from_kernel_name(name: &str) -> Option<Self> {
+        parse_kernel_name(name, false)
+    }
+
+    /// Bus number, the number of the root hub.
+    pub fn bus(&self) -> u32 {
+        self.bus
+    }
+
+    /// Hub ports from the root hub to the device, empty for the root hub itself.
+    pub fn ports(&self) -> &[u32] {
+        &self.ports
+    }
+
+    /// Port on the parent hub, `None` for a root hub.
+    pub fn port(&self) -> Option<u32> {
+        self.ports.last().copied()
+    }
+
+    /// Number of ports from the root hub, 0 for the root hub itself.
+    pub fn depth(&self) -> usize {
+        self.ports.len()
+    }
+
+    /// Whether this is the root hub of its bus.
+    pub fn is_root_hub(&self) -> bool {
+        self.ports.is_empty()
+    }
+
+    /// Path of the hub this device is connected to, `None` for a root hub.
+    pub fn parent(&self) -> Option<Self> {
+        let (_, ports) = self.ports.split_last()?;
+        Some(Self::new(self.bus, ports))
+    }
+
+    /// Path of the device on `port` of this hub.
+    pub fn child(&self, port: u32) -> Self {
+        let mut child = self.clone();
+        child.ports.push(port);
+        child
+    }
+
+    /// Whether `prefix` is this path or one of its ancestors.
+    pub fn starts_with(&self, prefix: &PortPath) -> bool {
+        self.bus == prefix.bus && self.ports.starts_with(&prefix.ports)
+    }
+
+    /// Whether the device at `other` is behind this hub, at any depth.
+    pub fn is_ancestor_of(&self, other: &PortPath) -> bool {
+        other.depth() > self.depth() && other.starts_with(self)
+    }
+
+    /// Whether this device is behind the hub at `other`, at any depth.
+    pub fn is_descendant_of(&self, other: &PortPath) -> bool {
+        other.is_ancestor_of(self)
+    }
+}
+
+impl USBDevice {
+    /// Physical position of the device: its bus and [`port_chain`](USBDevice::port_chain).
+    ///
+    /// Port chains don't include the bus, so it is looked up when called in
+    /// `/sys/bus/usb/devices`, below the root named by `FREEWILI_SYSFS_ROOT` if that is set.
+    /// Returns `None` if no entry there has this device's ports, VID, PID and serial
+    /// number, if more than one does, and always outside of Linux.
+    pub fn port_path(&self) -> Option<PortPath> {
+        #[cfg(target_os = "linux")]
+        {
+            crate::linux::find_port_path(self, &crate::linux::root_from_env())
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+
+    /// Bus number of the device, see [`port_path`](USBDevice::port_path).
+    pub fn bus(&self) -> Option<u32> {
+        self.port_path().map(|port_path| port_path.bus())
+    }
+}
+
+/// Parses `usb3` or `3-4.1`, and with `bare_bus` also `3` for a root hub.
+fn parse_kernel_name(name: &str, bare_bus: bool) -> Option<PortPath> {
+    let number = |s: &str| {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    };
+    if let Some(bus) = name.strip_prefix("usb") {
+        return Some(PortPath::root_hub(number(bus)?));
+    }
+    match name.split_once('-') {
+        Some((bus, ports)) => Some(PortPath::new(
+            number(bus)?,
+            ports.split('.').map(number).collect::<Option<Vec<_>>>()?,
+        )),
+        None if bare_bus => Some(PortPath::root_hub(number(name)?)),
+        None => None,
+    }
+}
+
+impl FromStr for PortPath {
+    type Err = ParsePortPathError;
+
+    /// Parses a kernel name like `3-4.1` or `usb3`, or a bare bus number like `3`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        parse_kernel_name(s, true).ok_or_else(|| ParsePortPathError(s.to_string()))
+    }
+}
+
+impl fmt::Display for PortPath {
+    /// Formats the kernel name, `usb3` for a root hub and `3-4.1` otherwise.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((first, rest)) = self.ports.split_first() else {
+            return write!(f, "usb{}", self.bus);
+        };
+        write!(f, "{}-{first}", self.bus)?;
+        for port in rest {
+            write!(f, ".{port}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PortPath {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PortPath {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> PortPath {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(path("1-3.2.4"), PortPath::new(1, [3, 2, 4]));
+        assert_eq!(path("usb2"), PortPath::root_hub(2));
+        assert_eq!(path("2"), PortPath::root_hub(2));
+        for name in ["1-3.2.4", "12-1", "usb2"] {
+            assert_eq!(path(name).to_string(), name);
+        }
+        for invalid in [
+            "",
+            "usb",
+            "1-",
+            "1-3.",
+            "1-3..2",
+            "a-1",
+            "1-3.2:1.0",
+            "-1",
+            "1 -3",
+        ] {
+            assert_eq!(
+                invalid.parse::<PortPath>(),
+                Err(ParsePortPathError(invalid.to_string())),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_sysfs_path() {
+        assert_eq!(
+            PortPath::from_sysfs_path(
+                "/devices/pci0000:00/0000:00:14.0/usb3/3-4/3-4.1/3-4.1:1.0/tty/ttyACM0"
+            ),
+            Some(path("3-4.1"))
+        );
+        assert_eq!(
+            PortPath::from_sysfs_path("/sys/bus/usb/devices/usb3"),
+            Some(PortPath::root_hub(3))
+        );
+        assert_eq!(PortPath::from_sysfs_path("/devices/virtual/tty/tty0"), None);
+    }
+
+    #[test]
+    fn test_relations() {
+        let hub = path("1-3");
+        let device = path("1-3.2.4");
+        assert_eq!(device.parent(), Some(path("1-3.2")));
+        assert_eq!(PortPath::root_hub(1).parent(), None);
+        assert_eq!(hub.child(2).child(4), device);
+        assert_eq!(device.port(), Some(4));
+        assert_eq!(device.depth(), 3);
+
+        assert!(device.starts_with(&hub));
+        assert!(device.starts_with(&device));
+        assert!(hub.is_ancestor_of(&device));
+        assert!(device.is_descendant_of(&PortPath::root_hub(1)));
+        assert!(!device.is_ancestor_of(&device));
+        assert!(!path("1-3.2").is_ancestor_of(&path("1-3.20")));
+        assert!(!path("2-3").is_ancestor_of(&path("1-3.2")));
+    }
+
+    #[test]
+    fn test_ordering() {
+        let mut paths: Vec<_> = ["2-1", "1-3.2", "1-10", "usb1", "1-3", "1-3.1.4", "1-2"]
+            .into_iter()
+            .map(path)
+            .collect();
+        paths.sort();
+        let sorted: Vec<_> = paths.iter().map(PortPath::to_string).collect();
+        assert_eq!(
+            sorted,
+            ["usb1", "1-2", "1-3", "1-3.1.4", "1-3.2", "1-10", "2-1"]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let device = path("3-4.1");
+        assert_eq!(serde_json::to_value(&device).unwrap(), "3-4.1");
+        assert_eq!(
+            serde_json::from_value::<PortPath>("3-4.1".into()).unwrap(),
+            device
+        );
+        assert!(serde_json::from_value::<PortPath>("3-x".into()).is_err());
+    }
+}
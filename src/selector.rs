@@ -0,0 +1,725 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::{DeviceSnapshot, DeviceType, FreeWiliDevice, PortPath, Result, USBDevice};
+
+/// Interval between rescans while waiting when no hotplug notification arrives.
+#[cfg(target_os = "linux")]
+const WAIT_RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval between rescans while waiting on hosts without hotplug notifications.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// USB interface of a FreeWili, as returned by the `get_*_usb_device` getters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interface {
+    /// Main CPU, see [`FreeWiliDevice::get_main_usb_device`]
+    Main,
+    /// Display CPU, see [`FreeWiliDevice::get_display_usb_device`]
+    Display,
+    /// FPGA (FTDI), see [`FreeWiliDevice::get_fpga_usb_device`]
+    Fpga,
+    /// USB hub, see [`FreeWiliDevice::get_hub_usb_device`]
+    Hub,
+}
+
+impl Interface {
+    const ALL: [Interface; 4] = [
+        Interface::Main,
+        Interface::Display,
+        Interface::Fpga,
+        Interface::Hub,
+    ];
+
+    fn key(self) -> &'static str {
+        match self {
+            Interface::Main => "main",
+            Interface::Display => "display",
+            Interface::Fpga => "fpga",
+            Interface::Hub => "hub",
+        }
+    }
+
+    /// The USB device of this interface in `device`, if present.
+    pub fn usb_device(self, device: &DeviceSnapshot) -> Option<&USBDevice> {
+        match self {
+            Interface::Main => device.main.as_ref(),
+            Interface::Display => device.display.as_ref(),
+            Interface::Fpga => device.fpga.as_ref(),
+            Interface::Hub => device.hub.as_ref(),
+        }
+    }
+
+    /// Returns `true` if the interface is enumerated and usable.
+    ///
+    /// Serial and mass storage interfaces also need their serial port or mount path to
+    /// exist, as those show up a little after the USB device itself.
+    pub fn is_ready(self, device: &DeviceSnapshot) -> bool {
+        let Some(usb_device) = self.usb_device(device) else {
+            return false;
+        };
+        match (&usb_device.port, &usb_device.path) {
+            (Some(port), _) => Path::new(port).exists(),
+            (None, Some(path)) => Path::new(path).exists(),
+            (None, None) => self == Interface::Hub,
+        }
+    }
+}
+
+/// Describes which FreeWili device to use.
+///
+/// Selectors are built from the variants below or parsed from a query string. A query
+/// is made of `key=value` terms:
+///
+/// | Term | Matches |
+/// |------|---------|
+/// | `serial=FW4037` | Serial number, case-insensitive |
+/// | `type=defcon2025fwbadge` | [`DeviceType`], by its lowercase name |
+/// | `unique_id=259` or `id=259` | Unique ID |
+/// | `port=3-4.1` | [`PortPath`] of any of the device's USB devices, `bus-port.port...` |
+/// | `vid:pid=093c:2054` | VID and PID (hex) of any of the device's USB devices |
+/// | `name=Free-WiLi` | Device name, case-insensitive |
+/// | `name~=wili` | Part of the device name, case-insensitive |
+/// | `ready=main` | [`Interface`] is ready: `main`, `display`, `fpga` or `hub` |
+///
+/// Terms are combined with `and` (also `&`, `&&` or `,`) and `or` (also `|` or `||`),
+/// where `and` binds tighter, and can be grouped with parentheses. Values containing
+/// spaces or operators can be double quoted.
+///
+/// ```
+/// use freewili_finder_rs::{DeviceSelector, Interface};
+///
+/// let selector: DeviceSelector = "serial=FW4037 and ready=main".parse()?;
+/// assert_eq!(
+///     selector,
+///     DeviceSelector::Serial("FW4037".to_string()).and(DeviceSelector::Ready(Interface::Main))
+/// );
+///
+/// let selector: DeviceSelector = "(type=winky or name~=\"Free WiLi\") and port=1-3.2".parse()?;
+/// # Ok::<(), freewili_finder_rs::ParseSelectorError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Serial number of the device, compared case-insensitively
+    Serial(String),
+    /// Type of the device
+    DeviceType(DeviceType),
+    /// Location based unique ID of the device
+    UniqueId(u64),
+    /// One of the device's USB devices is at this bus and port chain, see
+    /// [`USBDevice::port_path`]
+    PortPath(PortPath),
+    /// One of the device's USB devices has this VID and PID
+    VidPid(u16, u16),
+    /// Name of the device, compared case-insensitively
+    Name(String),
+    /// Part of the name of the device, compared case-insensitively
+    NameContains(String),
+    /// The interface is present and ready, see [`Interface::is_ready`]
+    Ready(Interface),
+    /// Every selector matches, an empty list matches any device
+    And(Vec<DeviceSelector>),
+    /// At least one selector matches, an empty list matches no device
+    Or(Vec<DeviceSelector>),
+}
+
+impl DeviceSelector {
+    /// Selector matching any device.
+    pub fn any() -> Self {
+        DeviceSelector::And(Vec::new())
+    }
+
+    /// Combines two selectors, both of which have to match.
+    pub fn and(self, other: DeviceSelector) -> Self {
+        match (self, other) {
+            (DeviceSelector::And(mut selectors), DeviceSelector::And(others)) => {
+                selectors.extend(others);
+                DeviceSelector::And(selectors)
+            }
+            (DeviceSelector::And(mut selectors), other) => {
+                selectors.push(other);
+                DeviceSelector::And(selectors)
+            }
+            (selector, DeviceSelector::And(mut others)) => {
+                others.insert(0, selector);
+                DeviceSelector::And(others)
+            }
+            (selector, other) => DeviceSelector::And(vec![selector, other]),
+        }
+    }
+
+    /// Combines two selectors, either of which has to match.
+    pub fn or(self, other: DeviceSelector) -> Self {
+        match (self, other) {
+            (DeviceSelector::Or(mut selectors), DeviceSelector::Or(others)) => {
+                selectors.extend(others);
+                DeviceSelector::Or(selectors)
+            }
+            (DeviceSelector::Or(mut selectors), other) => {
+                selectors.push(other);
+                DeviceSelector::Or(selectors)
+            }
+            (selector, DeviceSelector::Or(mut others)) => {
+                others.insert(0, selector);
+                DeviceSelector::Or(others)
+            }
+            (selector, other) => DeviceSelector::Or(vec![selector, other]),
+        }
+    }
+
+    /// Returns `true` if `device` is selected.
+    pub fn matches(&self, device: &DeviceSnapshot) -> bool {
+        match self {
+            DeviceSelector::Serial(serial) => device.serial.eq_ignore_ascii_case(serial),
+            DeviceSelector::DeviceType(device_type) => device.device_type == *device_type,
+            DeviceSelector::UniqueId(unique_id) => device.unique_id == *unique_id,
+            DeviceSelector::PortPath(port_path) => device.usb_devices.iter().any(|usb_device| {
+                usb_device.port_chain == port_path.ports()
+                    && usb_device.port_path().as_ref() == Some(port_path)
+            }),
+            DeviceSelector::VidPid(vid, pid) => device
+                .usb_devices
+                .iter()
+                .any(|usb_device| usb_device.vid == *vid && usb_device.pid == *pid),
+            DeviceSelector::Name(name) => device.name.to_lowercase() == name.to_lowercase(),
+            DeviceSelector::NameContains(part) => {
+                device.name.to_lowercase().contains(&part.to_lowercase())
+            }
+            DeviceSelector::Ready(interface) => interface.is_ready(device),
+            DeviceSelector::And(selectors) => {
+                selectors.iter().all(|selector| selector.matches(device))
+            }
+            DeviceSelector::Or(selectors) => {
+                selectors.iter().any(|selector| selector.matches(device))
+            }
+        }
+    }
+}
+
+/// Error returned when parsing a [`DeviceSelector`] query fails.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid device selector: {0}")]
+pub struct ParseSelectorError(String);
+
+impl FromStr for DeviceSelector {
+    type Err = ParseSelectorError;
+
+    fn from_str(query: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?.into_iter().peekable(),
+        };
+        let selector = parser.or_expr()?;
+        match parser.tokens.next() {
+            None => Ok(selector),
+            Some(Token::RParen) => Err(ParseSelectorError("unmatched ')'".to_string())),
+            Some(token) => Err(ParseSelectorError(format!("unexpected {token:?}"))),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    /// Formats the selector as a query that parses back to the same selector.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let group = |f: &mut fmt::Formatter<'_>, selectors: &[DeviceSelector], op: &str| {
+            for (i, selector) in selectors.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {op} ")?;
+                }
+                match selector {
+                    DeviceSelector::And(nested) | DeviceSelector::Or(nested)
+                        if nested.len() != 1 =>
+                    {
+                        write!(f, "({selector})")?
+                    }
+                    _ => write!(f, "{selector}")?,
+                }
+            }
+            Ok(())
+        };
+        match self {
+            DeviceSelector::Serial(serial) => write!(f, "serial={}", quoted(serial)),
+            DeviceSelector::DeviceType(device_type) => {
+                write!(f, "type={}", device_type_key(*device_type))
+            }
+            DeviceSelector::UniqueId(unique_id) => write!(f, "unique_id={unique_id}"),
+            DeviceSelector::PortPath(port_path) => write!(f, "port={port_path}"),
+            DeviceSelector::VidPid(vid, pid) => write!(f, "vid:pid={vid:04x}:{pid:04x}"),
+            DeviceSelector::Name(name) => write!(f, "name={}", quoted(name)),
+            DeviceSelector::NameContains(part) => write!(f, "name~={}", quoted(part)),
+            DeviceSelector::Ready(interface) => write!(f, "ready={}", interface.key()),
+            // Empty groups have no query syntax, parentheses around nothing are the closest
+            DeviceSelector::And(selectors) | DeviceSelector::Or(selectors)
+                if selectors.is_empty() =>
+            {
+                write!(f, "()")
+            }
+            DeviceSelector::And(selectors) => group(f, selectors, "and"),
+            DeviceSelector::Or(selectors) => group(f, selectors, "or"),
+        }
+    }
+}
+
+fn quoted(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || "()&|,\"".contains(c)) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn device_type_key(device_type: DeviceType) -> &'static str {
+    match device_type {
+        DeviceType::Unknown => "unknown",
+        DeviceType::Freewili => "freewili",
+        DeviceType::Defcon2024Badge => "defcon2024badge",
+        DeviceType::Defcon2025FwBadge => "defcon2025fwbadge",
+        DeviceType::Uf2 => "uf2",
+        DeviceType::Winky => "winky",
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Term {
+        key: String,
+        contains: bool,
+        value: String,
+    },
+}
+
+fn tokenize(query: &str) -> std::result::Result<Vec<Token>, ParseSelectorError> {
+    let is_delimiter = |c: char| c.is_whitespace() || "()&|,\"".contains(c);
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' | '&' | '|' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    ',' => Token::And,
+                    '&' => {
+                        chars.next_if_eq(&'&');
+                        Token::And
+                    }
+                    _ => {
+                        chars.next_if_eq(&'|');
+                        Token::Or
+                    }
+                });
+            }
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = chars.next_if(|&c| !is_delimiter(c) && c != '=' && c != '~') {
+                    key.push(c);
+                }
+                if key.is_empty() {
+                    return Err(ParseSelectorError(format!("unexpected '{c}'")));
+                }
+                let contains = chars.next_if_eq(&'~').is_some();
+                if chars.next_if_eq(&'=').is_none() {
+                    match key.to_lowercase().as_str() {
+                        "and" if !contains => tokens.push(Token::And),
+                        "or" if !contains => tokens.push(Token::Or),
+                        _ => return Err(ParseSelectorError(format!("expected '=' after '{key}'"))),
+                    }
+                    continue;
+                }
+
+                let mut value = String::new();
+                if chars.next_if_eq(&'"').is_some() {
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some(c) => value.push(c),
+                                None => break,
+                            },
+                            Some(c) => value.push(c),
+                            None => {
+                                return Err(ParseSelectorError(format!(
+                                    "unterminated quote in value of '{key}'"
+                                )));
+                            }
+                        }
+                    }
+                } else {
+                    while let Some(c) = chars.next_if(|&c| !is_delimiter(c)) {
+                        value.push(c);
+                    }
+                }
+                tokens.push(Token::Term {
+                    key,
+                    contains,
+                    value,
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<I: Iterator<Item = Token>> {
+    tokens: std::iter::Peekable<I>,
+}
+
+impl<I: Iterator<Item = Token>> Parser<I> {
+    fn or_expr(&mut self) -> std::result::Result<DeviceSelector, ParseSelectorError> {
+        let mut selector = self.and_expr()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            selector = selector.or(self.and_expr()?);
+        }
+        Ok(selector)
+    }
+
+    fn and_expr(&mut self) -> std::result::Result<DeviceSelector, ParseSelectorError> {
+        let mut selector = self.term()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            selector = selector.and(self.term()?);
+        }
+        Ok(selector)
+    }
+
+    fn term(&mut self) -> std::result::Result<DeviceSelector, ParseSelectorError> {
+        match self.tokens.next() {
+            Some(Token::LParen) => {
+                let selector = self.or_expr()?;
+                match self.tokens.next() {
+                    Some(Token::RParen) => Ok(selector),
+                    _ => Err(ParseSelectorError("missing ')'".to_string())),
+                }
+            }
+            Some(Token::Term {
+                key,
+                contains,
+                value,
+            }) => parse_term(&key, contains, value),
+            Some(token) => Err(ParseSelectorError(format!(
+                "expected a term, found {token:?}"
+            ))),
+            None => Err(ParseSelectorError("expected a term".to_string())),
+        }
+    }
+}
+
+fn parse_term(
+    key: &str,
+    contains: bool,
+    value: String,
+) -> std::result::Result<DeviceSelector, ParseSelectorError> {
+    let invalid = |what: &str| ParseSelectorError(format!("invalid {what} '{value}'"));
+    let selector = match key.to_lowercase().as_str() {
+        "name" if contains => DeviceSelector::NameContains(value),
+        _ if contains => {
+            return Err(ParseSelectorError(format!(
+                "'~=' is only supported for name, not '{key}'"
+            )));
+        }
+        "serial" => DeviceSelector::Serial(value),
+        "name" => DeviceSelector::Name(value),
+        "type" => {
+            let device_type = [
+                DeviceType::Unknown,
+                DeviceType::Freewili,
+                DeviceType::Defcon2024Badge,
+                DeviceType::Defcon2025FwBadge,
+                DeviceType::Uf2,
+                DeviceType::Winky,
+            ]
+            .into_iter()
+            .find(|device_type| device_type_key(*device_type).eq_ignore_ascii_case(&value))
+            .ok_or_else(|| invalid("device type"))?;
+            DeviceSelector::DeviceType(device_type)
+        }
+        "unique_id" | "id" => {
+            DeviceSelector::UniqueId(value.parse().map_err(|_| invalid("unique ID"))?)
+        }
+        "port" => DeviceSelector::PortPath(value.parse().map_err(|_| invalid("port path"))?),
+        "vid:pid" => {
+            let (vid, pid) = value.split_once(':').ok_or_else(|| invalid("vid:pid"))?;
+            let hex = |id: &str| u16::from_str_radix(id, 16).map_err(|_| invalid("vid:pid"));
+            DeviceSelector::VidPid(hex(vid)?, hex(pid)?)
+        }
+        "ready" => {
+            let interface = Interface::ALL
+                .into_iter()
+                .find(|interface| interface.key().eq_ignore_ascii_case(&value))
+                .ok_or_else(|| invalid("interface"))?;
+            DeviceSelector::Ready(interface)
+        }
+        _ => return Err(ParseSelectorError(format!("unknown key '{key}'"))),
+    };
+    Ok(selector)
+}
+
+impl FreeWiliDevice {
+    /// Finds all connected devices matching `selector`.
+    ///
+    /// Devices that fail to read are skipped, as they are usually in the middle of
+    /// enumerating or disconnecting.
+    ///
+    /// ```no_run
+    /// use freewili_finder_rs::FreeWiliDevice;
+    ///
+    /// let selector = "serial=FW4037 or vid:pid=093c:2058".parse().unwrap();
+    /// for device in FreeWiliDevice::find(&selector)? {
+    ///     println!("{device}");
+    /// }
+    /// # Ok::<(), freewili_finder_rs::FreeWiliError>(())
+    /// ```
+    pub fn find(selector: &DeviceSelector) -> Result<Vec<FreeWiliDevice>> {
+        Ok(Self::find_all()?
+            .into_iter()
+            .filter(|device| {
+                device
+                    .snapshot()
+                    .is_ok_and(|snapshot| selector.matches(&snapshot))
+            })
+            .collect())
+    }
+
+    /// Waits until a device matching `selector` is connected.
+    ///
+    /// Returns `Ok(None)` if no device matched within `timeout`. Add
+    /// [`DeviceSelector::Ready`] to the selector to also wait for interfaces to come up,
+    /// for example the main serial port after a reset:
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use freewili_finder_rs::{DeviceSelector, FreeWiliDevice, Interface};
+    ///
+    /// let selector = DeviceSelector::Serial("FW4037".to_string())
+    ///     .and(DeviceSelector::Ready(Interface::Main));
+    /// match FreeWiliDevice::wait_for(&selector, Duration::from_secs(10))? {
+    ///     Some(device) => println!("{}", device.get_main_usb_device()?),
+    ///     None => println!("FW4037 didn't come back"),
+    /// }
+    /// # Ok::<(), freewili_finder_rs::FreeWiliError>(())
+    /// ```
+    pub fn wait_for(
+        selector: &DeviceSelector,
+        timeout: Duration,
+    ) -> Result<Option<FreeWiliDevice>> {
+        let deadline = Instant::now() + timeout;
+        // Subscribed before the first scan so changes in between aren't missed
+        #[cfg(target_os = "linux")]
+        let monitor = crate::uevent::UeventMonitor::open().ok();
+
+        loop {
+            if let Some(device) = Self::find(selector)?.into_iter().next() {
+                return Ok(Some(device));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            #[cfg(target_os = "linux")]
+            if let Some(monitor) = &monitor {
+                // Any outcome is followed by a rescan, errors included
+                let _ = monitor.wait((deadline - now).min(WAIT_RESCAN_INTERVAL));
+                continue;
+            }
+            std::thread::sleep((deadline - now).min(WAIT_POLL_INTERVAL));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UsbDeviceType;
+
+    fn usb_device(kind: UsbDeviceType, port_chain: &[u32], port: Option<&str>) -> USBDevice {
+        USBDevice {
+            kind,
+            kind_name: format!("{kind:?}"),
+            vid: 0,
+            pid: 0,
+            name: String::new(),
+            serial: String::new(),
+            location: port_chain.last().copied().unwrap_or_default(),
+            port_chain: port_chain.to_vec(),
+            port: port.map(str::to_string),
+            path: None,
+        }
+    }
+
+    fn snapshot() -> DeviceSnapshot {
+        let mut hub = usb_device(UsbDeviceType::Hub, &[3, 4], None);
+        (hub.vid, hub.pid) = (0x0424, 0x2513);
+        // A serial port that doesn't exist on the test host
+        let main = usb_device(
+            UsbDeviceType::SerialMain,
+            &[3, 4, 1],
+            Some("/nonexistent/ttyACM0"),
+        );
+        DeviceSnapshot {
+            unique_id: 259,
+            device_type: DeviceType::Freewili,
+            device_type_name: "Free-WiLi".to_string(),
+            name: "Free-WiLi".to_string(),
+            serial: "FW4037".to_string(),
+            standalone: false,
+            usb_devices: vec![main.clone(), hub.clone()],
+            main: Some(main),
+            display: None,
+            fpga: None,
+            hub: Some(hub),
+        }
+    }
+
+    #[test]
+    fn test_selector_matches() {
+        let device = snapshot();
+
+        assert!(DeviceSelector::any().matches(&device));
+        assert!(DeviceSelector::Serial("fw4037".to_string()).matches(&device));
+        assert!(!DeviceSelector::Serial("FW4038".to_string()).matches(&device));
+        assert!(DeviceSelector::DeviceType(DeviceType::Freewili).matches(&device));
+        assert!(!DeviceSelector::DeviceType(DeviceType::Winky).matches(&device));
+        assert!(DeviceSelector::UniqueId(259).matches(&device));
+        // Not in sysfs, so the bus is unknown
+        assert!(!DeviceSelector::PortPath(PortPath::new(1, [3, 4])).matches(&device));
+        assert!(DeviceSelector::VidPid(0x0424, 0x2513).matches(&device));
+        assert!(!DeviceSelector::VidPid(0x093c, 0x2055).matches(&device));
+        assert!(DeviceSelector::Name("free-wili".to_string()).matches(&device));
+        assert!(DeviceSelector::NameContains("WiLi".to_string()).matches(&device));
+        assert!(!DeviceSelector::NameContains("badge".to_string()).matches(&device));
+        assert!(!DeviceSelector::Or(Vec::new()).matches(&device));
+
+        assert!(DeviceSelector::Ready(Interface::Hub).matches(&device));
+        assert!(!DeviceSelector::Ready(Interface::Main).matches(&device));
+        assert!(!DeviceSelector::Ready(Interface::Display).matches(&device));
+
+        let selector = DeviceSelector::Serial("FW4037".to_string())
+            .and(DeviceSelector::UniqueId(259))
+            .and(DeviceSelector::Ready(Interface::Hub));
+        assert_eq!(
+            selector,
+            DeviceSelector::And(vec![
+                DeviceSelector::Serial("FW4037".to_string()),
+                DeviceSelector::UniqueId(259),
+                DeviceSelector::Ready(Interface::Hub),
+            ])
+        );
+        assert!(selector.matches(&device));
+        assert!(
+            !selector
+                .and(DeviceSelector::Ready(Interface::Main))
+                .matches(&device)
+        );
+    }
+
+    #[test]
+    fn test_selector_parse() {
+        let parse = |query: &str| query.parse::<DeviceSelector>();
+
+        assert_eq!(
+            parse("serial=FW1234"),
+            Ok(DeviceSelector::Serial("FW1234".to_string()))
+        );
+        assert_eq!(
+            parse("type=defcon2025fwbadge"),
+            Ok(DeviceSelector::DeviceType(DeviceType::Defcon2025FwBadge))
+        );
+        assert_eq!(
+            parse("port=1-3.2"),
+            Ok(DeviceSelector::PortPath(PortPath::new(1, [3, 2])))
+        );
+        assert_eq!(
+            parse("port=1"),
+            Ok(DeviceSelector::PortPath(PortPath::root_hub(1)))
+        );
+        assert_eq!(
+            parse("vid:pid=093c:2054"),
+            Ok(DeviceSelector::VidPid(0x093c, 0x2054))
+        );
+        assert_eq!(
+            parse("name~=Wili"),
+            Ok(DeviceSelector::NameContains("Wili".to_string()))
+        );
+        assert_eq!(
+            parse(r#"name="Free \"WiLi\"""#),
+            Ok(DeviceSelector::Name("Free \"WiLi\"".to_string()))
+        );
+        assert_eq!(parse("id=259"), Ok(DeviceSelector::UniqueId(259)));
+        assert_eq!(
+            parse("ready=main"),
+            Ok(DeviceSelector::Ready(Interface::Main))
+        );
+
+        let serial = || DeviceSelector::Serial("FW1".to_string());
+        let winky = || DeviceSelector::DeviceType(DeviceType::Winky);
+        let main = || DeviceSelector::Ready(Interface::Main);
+        assert_eq!(
+            parse("serial=FW1 or type=winky and ready=main"),
+            Ok(serial().or(winky().and(main())))
+        );
+        assert_eq!(
+            parse("(serial=FW1 || type=winky) && ready=main"),
+            Ok(serial().or(winky()).and(main()))
+        );
+        assert_eq!(
+            parse("serial=FW1,type=winky|ready=main"),
+            Ok(serial().and(winky()).or(main()))
+        );
+
+        for invalid in [
+            "",
+            "serial",
+            "color=red",
+            "type=toaster",
+            "port=1-x",
+            "vid:pid=093c",
+            "serial~=FW",
+            "serial=FW1 and",
+            "(serial=FW1",
+            "serial=FW1)",
+            "name=\"unterminated",
+            "ready=gpu",
+        ] {
+            assert!(parse(invalid).is_err(), "{invalid:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_selector_display_round_trip() {
+        for query in [
+            "serial=FW1234",
+            "type=defcon2025fwbadge",
+            "unique_id=259",
+            "port=1-3.2",
+            "vid:pid=093c:2054",
+            "name~=\"Free WiLi\"",
+            "serial=FW1 or (type=winky and ready=main)",
+            "(serial=FW1 or type=winky) and ready=main",
+        ] {
+            let selector: DeviceSelector = query.parse().unwrap();
+            assert_eq!(selector.to_string(), query);
+            assert_eq!(selector.to_string().parse(), Ok(selector));
+        }
+    }
+
+    #[test]
+    fn test_wait_for_timeout() -> Result<()> {
+        let selector = DeviceSelector::Serial("no such serial".to_string());
+        let started = Instant::now();
+        let device = FreeWiliDevice::wait_for(&selector, Duration::from_millis(300))?;
+        assert!(device.is_none());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+//! Opening the serial ports of a FreeWili's CPUs with the `serialport` crate.
+//!
+//! ```no_run
+//! use std::io::Write;
+//! use freewili_finder_rs::FreeWiliDevice;
+//! use freewili_finder_rs::serial::SerialSettings;
+//!
+//! for device in FreeWiliDevice::find_all()? {
+//!     let mut main = device.open_main_serial(&SerialSettings::default())?;
+//!     main.write_all(b"\r\n")?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::{FreeWiliDevice, FreeWiliError, Interface, USBDevice};
+
+pub use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+/// Settings a serial port is opened with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialSettings {
+    /// Baud rate, ignored by the CPUs' USB CDC ports but used by the FTDI
+    pub baud_rate: u32,
+    /// How long reads and writes wait before failing with `TimedOut`
+    pub timeout: Duration,
+    /// Number of bits per character
+    pub data_bits: DataBits,
+    /// Parity checking mode
+    pub parity: Parity,
+    /// Number of stop bits
+    pub stop_bits: StopBits,
+    /// Flow control mode
+    pub flow_control: FlowControl,
+}
+
+impl SerialSettings {
+    /// 8N1 without flow control at `baud_rate`, with the default timeout.
+    pub fn new(baud_rate: u32) -> Self {
+        SerialSettings {
+            baud_rate,
+            ..Default::default()
+        }
+    }
+
+    /// Sets how long reads and writes wait.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Default for SerialSettings {
+    /// 115200 baud 8N1 without flow control and a one second timeout.
+    fn default() -> Self {
+        SerialSettings {
+            baud_rate: 115_200,
+            timeout: Duration::from_secs(1),
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+/// Errors returned when opening a FreeWili serial port.
+#[derive(Error, Debug)]
+pub enum SerialError {
+    /// Reading the device failed, for example because it was unplugged
+    #[error(transparent)]
+    Device(#[from] FreeWiliError),
+    /// The device has no USB device for the interface
+    #[error("FreeWili has no {} USB device", interface_name(*.0))]
+    MissingInterface(Interface),
+    /// The interface's USB device has no serial port, for example while in the bootloader
+    #[error("FreeWili {} USB device has no serial port", interface_name(*.0))]
+    NoPort(Interface),
+    /// The serial port exists but couldn't be opened
+    #[error("Failed to open FreeWili {} serial port {port}: {source}", interface_name(*.interface))]
+    Open {
+        /// Interface whose port failed to open
+        interface: Interface,
+        /// Path of the serial port
+        port: String,
+        /// Error returned by `serialport`
+        #[source]
+        source: serialport::Error,
+    },
+}
+
+fn interface_name(interface: Interface) -> &'static str {
+    match interface {
+        Interface::Main => "main CPU",
+        Interface::Display => "display CPU",
+        Interface::Fpga => "FPGA",
+        Interface::Hub => "hub",
+    }
+}
+
+impl FreeWiliDevice {
+    /// Opens the main CPU's serial port, found with
+    /// [`get_main_usb_device`](Self::get_main_usb_device).
+    pub fn open_main_serial(
+        &self,
+        settings: &SerialSettings,
+    ) -> Result<Box<dyn SerialPort>, SerialError> {
+        open(Interface::Main, self.get_main_usb_device(), settings)
+    }
+
+    /// Opens the display CPU's serial port, found with
+    /// [`get_display_usb_device`](Self::get_display_usb_device).
+    pub fn open_display_serial(
+        &self,
+        settings: &SerialSettings,
+    ) -> Result<Box<dyn SerialPort>, SerialError> {
+        open(Interface::Display, self.get_display_usb_device(), settings)
+    }
+}
+
+fn open(
+    interface: Interface,
+    usb_device: crate::Result<USBDevice>,
+    settings: &SerialSettings,
+) -> Result<Box<dyn SerialPort>, SerialError> {
+    open_port(
+        interface,
+        &interface_usb_device(interface, usb_device)?,
+        settings,
+    )
+}
+
+/// Result of a `get_*_usb_device` getter, with a missing interface reported as such.
+pub(crate) fn interface_usb_device(
+    interface: Interface,
+    usb_device: crate::Result<USBDevice>,
+) -> Result<USBDevice, SerialError> {
+    match usb_device {
+        Err(FreeWiliError::NoMoreDevices) => Err(SerialError::MissingInterface(interface)),
+        result => Ok(result?),
+    }
+}
+
+/// Opens the serial port of `interface`'s USB device.
+pub(crate) fn open_port(
+    interface: Interface,
+    usb_device: &USBDevice,
+    settings: &SerialSettings,
+) -> Result<Box<dyn SerialPort>, SerialError> {
+    let port = usb_device
+        .port
+        .clone()
+        .ok_or(SerialError::NoPort(interface))?;
+    serialport::new(&port, settings.baud_rate)
+        .timeout(settings.timeout)
+        .data_bits(settings.data_bits)
+        .parity(settings.parity)
+        .stop_bits(settings.stop_bits)
+        .flow_control(settings.flow_control)
+        .open()
+        .map_err(|source| SerialError::Open {
+            interface,
+            port,
+            source,
+        })
+}
+
+/// Pseudo-terminal standing in for a CPU's serial port: the master end, the slave end
+/// and the slave's path.
+#[cfg(all(test, target_os = "linux"))]
+pub(crate) fn open_pty() -> (std::fs::File, std::fs::File, std::path::PathBuf) {
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    let (master, slave) = unsafe {
+        let (mut master, mut slave) = (0, 0);
+        let res = libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        );
+        assert_eq!(res, 0, "openpty failed");
+        (
+            std::fs::File::from_raw_fd(master),
+            std::fs::File::from_raw_fd(slave),
+        )
+    };
+    let slave_path = std::fs::read_link(format!("/proc/self/fd/{}", slave.as_raw_fd())).unwrap();
+    (master, slave, slave_path)
+}
+
+/// Waits up to `timeout` for the pseudo-terminal `slave` to be set to `baud_rate`.
+#[cfg(all(test, target_os = "linux"))]
+pub(crate) fn wait_for_baud_rate(slave: &std::fs::File, baud_rate: u32, timeout: Duration) -> bool {
+    use std::os::fd::AsRawFd;
+
+    let deadline = std::time::Instant::now() + timeout;
+    while std::time::Instant::now() < deadline {
+        // serialport sets the speed with termios2, which cfgetospeed doesn't report
+        let speed = unsafe {
+            let mut termios: libc::termios2 = std::mem::zeroed();
+            assert_eq!(
+                libc::ioctl(slave.as_raw_fd(), libc::TCGETS2, &mut termios),
+                0
+            );
+            termios.c_ospeed
+        };
+        if speed == baud_rate {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+    use crate::testing::FakeFreeWili;
+
+    #[test]
+    fn test_errors() {
+        let settings = SerialSettings::default();
+        let device = FakeFreeWili::new("FW4037")
+            .with_main("/nonexistent/ttyACM0")
+            .device();
+
+        let error = device.open_display_serial(&settings).unwrap_err();
+        assert!(matches!(
+            error,
+            SerialError::MissingInterface(Interface::Display)
+        ));
+        assert_eq!(error.to_string(), "FreeWili has no display CPU USB device");
+
+        let error = device.open_main_serial(&settings).unwrap_err();
+        assert!(matches!(
+            error,
+            SerialError::Open { interface: Interface::Main, ref port, .. } if port == "/nonexistent/ttyACM0"
+        ));
+        assert!(
+            error
+                .to_string()
+                .starts_with("Failed to open FreeWili main CPU serial port /nonexistent/ttyACM0: ")
+        );
+
+        let bootloader = FakeFreeWili::new("FW4037")
+            .with_main_uf2("/media/RPI-RP2")
+            .device();
+        assert!(matches!(
+            bootloader.open_main_serial(&settings),
+            Err(SerialError::NoPort(Interface::Main))
+        ));
+
+        assert!(matches!(
+            FreeWiliDevice::default().open_main_serial(&settings),
+            Err(SerialError::Device(FreeWiliError::InvalidDevice))
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_open_pty() {
+        use std::io::{Read, Write};
+
+        let (mut master, slave, slave_path) = open_pty();
+
+        let device = FakeFreeWili::standalone(DeviceType::Winky, "WK0042")
+            .with_main(slave_path.to_string_lossy())
+            .device();
+        let settings = SerialSettings::new(1_000_000).with_timeout(Duration::from_millis(500));
+        let mut port = device.open_main_serial(&settings).unwrap();
+        assert_eq!(port.baud_rate().unwrap(), 1_000_000);
+        port.write_all(b"ping").unwrap();
+
+        let mut buffer = [0; 4];
+        master.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"ping");
+        drop(slave);
+    }
+}
@@ -0,0 +1,89 @@
+//! Stable names of serial ports from the links udev creates in `/dev/serial`.
+
+use crate::USBDevice;
+
+/// Link to the serial port of `usb_device` in the `serial/<links>` directory next to its
+/// port, or `None` if it has no port or no link points at it.
+///
+/// The links are looked up relative to the directory of the port, so a port inside a
+/// fake root resolves to links in that root. Always `None` outside of Linux.
+pub(crate) fn find(usb_device: &USBDevice, links: &str) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        let port = std::path::Path::new(usb_device.port.as_ref()?);
+        find_link(&port.parent()?.join("serial").join(links), port)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (usb_device, links);
+        None
+    }
+}
+
+/// First link in `dir`, by name, that points at `port`.
+#[cfg(target_os = "linux")]
+fn find_link(dir: &std::path::Path, port: &std::path::Path) -> Option<String> {
+    let port = std::fs::canonicalize(port).ok()?;
+    let mut links: Vec<_> = std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|link| std::fs::canonicalize(link).is_ok_and(|target| target == port))
+        .collect();
+    links.sort();
+    links
+        .into_iter()
+        .next()
+        .map(|link| link.to_string_lossy().into_owned())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let dev =
+            std::env::temp_dir().join(format!("freewili-serial-links-{}", std::process::id()));
+        let by_id = dev.join("serial/by-id");
+        let by_path = dev.join("serial/by-path");
+        std::fs::create_dir_all(&by_id).unwrap();
+        std::fs::create_dir_all(&by_path).unwrap();
+        std::fs::write(dev.join("ttyACM0"), "").unwrap();
+        std::fs::write(dev.join("ttyACM1"), "").unwrap();
+        let link = |dir: &std::path::Path, name: &str, target: &str| {
+            std::os::unix::fs::symlink(target, dir.join(name)).unwrap()
+        };
+        link(&by_id, "usb-FreeWili_DisplayCPU_v55-if00", "../../ttyACM1");
+        link(&by_id, "usb-FreeWili_MainCPU_v73-if00", "../../ttyACM0");
+        link(&by_path, "pci-0000:00:14.0-usb-0:4.1:1.0", "../../ttyACM0");
+
+        let main = USBDevice {
+            port: Some(dev.join("ttyACM0").to_string_lossy().into_owned()),
+            ..crate::usbdef::usb_device(0x093c, 0x2054, vec![3, 4, 1])
+        };
+        let display = USBDevice {
+            port: Some(dev.join("ttyACM1").to_string_lossy().into_owned()),
+            ..crate::usbdef::usb_device(0x093c, 0x2055, vec![3, 4, 2])
+        };
+        let mut missing = crate::usbdef::usb_device(0x093c, 0x2055, vec![3, 4, 2]);
+        missing.port = Some(dev.join("ttyACM9").to_string_lossy().into_owned());
+
+        let path = |path: std::path::PathBuf| Some(path.to_string_lossy().into_owned());
+        let main_by_id = path(by_id.join("usb-FreeWili_MainCPU_v73-if00"));
+        assert_eq!(main.port_by_id(), main_by_id);
+        assert_eq!(
+            main.port_by_path(),
+            path(by_path.join("pci-0000:00:14.0-usb-0:4.1:1.0"))
+        );
+        assert_eq!(main.stable_port(), main_by_id);
+        assert_eq!(
+            display.port_by_id(),
+            path(by_id.join("usb-FreeWili_DisplayCPU_v55-if00"))
+        );
+        assert_eq!(display.port_by_path(), None);
+        assert_eq!(missing.port_by_id(), None);
+        assert_eq!(missing.stable_port(), missing.port);
+        std::fs::remove_dir_all(&dev).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+
+use crate::{DeviceType, USBDevice};
+
+/// Plain-data copy of a [`FreeWiliDevice`](crate::FreeWiliDevice) taken at a single point in time.
+///
+/// Created by [`FreeWiliDevice::snapshot`](crate::FreeWiliDevice::snapshot) or
+/// [`FreeWiliDevice::find_all_snapshots`](crate::FreeWiliDevice::find_all_snapshots).
+/// Every field is read in one pass while the C handle is locked, and the snapshot doesn't
+/// reference the handle afterwards, so it stays readable after the device is unplugged.
+///
+/// With the `serde` feature this serializes with the field names below, which are stable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceSnapshot {
+    /// Location based unique ID of the device
+    pub unique_id: u64,
+    /// The type of FreeWili device
+    pub device_type: DeviceType,
+    /// Human-readable name of the device type
+    pub device_type_name: String,
+    /// Human-readable device name
+    pub name: String,
+    /// Device serial number
+    pub serial: String,
+    /// Whether the device is standalone (not behind a FreeWili hub)
+    pub standalone: bool,
+    /// Every USB device belonging to this FreeWili
+    pub usb_devices: Vec<USBDevice>,
+    /// Main CPU USB device, if present
+    pub main: Option<USBDevice>,
+    /// Display CPU USB device, if present
+    pub display: Option<USBDevice>,
+    /// FPGA (FTDI) USB device, if present
+    pub fpga: Option<USBDevice>,
+    /// USB hub the device is built around, if present
+    pub hub: Option<USBDevice>,
+}
+
+impl fmt::Display for DeviceSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.serial)
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{DeviceSelector, DeviceSnapshot, FreeWiliDevice, Result, USBDevice, usbdef};
+
+/// Where FreeWili devices come from.
+///
+/// [`SystemDevices`] finds the devices connected to this machine, and
+/// [`testing::FakeSource`](crate::testing::FakeSource) serves in-memory fakes. Code that is
+/// generic over the source can be unit-tested without boards:
+///
+/// ```
+/// use freewili_finder_rs::{DeviceSource, Result};
+///
+/// fn main_ports(source: &impl DeviceSource) -> Result<Vec<String>> {
+///     let mut ports = Vec::new();
+///     for device in source.find_all()? {
+///         ports.extend(device.get_main_usb_device().ok().and_then(|main| main.port));
+///     }
+///     Ok(ports)
+/// }
+/// ```
+pub trait DeviceSource {
+    /// Finds all FreeWili devices, like [`FreeWiliDevice::find_all`].
+    fn find_all(&self) -> Result<Vec<FreeWiliDevice>>;
+
+    /// Reads the USB devices of a device found by this source, like
+    /// [`FreeWiliDevice::get_usb_devices`].
+    fn get_usb_devices(&self, device: &FreeWiliDevice) -> Result<Vec<USBDevice>>;
+
+    /// Reads a device found by this source, like [`FreeWiliDevice::snapshot`].
+    ///
+    /// The USB devices come from [`get_usb_devices`](Self::get_usb_devices), and the main,
+    /// display, FPGA and hub interfaces are resolved from them, so a device this source
+    /// reports as gone or failing fails here too.
+    fn snapshot(&self, device: &FreeWiliDevice) -> Result<DeviceSnapshot> {
+        let resolved = usbdef::from_usb_devices(self.get_usb_devices(device)?);
+        Ok(DeviceSnapshot {
+            unique_id: device.unique_id()?,
+            device_type: device.device_type()?,
+            device_type_name: device.device_type_name()?,
+            name: device.name()?,
+            serial: device.serial()?,
+            standalone: device.standalone()?,
+            ..resolved
+        })
+    }
+
+    /// Finds all devices matching `selector`, like [`FreeWiliDevice::find`].
+    ///
+    /// Devices that fail to [`snapshot`](Self::snapshot) are skipped.
+    fn find(&self, selector: &DeviceSelector) -> Result<Vec<FreeWiliDevice>> {
+        Ok(self
+            .find_all()?
+            .into_iter()
+            .filter(|device| {
+                self.snapshot(device)
+                    .is_ok_and(|snapshot| selector.matches(&snapshot))
+            })
+            .collect())
+    }
+}
+
+/// The devices connected to this machine, found by the enabled backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemDevices;
+
+impl DeviceSource for SystemDevices {
+    fn find_all(&self) -> Result<Vec<FreeWiliDevice>> {
+        FreeWiliDevice::find_all()
+    }
+
+    fn get_usb_devices(&self, device: &FreeWiliDevice) -> Result<Vec<USBDevice>> {
+        device.get_usb_devices()
+    }
+
+    fn snapshot(&self, device: &FreeWiliDevice) -> Result<DeviceSnapshot> {
+        device.snapshot()
+    }
+
+    fn find(&self, selector: &DeviceSelector) -> Result<Vec<FreeWiliDevice>> {
+        FreeWiliDevice::find(selector)
+    }
+}
+
+#[cfg(all(feature = "native-linux", target_os = "linux"))]
+impl DeviceSource for crate::sysfs::Enumerator {
+    fn find_all(&self) -> Result<Vec<FreeWiliDevice>> {
+        crate::sysfs::Enumerator::find_all(self)
+    }
+
+    fn get_usb_devices(&self, device: &FreeWiliDevice) -> Result<Vec<USBDevice>> {
+        device.get_usb_devices()
+    }
+}
+
+impl<S: DeviceSource + ?Sized> DeviceSource for &S {
+    fn find_all(&self) -> Result<Vec<FreeWiliDevice>> {
+        (**self).find_all()
+    }
+
+    fn get_usb_devices(&self, device: &FreeWiliDevice) -> Result<Vec<USBDevice>> {
+        (**self).get_usb_devices(device)
+    }
+
+    fn snapshot(&self, device: &FreeWiliDevice) -> Result<DeviceSnapshot> {
+        (**self).snapshot(device)
+    }
+
+    fn find(&self, selector: &DeviceSelector) -> Result<Vec<FreeWiliDevice>> {
+        (**self).find(selector)
+    }
+}
@@ -0,0 +1,503 @@
+//! Block devices, filesystems and mount points of mass storage devices (Linux only).
+//!
+//! The UF2 bootloaders show up as [`MassStorage`](crate::UsbDeviceType::MassStorage)
+//! devices. [`StorageInfo`] follows such a device to its disk and partitions in sysfs, and
+//! finds where they are mounted in `/proc/self/mountinfo` by device number, so it works
+//! whatever an automounter names the mount point:
+//!
+//! ```no_run
+//! use freewili_finder_rs::{FreeWiliDevice, UsbDeviceType};
+//!
+//! for device in FreeWiliDevice::find_all()? {
+//!     for usb_device in device.get_usb_devices()? {
+//!         if usb_device.kind == UsbDeviceType::MassStorage {
+//!             let storage = usb_device.storage_info()?;
+//!             println!("{}: {:?}", storage.disk.dev_node.display(), storage.mount_point());
+//!         }
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`FreeWiliStorage`] works with the files on such a volume by paths relative to its root,
+//! mounting it first if nothing has:
+//!
+//! ```no_run
+//! use freewili_finder_rs::FreeWiliDevice;
+//!
+//! for device in FreeWiliDevice::find_all()? {
+//!     let storage = device.storage()?;
+//!     storage.mkdir("scripts")?;
+//!     storage.write("scripts/blink.wasm", std::fs::read("blink.wasm")?)?;
+//!     storage.eject()?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Like [`sysfs::Enumerator`](crate::sysfs::Enumerator), this reads below the directory in
+//! the `FREEWILI_SYSFS_ROOT` environment variable if it is set.
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use thiserror::Error;
+
+use crate::linux::{
+    class_devices, dev_name, find_port_path, partitions, read_attr, read_uevent, unescape_mount,
+};
+use crate::{USBDevice, UsbDeviceType};
+
+mod eject;
+mod files;
+pub use eject::{EjectError, EjectOptions};
+pub use files::{FreeWiliStorage, FreeWiliStorageError, StorageEntry};
+
+/// Size of the sectors sysfs reports block device sizes in, whatever the device uses.
+const SECTOR_SIZE: u64 = 512;
+
+/// Errors returned when resolving the block device of a mass storage device.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    /// The USB device isn't a mass storage device
+    #[error("USB device is not a mass storage device")]
+    NotMassStorage,
+    /// No block device belongs to the USB device, for example because the kernel
+    /// hasn't finished probing it yet
+    #[error("No block device found for the mass storage device at port chain {0:?}")]
+    NotFound(Vec<u32>),
+}
+
+/// Disk of a mass storage device and its partitions.
+///
+/// With the `serde` feature this serializes with the field names below.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StorageInfo {
+    /// The whole disk, like `sda`
+    pub disk: BlockDevice,
+    /// Partitions of the disk by name, like `sda1`
+    pub partitions: Vec<BlockDevice>,
+}
+
+/// A disk or partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockDevice {
+    /// Kernel name, like `sda1`
+    pub name: String,
+    /// Device node, like `/dev/sda1`
+    pub dev_node: PathBuf,
+    /// Major device number
+    pub major: u32,
+    /// Minor device number
+    pub minor: u32,
+    /// Partition number, `None` for a disk
+    pub partition: Option<u32>,
+    /// Size in bytes
+    pub size: u64,
+    /// Whether the device is read-only
+    pub read_only: bool,
+    /// Filesystem label, from `/dev/disk/by-label`
+    pub label: Option<String>,
+    /// Filesystem UUID, from `/dev/disk/by-uuid`
+    pub uuid: Option<String>,
+    /// Filesystem type while mounted, like `vfat`
+    pub fs_type: Option<String>,
+    /// Where the device is mounted, the first mount if there are several
+    pub mount_point: Option<PathBuf>,
+    /// Capacity and free space of the mounted filesystem
+    pub space: Option<FsSpace>,
+}
+
+/// Capacity and free space of a mounted filesystem, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsSpace {
+    /// Size of the filesystem
+    pub total: u64,
+    /// Free space, including space reserved for root
+    pub free: u64,
+    /// Free space available to unprivileged users
+    pub available: u64,
+}
+
+impl StorageInfo {
+    /// Resolves the block devices of a mass storage USB device.
+    ///
+    /// The disk is found by the device's port path, or else by looking up its
+    /// [`path`](USBDevice::path) in the mount table.
+    pub fn read(usb_device: &USBDevice) -> Result<Self, StorageError> {
+        Self::read_with_root(usb_device, crate::linux::root_from_env())
+    }
+
+    /// Like [`read`](Self::read), but reads `sys`, `dev` and `proc` below `root`. Device
+    /// nodes and mount points are reported below `root` too.
+    pub fn read_with_root(
+        usb_device: &USBDevice,
+        root: impl AsRef<Path>,
+    ) -> Result<Self, StorageError> {
+        let root = root.as_ref();
+        let mounts = read_mountinfo(&root.join("proc/self/mountinfo"));
+        let disk = find_disk(usb_device, root, &mounts)?;
+
+        let resolver = Resolver {
+            root,
+            mounts: &mounts,
+        };
+        Ok(StorageInfo {
+            disk: resolver.block_device(&disk),
+            partitions: partitions(&disk)
+                .iter()
+                .map(|partition| resolver.block_device(partition))
+                .collect(),
+        })
+    }
+
+    /// The disk and its partitions.
+    pub fn block_devices(&self) -> impl Iterator<Item = &BlockDevice> {
+        std::iter::once(&self.disk).chain(&self.partitions)
+    }
+
+    /// The first mounted disk or partition.
+    pub fn mounted(&self) -> Option<&BlockDevice> {
+        self.block_devices()
+            .find(|block_device| block_device.mount_point.is_some())
+    }
+
+    /// Where the disk or its first mounted partition is mounted.
+    pub fn mount_point(&self) -> Option<&Path> {
+        self.mounted()?.mount_point.as_deref()
+    }
+
+    /// Capacity of the disk in bytes.
+    pub fn capacity(&self) -> u64 {
+        self.disk.size
+    }
+
+    /// Space available to unprivileged users on the mounted filesystem.
+    pub fn free_space(&self) -> Option<u64> {
+        Some(self.mounted()?.space?.available)
+    }
+}
+
+impl USBDevice {
+    /// Resolves the block devices of a [`MassStorage`](UsbDeviceType::MassStorage) device,
+    /// see [`StorageInfo::read`].
+    pub fn storage_info(&self) -> Result<StorageInfo, StorageError> {
+        StorageInfo::read(self)
+    }
+}
+
+/// A line of `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mount {
+    major: u32,
+    minor: u32,
+    mount_point: String,
+    fs_type: String,
+}
+
+/// Reads a `mountinfo` file, skipping malformed lines.
+fn read_mountinfo(path: &Path) -> Vec<Mount> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(parse_mountinfo_line)
+        .collect()
+}
+
+/// Parses `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw`: the ID, parent
+/// ID, device number, root, mount point and options, optional fields up to `-`, then the
+/// filesystem type, source and superblock options.
+fn parse_mountinfo_line(line: &str) -> Option<Mount> {
+    let mut fields = line.split_whitespace();
+    let (major, minor) = parse_dev(fields.nth(2)?)?;
+    let mount_point = unescape_mount(fields.nth(1)?);
+    let fs_type = fields.skip_while(|field| *field != "-").nth(1)?;
+    Some(Mount {
+        major,
+        minor,
+        mount_point,
+        fs_type: unescape_mount(fs_type),
+    })
+}
+
+/// Parses a `major:minor` device number.
+fn parse_dev(dev: &str) -> Option<(u32, u32)> {
+    let (major, minor) = dev.trim().split_once(':')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Sysfs directory of the disk of a mass storage device.
+fn find_disk(
+    usb_device: &USBDevice,
+    root: &Path,
+    mounts: &[Mount],
+) -> Result<PathBuf, StorageError> {
+    if usb_device.kind != UsbDeviceType::MassStorage {
+        return Err(StorageError::NotMassStorage);
+    }
+    let block_devices = class_devices(&root.join("sys"), "block");
+    let by_port = find_port_path(usb_device, root).and_then(|port_path| {
+        let name = port_path.to_string();
+        block_devices
+            .iter()
+            .filter(|(usb_name, _)| *usb_name == name)
+            .map(|(_, path)| path)
+            .find(|path| {
+                read_uevent(&path.join("uevent"))
+                    .get("DEVTYPE")
+                    .map(String::as_str)
+                    == Some("disk")
+            })
+            .cloned()
+    });
+    by_port
+        .or_else(|| disk_by_mount_point(usb_device.path.as_deref()?, root, mounts, &block_devices))
+        .ok_or_else(|| StorageError::NotFound(usb_device.port_chain.clone()))
+}
+
+/// Disk of the block device mounted at `path`, which is below `root`.
+fn disk_by_mount_point(
+    path: &str,
+    root: &Path,
+    mounts: &[Mount],
+    block_devices: &[(String, PathBuf)],
+) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let mount_point = Path::new("/").join(path.strip_prefix(root).unwrap_or(path));
+    let mount = mounts
+        .iter()
+        .find(|mount| Path::new(&mount.mount_point) == mount_point)?;
+    let (_, block_device) = block_devices.iter().find(|(_, block_device)| {
+        parse_dev(&read_attr(&block_device.join("dev"))) == Some((mount.major, mount.minor))
+    })?;
+    // A partition's sysfs directory is inside its disk's
+    if block_device.join("partition").is_file() {
+        block_device.parent().map(Path::to_path_buf)
+    } else {
+        Some(block_device.clone())
+    }
+}
+
+/// Runs `command` quietly, returning whether it succeeded.
+fn run(command: &mut Command) -> bool {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Reads block devices below a root.
+struct Resolver<'a> {
+    root: &'a Path,
+    mounts: &'a [Mount],
+}
+
+impl Resolver<'_> {
+    fn block_device(&self, sys_path: &Path) -> BlockDevice {
+        let uevent = read_uevent(&sys_path.join("uevent"));
+        let (major, minor) = parse_dev(&read_attr(&sys_path.join("dev"))).unwrap_or_default();
+        let dev_node = self.root.join("dev").join(dev_name(sys_path, &uevent));
+        let mount = self
+            .mounts
+            .iter()
+            .find(|mount| (mount.major, mount.minor) == (major, minor));
+        let mount_point = mount.map(|mount| {
+            let mount_point = Path::new(&mount.mount_point);
+            self.root
+                .join(mount_point.strip_prefix("/").unwrap_or(mount_point))
+        });
+        BlockDevice {
+            name: sys_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            major,
+            minor,
+            partition: read_attr(&sys_path.join("partition")).trim().parse().ok(),
+            size: read_attr(&sys_path.join("size"))
+                .trim()
+                .parse::<u64>()
+                .unwrap_or_default()
+                * SECTOR_SIZE,
+            read_only: read_attr(&sys_path.join("ro")).trim() == "1",
+            label: self.disk_link("by-label", &dev_node),
+            uuid: self.disk_link("by-uuid", &dev_node),
+            fs_type: mount.map(|mount| mount.fs_type.clone()),
+            space: mount_point.as_deref().and_then(fs_space),
+            mount_point,
+            dev_node,
+        }
+    }
+
+    /// Name of the link in `/dev/disk/<dir>` that points at `dev_node`, decoded.
+    fn disk_link(&self, dir: &str, dev_node: &Path) -> Option<String> {
+        let dev_node = std::fs::canonicalize(dev_node).ok()?;
+        let mut names: Vec<String> = std::fs::read_dir(self.root.join("dev/disk").join(dir))
+            .ok()?
+            .flatten()
+            .filter(|entry| {
+                std::fs::canonicalize(entry.path()).is_ok_and(|target| target == dev_node)
+            })
+            .map(|entry| unescape_udev(&entry.file_name().to_string_lossy()))
+            .collect();
+        names.sort();
+        names.into_iter().next()
+    }
+}
+
+/// Decodes the `\x20` style escapes udev uses in `/dev/disk` link names.
+fn unescape_udev(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i..i + 4)
+            .filter(|escape| escape.starts_with(b"\\x"))
+            .and_then(|escape| std::str::from_utf8(&escape[2..]).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+        match hex {
+            Some(byte) => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            None => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// Capacity and free space of the filesystem mounted at `mount_point`.
+fn fs_space(mount_point: &Path) -> Option<FsSpace> {
+    let path = CString::new(mount_point.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let fragment = stat.f_frsize as u64;
+    Some(FsSpace {
+        total: stat.f_blocks as u64 * fragment,
+        free: stat.f_bfree as u64 * fragment,
+        available: stat.f_bavail as u64 * fragment,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linux::fixture_root;
+    use crate::usbdef;
+
+    fn mass_storage(port_chain: Vec<u32>, path: Option<String>) -> USBDevice {
+        USBDevice {
+            path,
+            ..usbdef::usb_device(usbdef::VID_RPI, usbdef::PID_RPI_2040_UF2, port_chain)
+        }
+    }
+
+    #[test]
+    fn test_fixture() {
+        let Some(root) = fixture_root() else {
+            return;
+        };
+        let storage = StorageInfo::read_with_root(&mass_storage(vec![4, 4], None), &root).unwrap();
+
+        let disk = &storage.disk;
+        assert_eq!(disk.name, "sda");
+        assert_eq!(disk.dev_node, root.join("dev/sda"));
+        assert_eq!((disk.major, disk.minor), (8, 0));
+        assert_eq!(disk.partition, None);
+        assert_eq!(disk.size, 128 * 1024 * 1024);
+        assert_eq!(disk.mount_point, None);
+        assert_eq!(disk.label, None);
+        assert_eq!(storage.capacity(), disk.size);
+
+        assert_eq!(storage.partitions.len(), 1);
+        let partition = &storage.partitions[0];
+        assert_eq!(partition.name, "sda1");
+        assert_eq!(partition.dev_node, root.join("dev/sda1"));
+        assert_eq!((partition.major, partition.minor), (8, 1));
+        assert_eq!(partition.partition, Some(1));
+        assert_eq!(partition.size, 262143 * 512);
+        assert!(!partition.read_only);
+        assert_eq!(partition.label.as_deref(), Some("RPI-RP2"));
+        assert_eq!(partition.uuid.as_deref(), Some("0042-0042"));
+        assert_eq!(partition.fs_type.as_deref(), Some("vfat"));
+        let mount_point = root.join("media/fw/RPI-RP2");
+        assert_eq!(storage.mount_point(), Some(mount_point.as_path()));
+        // The fixture isn't a real mount, statvfs reports the filesystem it lives on
+        let space = partition.space.unwrap();
+        assert!(space.total >= space.free && space.free >= space.available);
+        assert_eq!(storage.free_space(), Some(space.available));
+
+        // Found through the mount table when the port chain doesn't lead to it
+        let by_path = StorageInfo::read_with_root(
+            &mass_storage(vec![9, 9], Some(mount_point.display().to_string())),
+            &root,
+        )
+        .unwrap();
+        assert_eq!(by_path, storage);
+    }
+
+    #[test]
+    fn test_errors() {
+        let Some(root) = fixture_root() else {
+            return;
+        };
+        let missing = mass_storage(vec![4, 9], Some("/media/elsewhere".to_string()));
+        assert_eq!(
+            StorageInfo::read_with_root(&missing, &root),
+            Err(StorageError::NotFound(vec![4, 9]))
+        );
+        // Port chains don't start with the bus, 3-4.4 is at [4, 4]
+        assert_eq!(
+            StorageInfo::read_with_root(&mass_storage(vec![3, 4, 4], None), &root),
+            Err(StorageError::NotFound(vec![3, 4, 4]))
+        );
+        let serial = usbdef::usb_device(usbdef::VID_ICS, usbdef::PID_MAIN_CDC, vec![3, 4, 1]);
+        assert_eq!(
+            StorageInfo::read_with_root(&serial, &root),
+            Err(StorageError::NotMassStorage)
+        );
+    }
+
+    #[test]
+    fn test_parse_mountinfo_line() {
+        // Automounters name mount points however they like, and may mount by UUID
+        assert_eq!(
+            parse_mountinfo_line(
+                "612 29 8:17 / /run/media/user/RPI\\040RP2 rw,nosuid shared:310 master:7 - vfat /dev/disk/by-uuid/0042-0042 rw"
+            ),
+            Some(Mount {
+                major: 8,
+                minor: 17,
+                mount_point: "/run/media/user/RPI RP2".to_string(),
+                fs_type: "vfat".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_mountinfo_line("22 1 259:2 / / rw - ext4 /dev/nvme0n1p2 rw")
+                .map(|mount| mount.fs_type),
+            Some("ext4".to_string())
+        );
+        assert_eq!(parse_mountinfo_line("22 1 259:2 / / rw"), None);
+        assert_eq!(parse_mountinfo_line("22 1 bogus / / rw - ext4 x rw"), None);
+    }
+
+    #[test]
+    fn test_unescape_udev() {
+        assert_eq!(unescape_udev("RPI-RP2"), "RPI-RP2");
+        assert_eq!(unescape_udev("My\\x20Disk"), "My Disk");
+        assert_eq!(unescape_udev("trailing\\x2"), "trailing\\x2");
+    }
+}
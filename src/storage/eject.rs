@@ -0,0 +1,369 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use thiserror::Error;
+
+use super::{Mount, StorageError, find_disk, parse_dev, partitions, read_mountinfo, run};
+use crate::USBDevice;
+use crate::linux::{dev_name, read_attr, read_uevent};
+
+/// `SG_IO` ioctl of the SCSI generic driver.
+const SG_IO: libc::c_ulong = 0x2285;
+/// `sg_io_hdr::dxfer_direction` for commands without data.
+const SG_DXFER_NONE: libc::c_int = -1;
+/// SCSI START STOP UNIT command with LOEJ set and START cleared: stop and eject.
+const START_STOP_UNIT_EJECT: [u8; 6] = [0x1B, 0, 0, 0, 0x02, 0];
+/// How long the device gets to answer START STOP UNIT, in milliseconds.
+const SCSI_TIMEOUT_MS: libc::c_uint = 10_000;
+
+/// What [`USBDevice::eject_with`] does after unmounting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EjectOptions {
+    /// Send the disk a SCSI START STOP UNIT command to stop and eject it
+    pub stop: bool,
+    /// Remove the disk through sysfs, so the host lets go of it until it is plugged in again
+    pub remove: bool,
+}
+
+impl EjectOptions {
+    /// Also stops the disk with a SCSI command.
+    pub fn with_stop(mut self) -> Self {
+        self.stop = true;
+        self
+    }
+
+    /// Also removes the disk from the host.
+    pub fn with_remove(mut self) -> Self {
+        self.remove = true;
+        self
+    }
+}
+
+/// Errors returned when ejecting a mass storage device.
+#[derive(Error, Debug)]
+pub enum EjectError {
+    /// The block device couldn't be found
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    /// A filesystem couldn't be unmounted, for example because files on it are open
+    #[error("Failed to unmount {}: {source}", .mount_point.display())]
+    Unmount {
+        /// Mount point that is still mounted
+        mount_point: PathBuf,
+        /// Error that occurred
+        #[source]
+        source: io::Error,
+    },
+    /// The SCSI stop command failed
+    #[error("Failed to stop {}: {source}", .dev_node.display())]
+    Stop {
+        /// Device node of the disk
+        dev_node: PathBuf,
+        /// Error that occurred
+        #[source]
+        source: io::Error,
+    },
+    /// Removing the disk through sysfs failed
+    #[error("Failed to remove the disk through {}: {source}", .path.display())]
+    Remove {
+        /// Sysfs attribute that was written
+        path: PathBuf,
+        /// Error that occurred
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl USBDevice {
+    /// Flushes and unmounts every mounted filesystem of a
+    /// [`MassStorage`](crate::UsbDeviceType::MassStorage) device, so it can be unplugged
+    /// without corrupting it. Same as `eject_with(&EjectOptions::default())`.
+    pub fn eject(&self) -> Result<(), EjectError> {
+        self.eject_with(&EjectOptions::default())
+    }
+
+    /// Like [`eject`](Self::eject), then stops and removes the disk as `options` ask.
+    ///
+    /// Unmounting needs `CAP_SYS_ADMIN`. Without it, filesystems are unmounted with
+    /// `udisksctl`, which handles what desktop automounters mounted, or else `umount`.
+    /// Stopping needs write access to the disk's device node and removing needs root.
+    pub fn eject_with(&self, options: &EjectOptions) -> Result<(), EjectError> {
+        eject_with_root(self, options, &crate::linux::root_from_env())
+    }
+}
+
+/// Ejects the mass storage device, reading `sys`, `dev` and `proc` below `root`.
+pub(super) fn eject_with_root(
+    usb_device: &USBDevice,
+    options: &EjectOptions,
+    root: &Path,
+) -> Result<(), EjectError> {
+    let mounts = read_mountinfo(&root.join("proc/self/mountinfo"));
+    let disk = find_disk(usb_device, root, &mounts)?;
+    let mut block_devices = vec![disk.clone()];
+    block_devices.extend(partitions(&disk));
+    let devs: Vec<(u32, u32)> = block_devices
+        .iter()
+        .filter_map(|block_device| parse_dev(&read_attr(&block_device.join("dev"))))
+        .collect();
+
+    // Unmount the newest mounts first, they may be mounted inside the older ones
+    let mounted: Vec<&Mount> = mounts
+        .iter()
+        .rev()
+        .filter(|mount| devs.contains(&(mount.major, mount.minor)))
+        .collect();
+    for mount in &mounted {
+        let mount_point = Path::new(&mount.mount_point);
+        let mount_point = root.join(mount_point.strip_prefix("/").unwrap_or(mount_point));
+        sync_filesystem(&mount_point);
+        unmount(&mount_point, &dev_node(root, &disk, mount)).map_err(|source| {
+            EjectError::Unmount {
+                mount_point,
+                source,
+            }
+        })?;
+    }
+
+    let disk_node = root
+        .join("dev")
+        .join(dev_name(&disk, &read_uevent(&disk.join("uevent"))));
+    // Writes that bypassed the filesystems are cached by the block device itself
+    if let Ok(file) = File::open(&disk_node) {
+        let _ = file.sync_all();
+    }
+    if options.stop {
+        stop(&disk_node).map_err(|source| EjectError::Stop {
+            dev_node: disk_node.clone(),
+            source,
+        })?;
+    }
+    if options.remove {
+        let path = disk.join("device/delete");
+        std::fs::write(&path, "1").map_err(|source| EjectError::Remove { path, source })?;
+    }
+    Ok(())
+}
+
+/// Device node of the disk or partition `mount` is on.
+fn dev_node(root: &Path, disk: &Path, mount: &Mount) -> PathBuf {
+    let block_device = std::iter::once(disk.to_path_buf())
+        .chain(partitions(disk))
+        .find(|block_device| {
+            parse_dev(&read_attr(&block_device.join("dev"))) == Some((mount.major, mount.minor))
+        })
+        .unwrap_or_else(|| disk.to_path_buf());
+    let uevent = read_uevent(&block_device.join("uevent"));
+    root.join("dev").join(dev_name(&block_device, &uevent))
+}
+
+/// Writes the dirty data of the filesystem mounted at `mount_point` to the device.
+fn sync_filesystem(mount_point: &Path) {
+    // Unmounting flushes too, this only makes sure the data is out if that fails
+    if let Ok(dir) = File::open(mount_point) {
+        unsafe { libc::syncfs(dir.as_raw_fd()) };
+    }
+}
+
+/// Unmounts `mount_point`, falling back to `udisksctl` and `umount` without privileges.
+fn unmount(mount_point: &Path, dev_node: &Path) -> io::Result<()> {
+    let path = CString::new(mount_point.as_os_str().as_bytes())?;
+    if unsafe { libc::umount2(path.as_ptr(), 0) } == 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        // Not a mount point (anymore), e.g. because an automounter got there first
+        Some(libc::EINVAL) | Some(libc::ENOENT) => Ok(()),
+        Some(libc::EPERM) => {
+            let unmounted = run(Command::new("udisksctl")
+                .args(["unmount", "--no-user-interaction", "--block-device"])
+                .arg(dev_node))
+                || run(Command::new("umount").arg(mount_point));
+            if unmounted { Ok(()) } else { Err(error) }
+        }
+        _ => Err(error),
+    }
+}
+
+/// `sg_io_hdr` from `<scsi/sg.h>`.
+#[repr(C)]
+struct SgIoHdr {
+    interface_id: libc::c_int,
+    dxfer_direction: libc::c_int,
+    cmd_len: libc::c_uchar,
+    mx_sb_len: libc::c_uchar,
+    iovec_count: libc::c_ushort,
+    dxfer_len: libc::c_uint,
+    dxferp: *mut libc::c_void,
+    cmdp: *const libc::c_uchar,
+    sbp: *mut libc::c_uchar,
+    timeout: libc::c_uint,
+    flags: libc::c_uint,
+    pack_id: libc::c_int,
+    usr_ptr: *mut libc::c_void,
+    status: libc::c_uchar,
+    masked_status: libc::c_uchar,
+    msg_status: libc::c_uchar,
+    sb_len_wr: libc::c_uchar,
+    host_status: libc::c_ushort,
+    driver_status: libc::c_ushort,
+    resid: libc::c_int,
+    duration: libc::c_uint,
+    info: libc::c_uint,
+}
+
+/// Sends the disk at `dev_node` a SCSI START STOP UNIT command to stop and eject it.
+fn stop(dev_node: &Path) -> io::Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(dev_node)?;
+    let mut sense = [0u8; 32];
+    let mut header = SgIoHdr {
+        interface_id: b'S' as libc::c_int,
+        dxfer_direction: SG_DXFER_NONE,
+        cmd_len: START_STOP_UNIT_EJECT.len() as libc::c_uchar,
+        mx_sb_len: sense.len() as libc::c_uchar,
+        iovec_count: 0,
+        dxfer_len: 0,
+        dxferp: std::ptr::null_mut(),
+        cmdp: START_STOP_UNIT_EJECT.as_ptr(),
+        sbp: sense.as_mut_ptr(),
+        timeout: SCSI_TIMEOUT_MS,
+        flags: 0,
+        pack_id: 0,
+        usr_ptr: std::ptr::null_mut(),
+        status: 0,
+        masked_status: 0,
+        msg_status: 0,
+        sb_len_wr: 0,
+        host_status: 0,
+        driver_status: 0,
+        resid: 0,
+        duration: 0,
+        info: 0,
+    };
+    if unsafe { libc::ioctl(file.as_raw_fd(), SG_IO as _, &mut header) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if header.status != 0 || header.host_status != 0 || header.driver_status != 0 {
+        return Err(io::Error::other(format!(
+            "SCSI status {:#04x}, host status {:#06x}, driver status {:#06x}",
+            header.status, header.host_status, header.driver_status
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usbdef;
+
+    /// Builds a sysfs tree with one unmounted USB disk at `1-1` below `root`.
+    fn fake_disk(root: &Path) -> PathBuf {
+        let scsi = root.join("sys/devices/pci0000:00/usb1/1-1/1-1:1.0/host0/target0:0:0/0:0:0:0");
+        let disk = scsi.join("block/sdb");
+        std::fs::create_dir_all(disk.join("sdb1")).unwrap();
+        std::fs::write(
+            disk.join("uevent"),
+            "MAJOR=8\nMINOR=16\nDEVNAME=sdb\nDEVTYPE=disk\n",
+        )
+        .unwrap();
+        std::fs::write(disk.join("dev"), "8:16\n").unwrap();
+        std::fs::write(disk.join("sdb1/dev"), "8:17\n").unwrap();
+        std::fs::write(disk.join("sdb1/partition"), "1\n").unwrap();
+        std::fs::write(scsi.join("delete"), "").unwrap();
+        std::os::unix::fs::symlink("../../../0:0:0:0", disk.join("device")).unwrap();
+        std::fs::create_dir_all(root.join("sys/class/block")).unwrap();
+        std::os::unix::fs::symlink(
+            "../../devices/pci0000:00/usb1/1-1/1-1:1.0/host0/target0:0:0/0:0:0:0/block/sdb",
+            root.join("sys/class/block/sdb"),
+        )
+        .unwrap();
+        let usb = root.join("sys/devices/pci0000:00/usb1/1-1");
+        std::fs::write(usb.join("idVendor"), "2e8a\n").unwrap();
+        std::fs::write(usb.join("idProduct"), "0003\n").unwrap();
+        std::fs::create_dir_all(root.join("sys/bus/usb/devices")).unwrap();
+        std::os::unix::fs::symlink(
+            "../../../devices/pci0000:00/usb1/1-1",
+            root.join("sys/bus/usb/devices/1-1"),
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join("dev")).unwrap();
+        std::fs::write(root.join("dev/sdb"), "").unwrap();
+        scsi.join("delete")
+    }
+
+    #[test]
+    fn test_eject_remove() {
+        let root = std::env::temp_dir().join(format!("freewili-eject-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let delete = fake_disk(&root);
+        let storage = USBDevice {
+            path: None,
+            ..usbdef::usb_device(usbdef::VID_RPI, usbdef::PID_RPI_2040_UF2, vec![1])
+        };
+
+        let unmount_only = eject_with_root(&storage, &EjectOptions::default(), &root);
+        // An opened volume ejects below the root it was opened with
+        let mounted = USBDevice {
+            path: Some(root.join("media/RPI-RP2").to_string_lossy().into_owned()),
+            ..storage.clone()
+        };
+        let opened = crate::storage::FreeWiliStorage::open_with_root(&mounted, &root)
+            .unwrap()
+            .eject();
+        let kept = std::fs::read_to_string(&delete).unwrap();
+        let removed = eject_with_root(&storage, &EjectOptions::default().with_remove(), &root);
+        let written = std::fs::read_to_string(&delete).unwrap();
+        let missing = eject_with_root(
+            &USBDevice {
+                port_chain: vec![2],
+                ..storage.clone()
+            },
+            &EjectOptions::default(),
+            &root,
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+
+        unmount_only.unwrap();
+        opened.unwrap();
+        assert_eq!(kept, "");
+        removed.unwrap();
+        assert_eq!(written, "1");
+        assert!(matches!(
+            missing,
+            Err(EjectError::Storage(StorageError::NotFound(_)))
+        ));
+    }
+
+    #[test]
+    fn test_eject_options() {
+        assert_eq!(
+            EjectOptions::default(),
+            EjectOptions {
+                stop: false,
+                remove: false
+            }
+        );
+        assert_eq!(
+            EjectOptions::default().with_stop().with_remove(),
+            EjectOptions {
+                stop: true,
+                remove: true
+            }
+        );
+        let serial = usbdef::usb_device(usbdef::VID_ICS, usbdef::PID_MAIN_CDC, vec![3, 4, 1]);
+        assert!(matches!(
+            serial.eject(),
+            Err(EjectError::Storage(StorageError::NotMassStorage))
+        ));
+    }
+}
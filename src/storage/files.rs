@@ -0,0 +1,374 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use thiserror::Error;
+
+use super::eject::eject_with_root;
+use super::{EjectError, EjectOptions, StorageError, StorageInfo, run};
+use crate::{FreeWiliDevice, FreeWiliError, USBDevice, UsbDeviceType};
+
+/// Errors returned when opening or accessing a [`FreeWiliStorage`].
+#[derive(Error, Debug)]
+pub enum FreeWiliStorageError {
+    /// Reading the device failed, for example because it was unplugged
+    #[error(transparent)]
+    Device(#[from] FreeWiliError),
+    /// The device has no mass storage device
+    #[error("Device has no mass storage")]
+    NoStorage,
+    /// The block device of the mass storage device couldn't be found
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    /// The volume isn't mounted and mounting it failed
+    #[error("Failed to mount {}", .dev_node.display())]
+    Mount {
+        /// Device node that was mounted
+        dev_node: PathBuf,
+    },
+    /// The path leaves the volume through `..`, or is the volume root where a file is needed
+    #[error("{} is not a path on the volume", .0.display())]
+    InvalidPath(PathBuf),
+    /// A file operation on the volume failed
+    #[error("Failed to access {}: {source}", .path.display())]
+    Io {
+        /// Path on the volume, relative to its root
+        path: PathBuf,
+        /// Error that occurred
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// File or directory on a [`FreeWiliStorage`] volume.
+///
+/// With the `serde` feature this serializes with the field names below.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StorageEntry {
+    /// File name
+    pub name: String,
+    /// Path relative to the volume root
+    pub path: PathBuf,
+    /// Whether the entry is a directory
+    pub is_dir: bool,
+    /// Size of a file in bytes, 0 for directories
+    pub size: u64,
+}
+
+/// Files on the mounted volume of a FreeWili's mass storage device.
+///
+/// Every path is relative to the volume root. A leading `/` is allowed and also means the
+/// root, `..` is rejected so nothing outside the volume can be touched.
+#[derive(Debug, Clone)]
+pub struct FreeWiliStorage {
+    usb_device: USBDevice,
+    root: PathBuf,
+    /// Directory `sys`, `dev` and `proc` were read from, ejecting reads them there too
+    sys_root: PathBuf,
+}
+
+impl FreeWiliStorage {
+    /// Opens the volume of a [`MassStorage`](UsbDeviceType::MassStorage) device.
+    ///
+    /// The volume is found at the device's [`path`](USBDevice::path) or else in the mount
+    /// table. If it isn't mounted, it is mounted with `udisksctl` like a desktop automounter
+    /// would.
+    pub fn open(usb_device: &USBDevice) -> Result<Self, FreeWiliStorageError> {
+        Self::open_with_root(usb_device, crate::linux::root_from_env())
+    }
+
+    /// Like [`open`](Self::open), but reads `sys`, `dev` and `proc` below `root`, see
+    /// [`StorageInfo::read_with_root`].
+    pub fn open_with_root(
+        usb_device: &USBDevice,
+        root: impl AsRef<Path>,
+    ) -> Result<Self, FreeWiliStorageError> {
+        if usb_device.kind != UsbDeviceType::MassStorage {
+            return Err(StorageError::NotMassStorage.into());
+        }
+        let root = root.as_ref();
+        if let Some(path) = usb_device.path.as_deref().filter(|path| !path.is_empty()) {
+            return Ok(FreeWiliStorage {
+                usb_device: usb_device.clone(),
+                root: PathBuf::from(path),
+                sys_root: root.to_path_buf(),
+            });
+        }
+        let storage = StorageInfo::read_with_root(usb_device, root)?;
+        let mount_point = match storage.mount_point() {
+            Some(mount_point) => mount_point.to_path_buf(),
+            None => {
+                mount(&storage)?;
+                StorageInfo::read_with_root(usb_device, root)?
+                    .mount_point()
+                    .map(Path::to_path_buf)
+                    .ok_or_else(|| FreeWiliStorageError::Mount {
+                        dev_node: filesystem(&storage).dev_node.clone(),
+                    })?
+            }
+        };
+        Ok(FreeWiliStorage {
+            usb_device: usb_device.clone(),
+            root: mount_point,
+            sys_root: root.to_path_buf(),
+        })
+    }
+
+    /// Mass storage device the volume belongs to.
+    pub fn usb_device(&self) -> &USBDevice {
+        &self.usb_device
+    }
+
+    /// Where the volume is mounted.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Lists the directory `dir`, sorted by name. `""` or `"/"` lists the root.
+    pub fn list(&self, dir: impl AsRef<Path>) -> Result<Vec<StorageEntry>, FreeWiliStorageError> {
+        let dir = relative(dir.as_ref())?;
+        let io_error = |source| FreeWiliStorageError::Io {
+            path: dir.clone(),
+            source,
+        };
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(self.root.join(&dir)).map_err(io_error)? {
+            let entry = entry.map_err(io_error)?;
+            let metadata = entry.metadata().map_err(io_error)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            entries.push(StorageEntry {
+                path: dir.join(&name),
+                name,
+                is_dir: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// Reads the file at `path`.
+    pub fn read(&self, path: impl AsRef<Path>) -> Result<Vec<u8>, FreeWiliStorageError> {
+        let path = relative(path.as_ref())?;
+        std::fs::read(self.root.join(&path))
+            .map_err(|source| FreeWiliStorageError::Io { path, source })
+    }
+
+    /// Writes `contents` to the file at `path`, replacing it if it exists.
+    ///
+    /// The file is synced before this returns, so the board can be unplugged right after.
+    /// The directory it is in must exist, see [`mkdir`](Self::mkdir).
+    pub fn write(
+        &self,
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> Result<(), FreeWiliStorageError> {
+        let path = file_path(path.as_ref())?;
+        File::create(self.root.join(&path))
+            .and_then(|mut file| {
+                file.write_all(contents.as_ref())?;
+                file.sync_all()
+            })
+            .map_err(|source| FreeWiliStorageError::Io { path, source })
+    }
+
+    /// Deletes the file or empty directory at `path`.
+    pub fn delete(&self, path: impl AsRef<Path>) -> Result<(), FreeWiliStorageError> {
+        let path = file_path(path.as_ref())?;
+        let full_path = self.root.join(&path);
+        std::fs::symlink_metadata(&full_path)
+            .and_then(|metadata| {
+                if metadata.is_dir() {
+                    std::fs::remove_dir(&full_path)
+                } else {
+                    std::fs::remove_file(&full_path)
+                }
+            })
+            .map_err(|source| FreeWiliStorageError::Io { path, source })
+    }
+
+    /// Creates the directory `path` and any missing parents.
+    pub fn mkdir(&self, path: impl AsRef<Path>) -> Result<(), FreeWiliStorageError> {
+        let path = relative(path.as_ref())?;
+        std::fs::create_dir_all(self.root.join(&path))
+            .map_err(|source| FreeWiliStorageError::Io { path, source })
+    }
+
+    /// Flushes and unmounts the volume, see [`USBDevice::eject`].
+    ///
+    /// Reads `sys`, `dev` and `proc` below the root the volume was opened with.
+    pub fn eject(self) -> Result<(), EjectError> {
+        eject_with_root(&self.usb_device, &EjectOptions::default(), &self.sys_root)
+    }
+}
+
+impl FreeWiliDevice {
+    /// Opens the volume of the device's first mass storage device, see
+    /// [`FreeWiliStorage::open`].
+    ///
+    /// A Free-WiLi with both CPUs in the bootloader has a volume for each, use
+    /// [`FreeWiliStorage::open`] to open a specific one.
+    pub fn storage(&self) -> Result<FreeWiliStorage, FreeWiliStorageError> {
+        let usb_devices = self.get_usb_devices()?;
+        let usb_device = usb_devices
+            .iter()
+            .find(|usb_device| usb_device.kind == UsbDeviceType::MassStorage)
+            .ok_or(FreeWiliStorageError::NoStorage)?;
+        FreeWiliStorage::open(usb_device)
+    }
+}
+
+/// `path` relative to the volume root, without `.` components.
+fn relative(path: &Path) -> Result<PathBuf, FreeWiliStorageError> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(FreeWiliStorageError::InvalidPath(path.to_path_buf()));
+            }
+        }
+    }
+    Ok(relative)
+}
+
+/// Like [`relative`], but rejects the volume root.
+fn file_path(path: &Path) -> Result<PathBuf, FreeWiliStorageError> {
+    let relative = relative(path)?;
+    if relative.as_os_str().is_empty() {
+        return Err(FreeWiliStorageError::InvalidPath(path.to_path_buf()));
+    }
+    Ok(relative)
+}
+
+/// The block device holding the filesystem: the first partition, or the disk if it has
+/// none.
+fn filesystem(storage: &StorageInfo) -> &super::BlockDevice {
+    storage.partitions.first().unwrap_or(&storage.disk)
+}
+
+/// Mounts the filesystem of `storage` with `udisksctl`.
+fn mount(storage: &StorageInfo) -> Result<(), FreeWiliStorageError> {
+    let dev_node = &filesystem(storage).dev_node;
+    let mounted = run(Command::new("udisksctl")
+        .args(["mount", "--no-user-interaction", "--block-device"])
+        .arg(dev_node));
+    if mounted {
+        Ok(())
+    } else {
+        Err(FreeWiliStorageError::Mount {
+            dev_node: dev_node.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+    use crate::testing::FakeFreeWili;
+    use crate::usbdef;
+
+    #[test]
+    fn test_files() {
+        let volume = std::env::temp_dir().join(format!("freewili-storage-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&volume);
+        std::fs::create_dir_all(&volume).unwrap();
+        std::fs::write(volume.join("INFO_UF2.TXT"), "UF2 Bootloader v3.0\n").unwrap();
+        let device = FakeFreeWili::standalone(DeviceType::Uf2, "5A09D5312E24")
+            .with_main_uf2(volume.to_string_lossy())
+            .device();
+        let storage = device.storage().unwrap();
+        assert_eq!(storage.root(), volume);
+
+        storage.mkdir("scripts/wasm").unwrap();
+        storage.write("/scripts/wasm/blink.wasm", b"\0asm").unwrap();
+        storage.write("config.txt", "volume=3\n").unwrap();
+        let root = storage.list("").unwrap();
+        let scripts = storage.list("/scripts/./wasm").unwrap();
+        let config = storage.read("config.txt").unwrap();
+        let not_empty = storage.delete("scripts/wasm");
+        storage.delete("scripts/wasm/blink.wasm").unwrap();
+        storage.delete("scripts/wasm").unwrap();
+        let after = storage.list("scripts").unwrap();
+        let missing = storage.read("scripts/wasm/blink.wasm");
+        std::fs::remove_dir_all(&volume).unwrap();
+
+        let names: Vec<_> = root.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["INFO_UF2.TXT", "config.txt", "scripts"]);
+        assert!(root[2].is_dir);
+        assert_eq!(
+            scripts,
+            [StorageEntry {
+                name: "blink.wasm".to_string(),
+                path: PathBuf::from("scripts/wasm/blink.wasm"),
+                is_dir: false,
+                size: 4,
+            }]
+        );
+        assert_eq!(config, b"volume=3\n");
+        assert!(not_empty.is_err());
+        assert!(after.is_empty());
+        assert!(matches!(
+            missing,
+            Err(FreeWiliStorageError::Io { path, .. }) if path == Path::new("scripts/wasm/blink.wasm")
+        ));
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        let storage = FreeWiliStorage {
+            usb_device: usbdef::usb_device(usbdef::VID_RPI, usbdef::PID_RPI_2040_UF2, vec![1]),
+            root: PathBuf::from("/nonexistent/volume"),
+            sys_root: PathBuf::from("/"),
+        };
+        assert!(matches!(
+            storage.read("../etc/passwd"),
+            Err(FreeWiliStorageError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            storage.mkdir("scripts/../../tmp"),
+            Err(FreeWiliStorageError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            storage.write("/", b""),
+            Err(FreeWiliStorageError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            storage.delete("."),
+            Err(FreeWiliStorageError::InvalidPath(path)) if path == Path::new(".")
+        ));
+        assert_eq!(relative(Path::new("/a/./b")).unwrap(), Path::new("a/b"));
+    }
+
+    #[test]
+    fn test_open() {
+        // Without a path from enumeration the mount point comes from the mount table
+        let Some(root) = crate::linux::fixture_root() else {
+            return;
+        };
+        let usb_device = USBDevice {
+            path: None,
+            ..usbdef::usb_device(usbdef::VID_RPI, usbdef::PID_RPI_2040_UF2, vec![4, 4])
+        };
+        let storage = FreeWiliStorage::open_with_root(&usb_device, &root).unwrap();
+        assert_eq!(storage.root(), root.join("media/fw/RPI-RP2"));
+        assert_eq!(storage.usb_device(), &usb_device);
+
+        let running = FakeFreeWili::new("FW4037")
+            .with_main("/dev/ttyACM0")
+            .device();
+        assert!(matches!(
+            running.storage(),
+            Err(FreeWiliStorageError::NoStorage)
+        ));
+        let serial = usbdef::usb_device(usbdef::VID_ICS, usbdef::PID_MAIN_CDC, vec![3, 4, 1]);
+        assert!(matches!(
+            FreeWiliStorage::open_with_root(&serial, &root),
+            Err(FreeWiliStorageError::Storage(StorageError::NotMassStorage))
+        ));
+    }
+}
@@ -0,0 +1,410 @@
+//! Pure-Rust Linux backend reading USB devices from sysfs.
+//!
+//! Enabled by the `native-linux` feature, which builds without CMake, a C++ compiler,
+//! libclang or libudev. Devices are grouped the same way the C library groups them, so
+//! both backends report the same names, types, port chains and unique IDs:
+//!
+//! - A Microchip USB2513 hub (`0424:2513`) and every USB device behind it form one
+//!   Free-WiLi, named after the FTDI serial number and identified by the hub's port chain.
+//! - Badges, Winky and RP2040 and RP2350 UF2 bootloaders that aren't behind such a hub
+//!   are standalone devices.
+//!
+//! Serial ports come from `/sys/class/tty`, and mass storage paths from the mount points
+//! in `/proc/mounts` of the disk or its partitions.
+//!
+//! [`Enumerator::with_root`] or the `FREEWILI_SYSFS_ROOT` environment variable read all
+//! of these below another directory instead, such as the fixture tree in
+//! `tests/fixtures/sysfs`, so code consuming [`USBDevice`]s can be tested without hardware:
+//!
+//! ```no_run
+//! use freewili_finder_rs::sysfs::Enumerator;
+//!
+//! let devices = Enumerator::new().with_root("tests/fixtures/sysfs").find_all()?;
+//! for device in devices {
+//!     println!("{device}: {}", device.get_main_usb_device()?);
+//! }
+//! # Ok::<(), freewili_finder_rs::FreeWiliError>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::linux::{
+    class_devices, dev_name, partitions, read_attr, read_hex, read_uevent, unescape_mount,
+};
+use crate::usbdef::{PID_HUB, VID_HUB, from_usb_devices, is_standalone_device};
+
+pub use crate::linux::ROOT_ENV;
+use crate::{DeviceSnapshot, FreeWiliDevice, FreeWiliError, PortPath, Result, USBDevice, usbdef};
+
+/// Finds all connected FreeWili devices by reading sysfs.
+///
+/// This is what [`FreeWiliDevice::find_all`] calls when the `ffi` feature is disabled or
+/// [`ROOT_ENV`] is set. Same as `Enumerator::new().find_all()`.
+pub fn find_all() -> Result<Vec<FreeWiliDevice>> {
+    Enumerator::new().find_all()
+}
+
+/// Enumerates FreeWili devices from sysfs below a configurable root directory.
+///
+/// The root stands in for `/`: USB devices are read from `<root>/sys`, mount points from
+/// `<root>/proc/mounts`, and the reported serial ports and mass storage paths are below
+/// `<root>/dev` and `<root>` too, so they exist inside a fixture tree.
+#[derive(Debug, Clone)]
+pub struct Enumerator {
+    root: PathBuf,
+}
+
+impl Default for Enumerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Enumerator {
+    /// Creates an enumerator reading below [`ROOT_ENV`] if it is set, or `/` otherwise.
+    pub fn new() -> Self {
+        Enumerator {
+            root: crate::linux::root_from_env(),
+        }
+    }
+
+    /// Reads `sys`, `dev` and `proc/mounts` below `root` instead.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// Directory standing in for `/`.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Finds all FreeWili devices below the root.
+    pub fn find_all(&self) -> Result<Vec<FreeWiliDevice>> {
+        Ok(self
+            .find_all_snapshots()?
+            .into_iter()
+            .map(FreeWiliDevice::from_snapshot)
+            .collect())
+    }
+}
+
+/// USB device read from `/sys/bus/usb/devices/<name>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SysfsUsbDevice {
+    /// Kernel name, `3-4.1` for a device or `usb3` for a root hub
+    name: String,
+    vid: u16,
+    pid: u16,
+    manufacturer: String,
+    product: String,
+    serial: String,
+    port_chain: Vec<u32>,
+}
+
+impl SysfsUsbDevice {
+    /// Manufacturer and product, leaving out whichever of them is empty.
+    fn display_name(&self) -> String {
+        [self.manufacturer.as_str(), self.product.as_str()]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn usb_device(&self, name: String, port: Option<String>, path: Option<String>) -> USBDevice {
+        USBDevice {
+            name,
+            serial: self.serial.clone(),
+            port,
+            path,
+            ..usbdef::usb_device(self.vid, self.pid, self.port_chain.clone())
+        }
+    }
+}
+
+impl Enumerator {
+    fn find_all_snapshots(&self) -> Result<Vec<DeviceSnapshot>> {
+        let usb_devices = self.usb_devices()?;
+        let ports = self.serial_ports();
+        let paths = self.mass_storage_paths();
+        let usb_device = |device: &SysfsUsbDevice| {
+            device.usb_device(
+                device.display_name(),
+                ports.get(&device.name).cloned(),
+                paths.get(&device.name).cloned(),
+            )
+        };
+
+        let mut devices = Vec::new();
+        for device in usb_devices.values() {
+            if is_fw_hub(device.vid, device.pid) {
+                let prefix = format!("{}.", device.name);
+                let mut children: Vec<USBDevice> = usb_devices
+                    .values()
+                    .filter(|child| child.name.starts_with(&prefix))
+                    .map(usb_device)
+                    .collect();
+                children.push(device.usb_device(device.product.clone(), None, None));
+                devices.push(from_usb_devices(children));
+            } else if is_standalone_device(device.vid, device.pid) {
+                let behind_hub = parent_name(&device.name)
+                    .and_then(|parent| usb_devices.get(&parent))
+                    .is_some_and(|parent| is_fw_hub(parent.vid, parent.pid));
+                if behind_hub {
+                    continue;
+                }
+                devices.push(from_usb_devices(vec![usb_device(device)]));
+            }
+        }
+        devices.sort_by_key(|device| device.unique_id);
+        Ok(devices)
+    }
+
+    /// Reads every USB device, keyed by kernel name. Interfaces are skipped.
+    fn usb_devices(&self) -> Result<BTreeMap<String, SysfsUsbDevice>> {
+        let dir = self.sys().join("bus/usb/devices");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // No USB bus at all, e.g. in a container, like udev this isn't an error
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => {
+                return Err(FreeWiliError::InternalError(Some(format!(
+                    "Failed to read {}: {e}",
+                    dir.display()
+                ))));
+            }
+        };
+
+        let mut devices = BTreeMap::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(port_chain) = port_chain_from_name(&name) else {
+                continue;
+            };
+            let path = entry.path();
+            let (Some(vid), Some(pid)) = (
+                read_hex(&path.join("idVendor")),
+                read_hex(&path.join("idProduct")),
+            ) else {
+                continue;
+            };
+            if vid == 0 || pid == 0 {
+                continue;
+            }
+            let device = SysfsUsbDevice {
+                name: name.clone(),
+                vid,
+                pid,
+                manufacturer: read_attr(&path.join("manufacturer")),
+                product: read_attr(&path.join("product")),
+                serial: read_attr(&path.join("serial")),
+                port_chain,
+            };
+            devices.insert(name, device);
+        }
+        Ok(devices)
+    }
+
+    /// Maps USB device names to the first serial port device node they provide.
+    fn serial_ports(&self) -> BTreeMap<String, String> {
+        let mut ports = BTreeMap::new();
+        for (usb_name, class_path) in class_devices(&self.sys(), "tty") {
+            let uevent = read_uevent(&class_path.join("uevent"));
+            let dev_name = dev_name(&class_path, &uevent);
+            ports
+                .entry(usb_name)
+                .or_insert_with(|| self.root.join("dev").join(dev_name).display().to_string());
+        }
+        ports
+    }
+
+    /// Maps USB device names to the first mount point of their disk or its partitions.
+    fn mass_storage_paths(&self) -> BTreeMap<String, String> {
+        let mounts = read_mounts(&self.root.join("proc/mounts"));
+        let mut paths = BTreeMap::new();
+        for (usb_name, class_path) in class_devices(&self.sys(), "block") {
+            let uevent = read_uevent(&class_path.join("uevent"));
+            if uevent.get("DEVTYPE").map(String::as_str) != Some("disk") {
+                continue;
+            }
+            // The mount table names device nodes as seen from the root, i.e. in /dev
+            let mut dev_nodes = vec![Path::new("/dev").join(dev_name(&class_path, &uevent))];
+            dev_nodes.extend(partitions(&class_path).iter().map(|partition| {
+                Path::new("/dev").join(dev_name(partition, &read_uevent(&partition.join("uevent"))))
+            }));
+            let mount_point = mounts
+                .iter()
+                .find(|(fs_name, _)| {
+                    dev_nodes
+                        .iter()
+                        .any(|dev_node| dev_node == Path::new(fs_name))
+                })
+                .map(|(_, mount_point)| {
+                    let mount_point = Path::new(mount_point);
+                    self.root
+                        .join(mount_point.strip_prefix("/").unwrap_or(mount_point))
+                        .display()
+                        .to_string()
+                });
+            if let Some(mount_point) = mount_point {
+                paths.entry(usb_name).or_insert(mount_point);
+            }
+        }
+        paths
+    }
+
+    fn sys(&self) -> PathBuf {
+        self.root.join("sys")
+    }
+}
+
+fn is_fw_hub(vid: u16, pid: u16) -> bool {
+    vid == VID_HUB && pid == PID_HUB
+}
+
+/// Hub ports of a USB device name, without the bus like the C library's port chains.
+///
+/// `usb3` is the root hub of bus 3, with no ports, and `3-4.1` is port 1 of the hub on
+/// port 4 of bus 3. Interfaces like `3-4.1:1.0` and anything else return `None`.
+fn port_chain_from_name(name: &str) -> Option<Vec<u32>> {
+    Some(PortPath::from_kernel_name(name)?.ports().to_vec())
+}
+
+/// Name of the USB device upstream of `name`, the root hub for devices on a root port.
+fn parent_name(name: &str) -> Option<String> {
+    Some(PortPath::from_kernel_name(name)?.parent()?.to_string())
+}
+
+/// Reads the device and mount point columns of a mount table.
+fn read_mounts(path: &Path) -> Vec<(String, String)> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((
+                unescape_mount(fields.next()?),
+                unescape_mount(fields.next()?),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceType, UsbDeviceType};
+
+    #[test]
+    fn test_port_chain_from_name() {
+        assert_eq!(port_chain_from_name("usb3"), Some(vec![]));
+        assert_eq!(port_chain_from_name("3-4"), Some(vec![4]));
+        assert_eq!(port_chain_from_name("1-3.2.4"), Some(vec![3, 2, 4]));
+        assert_eq!(port_chain_from_name("3-4.1:1.0"), None);
+        assert_eq!(port_chain_from_name("usb3-port1"), None);
+        assert_eq!(port_chain_from_name("ttyACM0"), None);
+
+        assert_eq!(parent_name("3-4.1").as_deref(), Some("3-4"));
+        assert_eq!(parent_name("3-4").as_deref(), Some("usb3"));
+        assert_eq!(parent_name("usb3"), None);
+    }
+
+    #[test]
+    fn test_display_name() {
+        let device = |manufacturer: &str, product: &str| SysfsUsbDevice {
+            name: "3-4.1".to_string(),
+            vid: 0x093c,
+            pid: 0x2054,
+            manufacturer: manufacturer.to_string(),
+            product: product.to_string(),
+            serial: String::new(),
+            port_chain: vec![4, 1],
+        };
+        assert_eq!(
+            device("FreeWili", "MainCPU v73").display_name(),
+            "FreeWili MainCPU v73"
+        );
+        assert_eq!(device("", "RP2 Boot").display_name(), "RP2 Boot");
+        assert_eq!(device("FreeWili", "").display_name(), "FreeWili");
+        assert_eq!(device("", "").display_name(), "");
+    }
+
+    #[test]
+    fn test_fixture_tree() -> Result<()> {
+        let Some(root) = crate::linux::fixture_root() else {
+            return Ok(());
+        };
+        let devices = Enumerator::new().with_root(&root).find_all_snapshots()?;
+        let dev = |name: &str| Some(root.join("dev").join(name).display().to_string());
+
+        // The keyboard and root hubs aren't FreeWili devices, and the RP2040 bootloader
+        // behind the hub is part of the Free-WiLi
+        assert_eq!(devices.len(), 2);
+
+        let winky = &devices[0];
+        assert_eq!(winky.device_type, DeviceType::Winky);
+        assert_eq!(winky.name, "FreeWili Winky");
+        assert_eq!(winky.serial, "WK0042");
+        assert_eq!(winky.unique_id, 2);
+        assert_eq!(winky.main.as_ref().unwrap().port, dev("ttyACM2"));
+
+        let freewili = &devices[1];
+        assert_eq!(freewili.device_type, DeviceType::Freewili);
+        assert_eq!(freewili.serial, "FW4037");
+        assert_eq!(freewili.unique_id, 4);
+        let kinds: Vec<UsbDeviceType> = freewili.usb_devices.iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                UsbDeviceType::SerialMain,
+                UsbDeviceType::SerialDisplay,
+                UsbDeviceType::Ftdi,
+                UsbDeviceType::MassStorage,
+                UsbDeviceType::Hub,
+            ]
+        );
+
+        let main = freewili.main.as_ref().unwrap();
+        assert_eq!(main.name, "FreeWili MainCPU v73");
+        assert_eq!(main.port_chain, [4, 1]);
+        assert_eq!(
+            crate::linux::find_port_path(main, &root),
+            Some("3-4.1".parse().unwrap())
+        );
+        assert_eq!(main.port, dev("ttyACM0"));
+        assert_eq!(
+            main.port_by_id(),
+            dev("serial/by-id/usb-FreeWili_MainCPU_v73_E463A8574B551838-if00")
+        );
+        assert_eq!(
+            main.port_by_path(),
+            dev("serial/by-path/pci-0000:00:14.0-usb-0:4.1:1.0")
+        );
+        assert_eq!(main.stable_port(), main.port_by_id());
+        assert_eq!(freewili.display.as_ref().unwrap().port, dev("ttyACM1"));
+        assert_eq!(
+            freewili.display.as_ref().unwrap().port_by_id(),
+            dev("serial/by-id/usb-FreeWili_DisplayCPU_v55_E463A8574B191638-if00")
+        );
+        assert_eq!(freewili.fpga.as_ref().unwrap().port, dev("ttyUSB0"));
+
+        let storage = &freewili.usb_devices[3];
+        assert_eq!(storage.port, None);
+        assert_eq!(storage.stable_port(), None);
+        assert_eq!(
+            storage.path,
+            Some(root.join("media/fw/RPI-RP2").display().to_string())
+        );
+        assert!(root.join("media/fw/RPI-RP2/INFO_UF2.TXT").is_file());
+
+        let hub = freewili.hub.as_ref().unwrap();
+        assert_eq!(
+            (hub.name.as_str(), hub.port_chain.as_slice()),
+            ("", &[4][..])
+        );
+        Ok(())
+    }
+}
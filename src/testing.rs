@@ -0,0 +1,552 @@
+//! In-memory FreeWili devices for testing code that uses a [`DeviceSource`].
+//!
+//! [`FakeFreeWili`] describes one device and [`FakeSource`] serves a changing set of them,
+//! with devices that can be unplugged and errors that can be injected:
+//!
+//! ```
+//! use freewili_finder_rs::testing::{FakeFreeWili, FakeSource};
+//! use freewili_finder_rs::{DeviceSource, FreeWiliError};
+//!
+//! let source = FakeSource::new().with_device(
+//!     FakeFreeWili::new("FW123")
+//!         .with_main("/dev/ttyACM0")
+//!         .with_display("/dev/ttyACM1"),
+//! );
+//!
+//! let devices = source.find_all()?;
+//! let main = devices[0].get_main_usb_device()?;
+//! assert_eq!(main.port.as_deref(), Some("/dev/ttyACM0"));
+//!
+//! // Injected errors reach every getter of the devices found
+//! source.set_error("FW123", Some(FreeWiliError::MemoryError));
+//! assert_eq!(devices[0].serial(), Err(FreeWiliError::MemoryError));
+//! source.set_error("FW123", None);
+//!
+//! // Devices found before an unplug fail like a stale C handle
+//! source.detach("FW123");
+//! assert_eq!(devices[0].get_main_usb_device(), Err(FreeWiliError::InvalidDevice));
+//! assert_eq!(source.get_usb_devices(&devices[0]), Err(FreeWiliError::InvalidDevice));
+//! assert!(source.find_all()?.is_empty());
+//! # Ok::<(), FreeWiliError>(())
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::usbdef::{self, LOCATION_DISPLAY, LOCATION_FPGA, LOCATION_MAIN};
+use crate::{
+    DeviceSnapshot, DeviceSource, DeviceType, FakeStatus, FreeWiliDevice, FreeWiliError, Result,
+    USBDevice,
+};
+
+/// Port chain of the first device attached to a [`FakeSource`] without one.
+const DEFAULT_PORT_CHAIN: [u32; 2] = [1, 1];
+
+/// Description of a fake FreeWili device.
+///
+/// [`new`](Self::new) builds a Free-WiLi: a hub with the FTDI FPGA interface carrying the
+/// serial number, to which the `with_*` methods add the main and display CPUs.
+/// [`standalone`](Self::standalone) builds a badge, Winky or UF2 bootloader instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeFreeWili {
+    serial: String,
+    port_chain: Option<Vec<u32>>,
+    standalone: bool,
+    /// USB devices by hub port, or the device itself at port 0 when standalone
+    usb_devices: BTreeMap<u32, USBDevice>,
+    error: Option<FreeWiliError>,
+}
+
+impl FakeFreeWili {
+    /// A Free-WiLi with the given serial number and no CPU interfaces yet.
+    pub fn new(serial: impl Into<String>) -> Self {
+        let serial = serial.into();
+        let mut device = FakeFreeWili {
+            serial: serial.clone(),
+            port_chain: None,
+            standalone: false,
+            usb_devices: BTreeMap::new(),
+            error: None,
+        };
+        device.insert(
+            LOCATION_FPGA,
+            usbdef::VID_FTDI,
+            usbdef::PID_FTDI,
+            "Intrepid FreeWili",
+            &serial,
+        );
+        device
+    }
+
+    /// A standalone device of `device_type` with the given serial number.
+    ///
+    /// # Panics
+    ///
+    /// If `device_type` isn't [`Winky`](DeviceType::Winky),
+    /// [`Defcon2024Badge`](DeviceType::Defcon2024Badge),
+    /// [`Defcon2025FwBadge`](DeviceType::Defcon2025FwBadge) or [`Uf2`](DeviceType::Uf2).
+    pub fn standalone(device_type: DeviceType, serial: impl Into<String>) -> Self {
+        let (vid, pid, name) = match device_type {
+            DeviceType::Winky => (usbdef::VID_ICS, usbdef::PID_WINKY, "FreeWili Winky"),
+            DeviceType::Defcon2024Badge => (
+                usbdef::VID_ICS,
+                usbdef::PID_DEFCON_2024,
+                "FreeWili DEFCON 2024 Badge",
+            ),
+            DeviceType::Defcon2025FwBadge => (
+                usbdef::VID_ICS,
+                usbdef::PID_DEFCON_BADGE_2025,
+                "FreeWili DEFCON 2025 Badge",
+            ),
+            DeviceType::Uf2 => (
+                usbdef::VID_RPI,
+                usbdef::PID_RPI_2350_UF2,
+                "Raspberry Pi RP2350 Boot",
+            ),
+            DeviceType::Freewili | DeviceType::Unknown => {
+                panic!("{device_type:?} isn't a standalone device type")
+            }
+        };
+        let serial = serial.into();
+        let mut device = FakeFreeWili {
+            serial: serial.clone(),
+            port_chain: None,
+            standalone: true,
+            usb_devices: BTreeMap::new(),
+            error: None,
+        };
+        device.insert(0, vid, pid, name, &serial);
+        device
+    }
+
+    /// Adds the main CPU serial port, or sets the port of a standalone device.
+    pub fn with_main(mut self, port: impl Into<String>) -> Self {
+        if !self.standalone {
+            self.insert(
+                LOCATION_MAIN,
+                usbdef::VID_ICS,
+                usbdef::PID_MAIN_CDC,
+                "FreeWili MainCPU",
+                "",
+            );
+        }
+        self.interface(self.main_location()).port = Some(port.into());
+        self
+    }
+
+    /// Adds the display CPU serial port.
+    ///
+    /// # Panics
+    ///
+    /// If the device is standalone, those have no display CPU.
+    pub fn with_display(mut self, port: impl Into<String>) -> Self {
+        self.assert_hub("display CPU");
+        self.insert(
+            LOCATION_DISPLAY,
+            usbdef::VID_ICS,
+            usbdef::PID_DISPLAY_CDC,
+            "FreeWili DisplayCPU",
+            "",
+        );
+        self.interface(LOCATION_DISPLAY).port = Some(port.into());
+        self
+    }
+
+    /// Sets the serial port of the FTDI FPGA interface.
+    ///
+    /// # Panics
+    ///
+    /// If the device is standalone, those have no FPGA.
+    pub fn with_fpga(mut self, port: impl Into<String>) -> Self {
+        self.assert_hub("FPGA");
+        self.interface(LOCATION_FPGA).port = Some(port.into());
+        self
+    }
+
+    /// Puts the main CPU into its UF2 bootloader, mounted at `path`.
+    ///
+    /// A standalone device becomes an RP2350 bootloader of type [`DeviceType::Uf2`].
+    pub fn with_main_uf2(mut self, path: impl Into<String>) -> Self {
+        let (location, pid, name) = match self.standalone {
+            true => (0, usbdef::PID_RPI_2350_UF2, "Raspberry Pi RP2350 Boot"),
+            false => (
+                LOCATION_MAIN,
+                usbdef::PID_RPI_2040_UF2,
+                "Raspberry Pi RP2 Boot",
+            ),
+        };
+        // Only a standalone bootloader is the device itself and keeps its serial number
+        let serial = if self.standalone {
+            self.serial.clone()
+        } else {
+            String::new()
+        };
+        self.insert(location, usbdef::VID_RPI, pid, name, &serial);
+        self.interface(location).path = Some(path.into());
+        self
+    }
+
+    /// Puts the display CPU into its UF2 bootloader, mounted at `path`.
+    ///
+    /// # Panics
+    ///
+    /// If the device is standalone, those have no display CPU.
+    pub fn with_display_uf2(mut self, path: impl Into<String>) -> Self {
+        self.assert_hub("display CPU");
+        self.insert(
+            LOCATION_DISPLAY,
+            usbdef::VID_RPI,
+            usbdef::PID_RPI_2040_UF2,
+            "Raspberry Pi RP2 Boot",
+            "",
+        );
+        self.interface(LOCATION_DISPLAY).path = Some(path.into());
+        self
+    }
+
+    /// Sets the port chain of the hub, or of the device itself when standalone.
+    ///
+    /// The unique ID is derived from it. Without one, [`FakeSource`] picks a free port.
+    pub fn with_port_chain(mut self, port_chain: impl Into<Vec<u32>>) -> Self {
+        self.port_chain = Some(port_chain.into());
+        self
+    }
+
+    /// Makes this device fail with `error` once attached, see [`FakeSource::set_error`].
+    pub fn with_error(mut self, error: FreeWiliError) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    /// Serial number the device was created with.
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// State a backend would report for this device.
+    pub fn snapshot(&self) -> DeviceSnapshot {
+        let port_chain = self
+            .port_chain
+            .clone()
+            .unwrap_or_else(|| DEFAULT_PORT_CHAIN.to_vec());
+        let resolve = |location: u32, usb_device: &USBDevice| {
+            let mut port_chain = port_chain.clone();
+            if location != 0 {
+                port_chain.push(location);
+            }
+            USBDevice {
+                name: usb_device.name.clone(),
+                serial: usb_device.serial.clone(),
+                port: usb_device.port.clone(),
+                path: usb_device.path.clone(),
+                ..usbdef::usb_device(usb_device.vid, usb_device.pid, port_chain)
+            }
+        };
+        let mut usb_devices: Vec<USBDevice> = self
+            .usb_devices
+            .iter()
+            .map(|(location, usb_device)| resolve(*location, usb_device))
+            .collect();
+        if !self.standalone {
+            usb_devices.push(usbdef::usb_device(
+                usbdef::VID_HUB,
+                usbdef::PID_HUB,
+                port_chain.clone(),
+            ));
+        }
+        usbdef::from_usb_devices(usb_devices)
+    }
+
+    /// Device handle a backend would return for this device.
+    ///
+    /// It isn't attached to a [`FakeSource`], so it stays valid for as long as it lives.
+    pub fn device(&self) -> FreeWiliDevice {
+        FreeWiliDevice::from_snapshot(self.snapshot())
+    }
+
+    fn main_location(&self) -> u32 {
+        if self.standalone { 0 } else { LOCATION_MAIN }
+    }
+
+    fn insert(&mut self, location: u32, vid: u16, pid: u16, name: &str, serial: &str) {
+        let usb_device = USBDevice {
+            name: name.to_string(),
+            serial: serial.to_string(),
+            ..usbdef::usb_device(vid, pid, Vec::new())
+        };
+        self.usb_devices.insert(location, usb_device);
+    }
+
+    fn interface(&mut self, location: u32) -> &mut USBDevice {
+        self.usb_devices
+            .get_mut(&location)
+            .expect("interface was just inserted")
+    }
+
+    fn assert_hub(&self, interface: &str) {
+        assert!(
+            !self.standalone,
+            "standalone device {} has no {interface}",
+            self.serial
+        );
+    }
+}
+
+/// [`DeviceSource`] serving [`FakeFreeWili`] devices.
+///
+/// Clones share the same devices, so a test can keep one to attach, detach or break
+/// devices while the code under test uses another.
+#[derive(Debug, Clone, Default)]
+pub struct FakeSource {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    devices: Vec<AttachedDevice>,
+    find_all_errors: VecDeque<FreeWiliError>,
+}
+
+/// A device plugged into a [`FakeSource`].
+#[derive(Debug)]
+struct AttachedDevice {
+    device: FakeFreeWili,
+    /// Shared with every handle to the device, holds its injected error or
+    /// [`FreeWiliError::InvalidDevice`] once it is detached
+    status: FakeStatus,
+}
+
+impl AttachedDevice {
+    fn set_status(&self, error: Option<FreeWiliError>) {
+        *self.status.lock().unwrap_or_else(PoisonError::into_inner) = error;
+    }
+}
+
+impl FakeSource {
+    /// Creates a source without any devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attaches `device`, builder style.
+    pub fn with_device(self, device: FakeFreeWili) -> Self {
+        self.attach(device);
+        self
+    }
+
+    /// Plugs `device` in, on a free hub port unless it has a port chain already.
+    pub fn attach(&self, mut device: FakeFreeWili) {
+        let mut state = self.lock();
+        if device.port_chain.is_none() {
+            let used: Vec<u64> = state
+                .devices
+                .iter()
+                .map(|attached| attached.device.snapshot().unique_id)
+                .collect();
+            let port_chain = (DEFAULT_PORT_CHAIN[1]..)
+                .map(|port| vec![DEFAULT_PORT_CHAIN[0], port])
+                .find(|port_chain| !used.contains(&usbdef::unique_id(port_chain)))
+                .expect("ran out of ports");
+            device.port_chain = Some(port_chain);
+        }
+        let status = Arc::new(Mutex::new(device.error.clone()));
+        state.devices.push(AttachedDevice { device, status });
+    }
+
+    /// Unplugs every device with the given serial number, returns whether any was found.
+    ///
+    /// Their [`FreeWiliDevice`]s fail with [`FreeWiliError::InvalidDevice`] afterwards,
+    /// even if a device with the same serial number is attached again.
+    pub fn detach(&self, serial: &str) -> bool {
+        let mut state = self.lock();
+        let count = state.devices.len();
+        state.devices.retain(|attached| {
+            let keep = attached.device.serial != serial;
+            if !keep {
+                attached.set_status(Some(FreeWiliError::InvalidDevice));
+            }
+            keep
+        });
+        state.devices.len() != count
+    }
+
+    /// Makes the device with the given serial number fail with `error`, or work again
+    /// with `None`.
+    ///
+    /// Every getter of its [`FreeWiliDevice`]s returns the error, as does
+    /// [`get_usb_devices`](DeviceSource::get_usb_devices). Returns whether the device was
+    /// found.
+    pub fn set_error(&self, serial: &str, error: Option<FreeWiliError>) -> bool {
+        let mut state = self.lock();
+        let mut found = false;
+        for attached in state.devices.iter_mut() {
+            if attached.device.serial != serial {
+                continue;
+            }
+            attached.device.error = error.clone();
+            attached.set_status(error.clone());
+            found = true;
+        }
+        found
+    }
+
+    /// Makes the next call to [`find_all`](DeviceSource::find_all) fail with `error`.
+    ///
+    /// Calling this repeatedly queues one error per call.
+    pub fn fail_next_find_all(&self, error: FreeWiliError) {
+        self.lock().find_all_errors.push_back(error);
+    }
+
+    /// Devices currently attached.
+    pub fn devices(&self) -> Vec<FakeFreeWili> {
+        self.lock()
+            .devices
+            .iter()
+            .map(|attached| attached.device.clone())
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl DeviceSource for FakeSource {
+    fn find_all(&self) -> Result<Vec<FreeWiliDevice>> {
+        let mut state = self.lock();
+        if let Some(error) = state.find_all_errors.pop_front() {
+            return Err(error);
+        }
+        let mut devices: Vec<(DeviceSnapshot, FakeStatus)> = state
+            .devices
+            .iter()
+            .map(|attached| (attached.device.snapshot(), attached.status.clone()))
+            .collect();
+        devices.sort_by_key(|(device, _)| device.unique_id);
+        Ok(devices
+            .into_iter()
+            .map(|(device, status)| FreeWiliDevice::from_fake(device, status))
+            .collect())
+    }
+
+    fn get_usb_devices(&self, device: &FreeWiliDevice) -> Result<Vec<USBDevice>> {
+        let unique_id = device.unique_id()?;
+        let serial = device.serial()?;
+        let state = self.lock();
+        let fake = state
+            .devices
+            .iter()
+            .map(|attached| (&attached.device, attached.device.snapshot()))
+            .find(|(_, snapshot)| snapshot.unique_id == unique_id && snapshot.serial == serial);
+        match fake {
+            Some((fake, _)) if fake.error.is_some() => Err(fake.error.clone().unwrap()),
+            Some((_, snapshot)) => Ok(snapshot.usb_devices),
+            None => Err(FreeWiliError::InvalidDevice),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceSelector, UsbDeviceType};
+
+    #[test]
+    fn test_fake_freewili() {
+        let device = FakeFreeWili::new("FW123")
+            .with_main("/dev/ttyACM0")
+            .with_display("/dev/ttyACM1")
+            .with_fpga("/dev/ttyUSB0")
+            .with_port_chain([3, 4])
+            .snapshot();
+        assert_eq!(device.device_type, DeviceType::Freewili);
+        assert_eq!(device.name, "Free-WiLi");
+        assert_eq!(device.serial, "FW123");
+        assert_eq!(device.unique_id, 259);
+        let main = device.main.unwrap();
+        assert_eq!(main.kind, UsbDeviceType::SerialMain);
+        assert_eq!(main.port_chain, [3, 4, 1]);
+        assert_eq!(main.port.as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(
+            device.display.unwrap().port.as_deref(),
+            Some("/dev/ttyACM1")
+        );
+        assert_eq!(device.fpga.unwrap().port.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(device.hub.unwrap().port_chain, [3, 4]);
+
+        let device = FakeFreeWili::new("FW123")
+            .with_main_uf2("/media/RPI-RP2")
+            .snapshot();
+        let main = device.main.unwrap();
+        assert_eq!(main.kind, UsbDeviceType::MassStorage);
+        assert_eq!(main.path.as_deref(), Some("/media/RPI-RP2"));
+
+        let device = FakeFreeWili::standalone(DeviceType::Winky, "WK1")
+            .with_main("/dev/ttyACM2")
+            .snapshot();
+        assert_eq!(device.device_type, DeviceType::Winky);
+        assert!(device.standalone);
+        assert_eq!(device.main.unwrap().port.as_deref(), Some("/dev/ttyACM2"));
+        assert_eq!(device.hub, None);
+
+        let device = FakeFreeWili::standalone(DeviceType::Winky, "WK1")
+            .with_main_uf2("/media/RP2350")
+            .snapshot();
+        assert_eq!(device.device_type, DeviceType::Uf2);
+    }
+
+    #[test]
+    fn test_fake_source() -> Result<()> {
+        let source = FakeSource::new()
+            .with_device(FakeFreeWili::new("FW1").with_main("/dev/ttyACM0"))
+            .with_device(FakeFreeWili::standalone(
+                DeviceType::Defcon2025FwBadge,
+                "B1",
+            ));
+        let devices = source.find_all()?;
+        assert_eq!(devices.len(), 2);
+        assert_ne!(devices[0].unique_id()?, devices[1].unique_id()?);
+        assert_eq!(source.get_usb_devices(&devices[0])?.len(), 3);
+
+        let selector = "type=defcon2025fwbadge".parse::<DeviceSelector>().unwrap();
+        let found = source.find(&selector)?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].serial()?, "B1");
+
+        // Injected errors
+        source.fail_next_find_all(FreeWiliError::InternalError(Some("udev".to_string())));
+        assert!(source.find_all().is_err());
+        assert_eq!(source.find_all()?.len(), 2);
+        assert!(source.set_error("FW1", Some(FreeWiliError::MemoryError)));
+        assert_eq!(
+            source.get_usb_devices(&devices[0]),
+            Err(FreeWiliError::MemoryError)
+        );
+        assert_eq!(devices[0].device_type(), Err(FreeWiliError::MemoryError));
+        assert_eq!(
+            devices[0].get_main_usb_device(),
+            Err(FreeWiliError::MemoryError)
+        );
+        assert!(source.find(&DeviceSelector::any())?.len() == 1);
+        source.set_error("FW1", None);
+        assert_eq!(devices[0].serial()?, "FW1");
+
+        // Unplug and reconnect, handles from before the unplug stay invalid
+        assert!(source.detach("FW1"));
+        assert!(!source.detach("FW1"));
+        assert_eq!(
+            source.get_usb_devices(&devices[0]),
+            Err(FreeWiliError::InvalidDevice)
+        );
+        assert_eq!(devices[0].serial(), Err(FreeWiliError::InvalidDevice));
+        assert_eq!(devices[0].snapshot(), Err(FreeWiliError::InvalidDevice));
+        assert_eq!(devices[1].serial()?, "B1");
+        source.attach(FakeFreeWili::new("FW1"));
+        assert_eq!(
+            devices[0].get_main_usb_device(),
+            Err(FreeWiliError::InvalidDevice)
+        );
+        assert_eq!(source.find_all()?.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,704 @@
+use crate::{DeviceSnapshot, FreeWiliDevice, PortPath, Result, USBDevice, UsbDeviceType};
+
+/// Index of a node in a [`UsbTopology`].
+///
+/// Only meaningful for the topology that returned it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+/// What a [`TopologyNode`] is in the USB tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// Top of the tree, the root hub of a bus
+    RootHub,
+    /// Hub, either reported as one or implied by devices behind it
+    Hub,
+    /// Device without anything behind it, such as a serial or mass storage interface
+    Device,
+}
+
+/// A port in a [`UsbTopology`] and the USB devices reported on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopologyNode {
+    bus: Option<u32>,
+    port_chain: Vec<u32>,
+    usb_devices: Vec<USBDevice>,
+    owner: Option<usize>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl TopologyNode {
+    /// Bus of the node, `None` for the tree of USB devices whose bus is unknown, see
+    /// [`USBDevice::bus`].
+    pub fn bus(&self) -> Option<u32> {
+        self.bus
+    }
+
+    /// Hub ports from the root hub to this node, empty for the root hub itself.
+    pub fn port_chain(&self) -> &[u32] {
+        &self.port_chain
+    }
+
+    /// Bus and port chain as a [`PortPath`], `None` if the bus is unknown.
+    pub fn port_path(&self) -> Option<PortPath> {
+        Some(PortPath::new(self.bus?, self.port_chain.clone()))
+    }
+
+    /// Port number on the parent hub, the last element of the port chain, 0 for a root hub.
+    pub fn port(&self) -> u32 {
+        self.port_chain.last().copied().unwrap_or_default()
+    }
+
+    /// Number of hubs between this node and the root hub, 0 for the root hub itself.
+    pub fn depth(&self) -> usize {
+        self.port_chain.len()
+    }
+
+    /// USB devices reported on this port.
+    ///
+    /// Empty for hubs that only exist because devices were found behind them. Usually
+    /// holds one device, several if a backend reported more than one on the same port.
+    pub fn usb_devices(&self) -> &[USBDevice] {
+        &self.usb_devices
+    }
+
+    /// First USB device reported on this port.
+    pub fn usb_device(&self) -> Option<&USBDevice> {
+        self.usb_devices.first()
+    }
+
+    /// Whether this is a root hub, a hub or a device.
+    pub fn kind(&self) -> NodeKind {
+        if self.parent.is_none() {
+            NodeKind::RootHub
+        } else if self.usb_devices.is_empty()
+            || !self.children.is_empty()
+            || self
+                .usb_devices
+                .iter()
+                .any(|usb_device| usb_device.kind == UsbDeviceType::Hub)
+        {
+            NodeKind::Hub
+        } else {
+            NodeKind::Device
+        }
+    }
+}
+
+/// Tree of the USB ports that FreeWili devices are connected through.
+///
+/// Built from the buses and port chains of [`USBDevice`]s: every distinct port chain on a
+/// bus becomes a node, and every prefix of one becomes its parent, up to the root hub of
+/// the bus, so hubs no USB device was reported for still show up. USB devices whose bus
+/// can't be found, see [`USBDevice::bus`], share one more tree with an unknown bus. A
+/// topology built from snapshots also knows which FreeWili each node belongs to.
+///
+/// ```
+/// use freewili_finder_rs::UsbTopology;
+/// use freewili_finder_rs::testing::FakeFreeWili;
+///
+/// let device = FakeFreeWili::new("FW4037")
+///     .with_main("/dev/ttyACM0")
+///     .with_port_chain([4]);
+/// let topology = UsbTopology::from_snapshots(vec![device.snapshot()]);
+///
+/// // Fake devices aren't in sysfs, so their bus is unknown
+/// let main = topology.find(None, &[4, 1]).unwrap();
+/// let hub = topology.parent(main).unwrap();
+/// assert_eq!(topology.node(hub).port_chain(), [4]);
+/// assert_eq!(topology.owner(main).unwrap().serial, "FW4037");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbTopology {
+    nodes: Vec<TopologyNode>,
+    roots: Vec<NodeId>,
+    devices: Vec<DeviceSnapshot>,
+    unplaced: Vec<USBDevice>,
+}
+
+impl UsbTopology {
+    /// Builds the topology of all connected FreeWili devices.
+    pub fn find_all() -> Result<UsbTopology> {
+        Ok(Self::from_snapshots(FreeWiliDevice::find_all_snapshots()?))
+    }
+
+    /// Builds the topology of already found devices.
+    pub fn from_devices(devices: &[FreeWiliDevice]) -> Result<UsbTopology> {
+        Ok(Self::from_snapshots(
+            devices
+                .iter()
+                .map(FreeWiliDevice::snapshot)
+                .collect::<Result<_>>()?,
+        ))
+    }
+
+    /// Builds the topology of device snapshots, recording which device owns which node.
+    pub fn from_snapshots(devices: Vec<DeviceSnapshot>) -> UsbTopology {
+        Self::from_snapshots_on(devices, USBDevice::bus)
+    }
+
+    /// [`from_snapshots`](Self::from_snapshots) with the bus of each USB device from `bus`.
+    fn from_snapshots_on(
+        devices: Vec<DeviceSnapshot>,
+        bus: impl Fn(&USBDevice) -> Option<u32>,
+    ) -> UsbTopology {
+        let mut topology = UsbTopology::default();
+        for (owner, device) in devices.iter().enumerate() {
+            for usb_device in &device.usb_devices {
+                topology.insert(bus(usb_device), usb_device.clone(), Some(owner));
+            }
+        }
+        topology.devices = devices;
+        topology.sort();
+        topology
+    }
+
+    /// Builds the topology of USB devices without owners, for example those of a single
+    /// [`FreeWiliDevice::get_usb_devices`].
+    pub fn from_usb_devices(usb_devices: impl IntoIterator<Item = USBDevice>) -> UsbTopology {
+        let mut topology = UsbTopology::default();
+        for usb_device in usb_devices {
+            topology.insert(usb_device.bus(), usb_device, None);
+        }
+        topology.sort();
+        topology
+    }
+
+    fn insert(&mut self, bus: Option<u32>, usb_device: USBDevice, owner: Option<usize>) {
+        if usb_device.port_chain.is_empty() {
+            self.unplaced.push(usb_device);
+            return;
+        }
+        let id = self.node_for(bus, &usb_device.port_chain);
+        let node = &mut self.nodes[id.0];
+        node.owner = node.owner.or(owner);
+        node.usb_devices.push(usb_device);
+    }
+
+    /// Node for `port_chain` on `bus`, creating it and its missing ancestors.
+    fn node_for(&mut self, bus: Option<u32>, port_chain: &[u32]) -> NodeId {
+        if let Some(id) = self.find(bus, port_chain) {
+            return id;
+        }
+        let parent = port_chain
+            .split_last()
+            .map(|(_, hub)| self.node_for(bus, hub));
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(TopologyNode {
+            bus,
+            port_chain: port_chain.to_vec(),
+            usb_devices: Vec::new(),
+            owner: None,
+            parent,
+            children: Vec::new(),
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// Orders roots by bus and children by port number.
+    fn sort(&mut self) {
+        let positions: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| (node.bus, node.port_chain.clone()))
+            .collect();
+        self.roots.sort_by_key(|id| positions[id.0].clone());
+        for node in &mut self.nodes {
+            node.children.sort_by_key(|id| positions[id.0].clone());
+        }
+    }
+
+    /// Root hubs, one per bus, ordered by bus with the unknown bus first.
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// The node with the given ID.
+    ///
+    /// # Panics
+    ///
+    /// If `id` wasn't returned by this topology.
+    pub fn node(&self, id: NodeId) -> &TopologyNode {
+        &self.nodes[id.0]
+    }
+
+    /// Every node, parents before their children.
+    pub fn nodes(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.roots.iter().flat_map(|&root| self.descendants(root))
+    }
+
+    /// Number of nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether no USB device had a port chain.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The node at `port_chain` on `bus`, if any. An empty port chain finds the root hub.
+    pub fn find(&self, bus: Option<u32>, port_chain: &[u32]) -> Option<NodeId> {
+        let mut id = *self.roots.iter().find(|id| self.nodes[id.0].bus == bus)?;
+        for &port in port_chain {
+            id = *self.nodes[id.0]
+                .children
+                .iter()
+                .find(|child| self.nodes[child.0].port() == port)?;
+        }
+        Some(id)
+    }
+
+    /// Hub the node is connected to, `None` for roots.
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+
+    /// Nodes connected to this one, ordered by port.
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.0].children
+    }
+
+    /// Other nodes on the same hub, or the other root hubs for a root hub, ordered by port.
+    pub fn siblings(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        match self.parent(id) {
+            Some(parent) => self.children(parent),
+            None => self.roots(),
+        }
+        .iter()
+        .copied()
+        .filter(move |&sibling| sibling != id)
+    }
+
+    /// Parent, grandparent and so on up to the root hub.
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), |&id| self.parent(id))
+    }
+
+    /// The node followed by everything behind it, parents before their children.
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut stack = vec![id];
+        std::iter::from_fn(move || {
+            let id = stack.pop()?;
+            stack.extend(self.children(id).iter().rev());
+            Some(id)
+        })
+    }
+
+    /// FreeWili the node's USB devices belong to.
+    ///
+    /// `None` for ports without USB devices and for topologies built with
+    /// [`from_usb_devices`](Self::from_usb_devices).
+    pub fn owner(&self, id: NodeId) -> Option<&DeviceSnapshot> {
+        self.nodes[id.0].owner.map(|owner| &self.devices[owner])
+    }
+
+    /// Nodes owned by the FreeWili with `unique_id`, parents before their children.
+    pub fn nodes_of(&self, unique_id: u64) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes().filter(move |&id| {
+            self.owner(id)
+                .is_some_and(|owner| owner.unique_id == unique_id)
+        })
+    }
+
+    /// Topmost node of the FreeWili with `unique_id`: its hub, or the device itself when
+    /// standalone.
+    pub fn root_of(&self, unique_id: u64) -> Option<NodeId> {
+        self.nodes_of(unique_id)
+            .min_by_key(|&id| self.node(id).depth())
+    }
+
+    /// Devices the topology was built from.
+    pub fn devices(&self) -> &[DeviceSnapshot] {
+        &self.devices
+    }
+
+    /// USB devices without a port chain, which can't be placed in the tree.
+    pub fn unplaced(&self) -> &[USBDevice] {
+        &self.unplaced
+    }
+
+    /// Renders the tree like `lsusb -t`, one node per line.
+    ///
+    /// ```text
+    /// /:  Bus 3: Root hub
+    ///     |__ Port 4: Hub 0424:2513 [Free-WiLi FW4037]
+    ///         |__ Port 1: Serial Main 093c:2054 /dev/ttyACM0
+    ///         |__ Port 3: FTDI 0403:6014 serial FW4037 /dev/ttyUSB0
+    /// ```
+    pub fn to_ascii_tree(&self) -> String {
+        let mut output = String::new();
+        for id in self.nodes() {
+            let node = self.node(id);
+            let position = match node.bus() {
+                _ if node.depth() > 0 => {
+                    format!("{}|__ Port {}", "    ".repeat(node.depth()), node.port())
+                }
+                Some(bus) => format!("/:  Bus {bus}"),
+                None => "/:  Bus ?".to_string(),
+            };
+            output += &format!("{position}: {}", self.label(id).join(" "));
+            if let Some(owner) = self.owned_root(id) {
+                output += &format!(" [{owner}]");
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    /// Renders the tree as a Graphviz DOT digraph, with each FreeWili in its own cluster.
+    pub fn to_dot(&self) -> String {
+        let node = |id: NodeId| {
+            format!(
+                "\"{}\" [label=\"{}\"];",
+                node_name(self.node(id)),
+                self.label(id)
+                    .iter()
+                    .map(|line| dot_escape(line))
+                    .collect::<Vec<_>>()
+                    .join("\\n")
+            )
+        };
+        let mut output = "digraph usb {\n    rankdir=LR;\n    node [shape=box];\n".to_string();
+        for id in self.nodes().filter(|&id| self.owner(id).is_none()) {
+            output += &format!("    {}\n", node(id));
+        }
+        for (owner, device) in self.devices.iter().enumerate() {
+            output += &format!(
+                "    subgraph cluster_{owner} {{\n        label=\"{}\";\n",
+                dot_escape(&device.to_string())
+            );
+            for id in self
+                .nodes()
+                .filter(|&id| self.nodes[id.0].owner == Some(owner))
+            {
+                output += &format!("        {}\n", node(id));
+            }
+            output += "    }\n";
+        }
+        for id in self.nodes() {
+            if let Some(parent) = self.parent(id) {
+                output += &format!(
+                    "    \"{}\" -> \"{}\";\n",
+                    node_name(self.node(parent)),
+                    node_name(self.node(id))
+                );
+            }
+        }
+        output += "}\n";
+        output
+    }
+
+    /// Renders the tree as a Mermaid flowchart, with each FreeWili in its own subgraph.
+    pub fn to_mermaid(&self) -> String {
+        let node = |id: NodeId| {
+            format!(
+                "{}[\"{}\"]",
+                node_name(self.node(id)),
+                self.label(id)
+                    .iter()
+                    .map(|line| mermaid_escape(line))
+                    .collect::<Vec<_>>()
+                    .join("<br/>")
+            )
+        };
+        let mut output = "flowchart LR\n".to_string();
+        for id in self.nodes().filter(|&id| self.owner(id).is_none()) {
+            output += &format!("    {}\n", node(id));
+        }
+        for (owner, device) in self.devices.iter().enumerate() {
+            output += &format!(
+                "    subgraph freewili_{owner} [\"{}\"]\n",
+                mermaid_escape(&device.to_string())
+            );
+            for id in self
+                .nodes()
+                .filter(|&id| self.nodes[id.0].owner == Some(owner))
+            {
+                output += &format!("        {}\n", node(id));
+            }
+            output += "    end\n";
+        }
+        for id in self.nodes() {
+            if let Some(parent) = self.parent(id) {
+                output += &format!(
+                    "    {} --> {}\n",
+                    node_name(self.node(parent)),
+                    node_name(self.node(id))
+                );
+            }
+        }
+        output
+    }
+
+    /// Lines describing a node: type name and VID:PID, serial number and serial port or
+    /// mount path of each USB device on it.
+    fn label(&self, id: NodeId) -> Vec<String> {
+        let node = self.node(id);
+        if node.usb_devices.is_empty() {
+            let kind = match node.kind() {
+                NodeKind::RootHub => "Root hub",
+                _ => "Hub",
+            };
+            return vec![kind.to_string()];
+        }
+        node.usb_devices
+            .iter()
+            .flat_map(|usb_device| {
+                let mut lines = vec![format!(
+                    "{} {:04x}:{:04x}",
+                    usb_device.kind_name, usb_device.vid, usb_device.pid
+                )];
+                if !usb_device.serial.is_empty() {
+                    lines.push(format!("serial {}", usb_device.serial));
+                }
+                lines.extend(usb_device.port.clone().or(usb_device.path.clone()));
+                lines
+            })
+            .collect()
+    }
+
+    /// Owner of a node if it is the topmost node of that owner.
+    fn owned_root(&self, id: NodeId) -> Option<&DeviceSnapshot> {
+        let owner = self.owner(id)?;
+        self.parent(id)
+            .is_none_or(|parent| self.nodes[parent.0].owner != self.nodes[id.0].owner)
+            .then_some(owner)
+    }
+}
+
+/// Identifier of a node in DOT and Mermaid output, unique within a topology.
+fn node_name(node: &TopologyNode) -> String {
+    let bus = node
+        .bus
+        .map_or_else(|| "x".to_string(), |bus| bus.to_string());
+    std::iter::once(bus)
+        .chain(node.port_chain.iter().map(u32::to_string))
+        .fold("usb".to_string(), |name, part| name + "_" + &part)
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+    use crate::testing::FakeFreeWili;
+
+    fn snapshots() -> Vec<DeviceSnapshot> {
+        vec![
+            FakeFreeWili::new("FW4037")
+                .with_main("/dev/ttyACM0")
+                .with_display("/dev/ttyACM1")
+                .with_port_chain([4])
+                .snapshot(),
+            FakeFreeWili::standalone(DeviceType::Winky, "WK0042")
+                .with_main("/dev/ttyACM2")
+                .with_port_chain([2])
+                .snapshot(),
+            FakeFreeWili::new("FW0001")
+                .with_port_chain([1, 2])
+                .snapshot(),
+        ]
+    }
+
+    /// The Winky on bus 1 and both Free-WiLis on bus 3.
+    fn topology() -> UsbTopology {
+        UsbTopology::from_snapshots_on(snapshots(), |usb_device| {
+            Some(if usb_device.port_chain == [2] { 1 } else { 3 })
+        })
+    }
+
+    fn chains(topology: &UsbTopology, ids: impl IntoIterator<Item = NodeId>) -> Vec<String> {
+        ids.into_iter()
+            .map(|id| topology.node(id).port_path().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_tree() {
+        let topology = topology();
+        assert_eq!(
+            chains(&topology, topology.roots().to_vec()),
+            ["usb1", "usb3"]
+        );
+        assert_eq!(
+            chains(&topology, topology.nodes()),
+            [
+                "usb1", "1-2", "usb3", "3-1", "3-1.2", "3-1.2.3", "3-4", "3-4.1", "3-4.2", "3-4.3",
+            ]
+        );
+        assert_eq!(topology.len(), 10);
+        assert!(topology.unplaced().is_empty());
+
+        let bus = topology.find(Some(3), &[]).unwrap();
+        assert_eq!(topology.node(bus).kind(), NodeKind::RootHub);
+        assert_eq!(topology.node(bus).bus(), Some(3));
+        assert_eq!(
+            topology.node(topology.find(Some(3), &[1]).unwrap()).kind(),
+            NodeKind::Hub
+        );
+        let hub = topology.find(Some(3), &[4]).unwrap();
+        assert_eq!(topology.node(hub).kind(), NodeKind::Hub);
+        assert_eq!(topology.node(hub).depth(), 1);
+        let winky = topology.find(Some(1), &[2]).unwrap();
+        assert_eq!(topology.node(winky).kind(), NodeKind::Device);
+        assert_eq!(topology.node(winky).port(), 2);
+        assert_eq!(topology.node(winky).port_chain(), [2]);
+        assert!(topology.find(Some(3), &[4, 9]).is_none());
+        assert!(topology.find(Some(3), &[2]).is_none());
+        assert!(topology.find(None, &[]).is_none());
+    }
+
+    #[test]
+    fn test_unknown_bus() {
+        // Fake devices aren't in sysfs
+        let topology = UsbTopology::from_snapshots(snapshots());
+        assert_eq!(topology.roots().len(), 1);
+        let root = topology.roots()[0];
+        assert_eq!(topology.node(root).bus(), None);
+        assert_eq!(topology.node(root).port_path(), None);
+        assert_eq!(topology.node(root).kind(), NodeKind::RootHub);
+        let hub = topology.find(None, &[4]).unwrap();
+        assert_eq!(topology.parent(hub), Some(root));
+        assert_eq!(topology.children(root).len(), 3);
+        assert!(
+            topology
+                .to_ascii_tree()
+                .starts_with("/:  Bus ?: Root hub\n    |__ Port 1: Hub\n")
+        );
+        assert!(
+            topology
+                .to_dot()
+                .contains("    \"usb_x_4\" -> \"usb_x_4_1\";\n")
+        );
+    }
+
+    #[test]
+    fn test_navigation() {
+        let topology = topology();
+        let display = topology.find(Some(3), &[4, 2]).unwrap();
+        let hub = topology.parent(display).unwrap();
+
+        assert_eq!(
+            chains(&topology, topology.ancestors(display)),
+            ["3-4", "usb3"]
+        );
+        assert_eq!(
+            chains(&topology, topology.siblings(display)),
+            ["3-4.1", "3-4.3"]
+        );
+        assert_eq!(
+            chains(&topology, topology.children(hub).to_vec()),
+            ["3-4.1", "3-4.2", "3-4.3"]
+        );
+        assert_eq!(
+            chains(&topology, topology.siblings(topology.roots()[0])),
+            ["usb3"]
+        );
+        assert_eq!(topology.descendants(hub).count(), 4);
+        assert_eq!(
+            topology.node(display).usb_device().unwrap().kind,
+            UsbDeviceType::SerialDisplay
+        );
+    }
+
+    #[test]
+    fn test_ascii_tree() {
+        assert_eq!(
+            topology().to_ascii_tree(),
+            "\
+/:  Bus 1: Root hub
+    |__ Port 2: Serial Main 093c:2056 serial WK0042 /dev/ttyACM2 [FreeWili Winky WK0042]
+/:  Bus 3: Root hub
+    |__ Port 1: Hub
+        |__ Port 2: Hub 0424:2513 [Free-WiLi FW0001]
+            |__ Port 3: FTDI 0403:6014 serial FW0001
+    |__ Port 4: Hub 0424:2513 [Free-WiLi FW4037]
+        |__ Port 1: Serial Main 093c:2054 /dev/ttyACM0
+        |__ Port 2: Serial Display 093c:2055 /dev/ttyACM1
+        |__ Port 3: FTDI 0403:6014 serial FW4037
+"
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = topology().to_dot();
+        assert!(dot.starts_with("digraph usb {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("    \"usb_3\" [label=\"Root hub\"];\n"));
+        assert!(dot.contains(
+            "    subgraph cluster_0 {\n        label=\"Free-WiLi FW4037\";\n        \"usb_3_4\" [label=\"Hub 0424:2513\"];\n"
+        ));
+        assert!(
+            dot.contains(
+                "        \"usb_3_4_1\" [label=\"Serial Main 093c:2054\\n/dev/ttyACM0\"];\n"
+            )
+        );
+        assert!(dot.contains("    \"usb_3_4\" -> \"usb_3_4_1\";\n"));
+        assert_eq!(dot.matches(" -> ").count(), topology().len() - 2);
+        assert_eq!(dot_escape(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+    }
+
+    #[test]
+    fn test_mermaid() {
+        let mermaid = topology().to_mermaid();
+        assert!(mermaid.starts_with("flowchart LR\n    usb_1[\"Root hub\"]\n"));
+        assert!(mermaid.contains(
+            "    subgraph freewili_1 [\"FreeWili Winky WK0042\"]\n        usb_1_2[\"Serial Main 093c:2056<br/>serial WK0042<br/>/dev/ttyACM2\"]\n    end\n"
+        ));
+        assert!(mermaid.contains("    usb_3_4 --> usb_3_4_2\n"));
+        assert_eq!(mermaid.matches(" --> ").count(), topology().len() - 2);
+        assert_eq!(mermaid_escape(r#"say "hi""#), "say #quot;hi#quot;");
+    }
+
+    #[test]
+    fn test_owners() {
+        let topology = topology();
+        let fw4037 = topology
+            .devices()
+            .iter()
+            .find(|device| device.serial == "FW4037")
+            .unwrap()
+            .unique_id;
+
+        let hub = topology.root_of(fw4037).unwrap();
+        assert_eq!(topology.node(hub).port_chain(), [4]);
+        assert_eq!(topology.nodes_of(fw4037).count(), 4);
+        assert_eq!(
+            topology
+                .owner(topology.find(Some(3), &[4, 3]).unwrap())
+                .unwrap()
+                .serial,
+            "FW4037"
+        );
+        assert_eq!(
+            topology
+                .owner(topology.find(Some(1), &[2]).unwrap())
+                .unwrap()
+                .serial,
+            "WK0042"
+        );
+        assert!(
+            topology
+                .owner(topology.find(Some(3), &[]).unwrap())
+                .is_none()
+        );
+        assert!(topology.root_of(12345).is_none());
+
+        let unowned = UsbTopology::from_usb_devices(topology.devices()[0].usb_devices.clone());
+        assert!(unowned.nodes().all(|id| unowned.owner(id).is_none()));
+    }
+}
@@ -0,0 +1,108 @@
+//! Minimal listener for kernel/udev hotplug notifications over netlink.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+/// Multicast group carrying raw kernel uevents.
+const GROUP_KERNEL: u32 = 1;
+/// Multicast group carrying events re-broadcast by udevd once rules have run.
+const GROUP_UDEV: u32 = 2;
+
+/// Subsystems whose events can change what `fw_device_find_all` reports.
+const RELEVANT_SUBSYSTEMS: [&[u8]; 3] = [b"SUBSYSTEM=usb", b"SUBSYSTEM=tty", b"SUBSYSTEM=block"];
+
+#[derive(Debug)]
+pub(crate) struct UeventMonitor {
+    fd: OwnedFd,
+}
+
+impl UeventMonitor {
+    /// Opens a non-blocking `NETLINK_KOBJECT_UEVENT` socket subscribed to kernel and udev events.
+    pub(crate) fn open() -> io::Result<Self> {
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = GROUP_KERNEL | GROUP_UDEV;
+        let res = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(UeventMonitor { fd })
+    }
+
+    /// Waits up to `timeout` for notifications and drains everything queued.
+    ///
+    /// Returns `true` if at least one event touched a USB, tty or block device.
+    pub(crate) fn wait(&self, timeout: Duration) -> io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let res = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(err)
+            };
+        }
+        if res == 0 {
+            return Ok(false);
+        }
+
+        let mut relevant = false;
+        let mut buffer = vec![0u8; 8192];
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if len < 0 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::WouldBlock => break,
+                    io::ErrorKind::Interrupted => continue,
+                    // The socket overflowed and events were lost, assume one mattered
+                    _ if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                        relevant = true;
+                        continue;
+                    }
+                    _ => return Err(err),
+                }
+            }
+            let message = &buffer[..len as usize];
+            relevant |= RELEVANT_SUBSYSTEMS.iter().any(|subsystem| {
+                message
+                    .windows(subsystem.len())
+                    .any(|window| window == *subsystem)
+            });
+        }
+        Ok(relevant)
+    }
+}
@@ -0,0 +1,733 @@
+//! Parsing and validating UF2 firmware images.
+//!
+//! A [UF2](https://github.com/microsoft/uf2) file is a sequence of 512 byte blocks, each
+//! carrying up to 476 bytes to write at a target address. The bootloaders of the FreeWili
+//! CPUs skip blocks they don't accept without reporting anything, so an image for the
+//! wrong chip or a truncated download only shows up as firmware that never changes.
+//! [`Uf2Image`] catches those before the image is copied:
+//!
+//! ```no_run
+//! use freewili_finder_rs::uf2::{Family, Uf2Image};
+//!
+//! let image = Uf2Image::read("FreeWili.uf2")?;
+//! image.validate_for(Family::Rp2040)?;
+//! for range in image.address_ranges() {
+//!     println!("{:#010x}..{:#010x}", range.start, range.end);
+//! }
+//! # Ok::<(), freewili_finder_rs::uf2::Uf2Error>(())
+//! ```
+
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use thiserror::Error;
+
+mod info;
+
+pub use info::{BootloaderInfo, BootloaderInfoError, INDEX_FILE, INFO_FILE};
+
+/// Size of every block in a UF2 file.
+pub const BLOCK_SIZE: usize = 512;
+/// Largest payload a block can carry.
+pub const MAX_PAYLOAD_SIZE: usize = 476;
+
+const MAGIC_START0: u32 = 0x0A32_4655;
+const MAGIC_START1: u32 = 0x9E5D_5157;
+const MAGIC_END: u32 = 0x0AB1_6F30;
+/// Offset of the payload in a block, after the eight header words.
+const DATA_OFFSET: usize = 32;
+
+/// Block flag: not to be written to flash, for example comments.
+pub const FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+/// Block flag: part of a file container, the word after the block count is a file size.
+pub const FLAG_FILE_CONTAINER: u32 = 0x0000_1000;
+/// Block flag: the word after the block count is a family ID.
+pub const FLAG_FAMILY_ID_PRESENT: u32 = 0x0000_2000;
+/// Block flag: the payload ends with an MD5 checksum of the target range.
+pub const FLAG_MD5_CHECKSUM_PRESENT: u32 = 0x0000_4000;
+/// Block flag: extension tags follow the payload.
+pub const FLAG_EXTENSION_TAGS_PRESENT: u32 = 0x0000_8000;
+
+/// RP2040 flash, where its bootloader writes images.
+const RP2040_FLASH: Range<u32> = 0x1000_0000..0x1100_0000;
+/// RP2040 SRAM, where its bootloader loads images to run without flashing.
+const RP2040_SRAM: Range<u32> = 0x2000_0000..0x2004_2000;
+/// Payload size and alignment the RP2040 bootloader requires.
+const RP2040_PAGE_SIZE: u32 = 256;
+
+/// Chip family a UF2 image is built for, from the blocks' family IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Family {
+    /// Raspberry Pi RP2040
+    Rp2040,
+    /// Raspberry Pi RP2350 running ARM code in secure mode
+    Rp2350ArmSecure,
+    /// Raspberry Pi RP2350 running RISC-V code
+    Rp2350RiscV,
+    /// Raspberry Pi RP2350 running ARM code in non-secure mode
+    Rp2350ArmNonSecure,
+    /// Any Raspberry Pi chip, written to absolute addresses
+    RpAbsolute,
+    /// Any Raspberry Pi chip, data rather than code
+    RpData,
+    /// Family ID of another chip
+    Other(OtherFamily),
+}
+
+/// Family ID that isn't one of the named [`Family`] variants, from [`Family::from_id`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OtherFamily(u32);
+
+impl OtherFamily {
+    /// Family ID stored in the blocks.
+    pub fn id(self) -> u32 {
+        self.0
+    }
+}
+
+impl Family {
+    /// Family with the given family ID.
+    pub fn from_id(id: u32) -> Self {
+        match id {
+            0xE48B_FF56 => Family::Rp2040,
+            0xE48B_FF59 => Family::Rp2350ArmSecure,
+            0xE48B_FF5A => Family::Rp2350RiscV,
+            0xE48B_FF5B => Family::Rp2350ArmNonSecure,
+            0xE48B_FF57 => Family::RpAbsolute,
+            0xE48B_FF58 => Family::RpData,
+            id => Family::Other(OtherFamily(id)),
+        }
+    }
+
+    /// Family ID stored in the blocks.
+    pub fn id(self) -> u32 {
+        match self {
+            Family::Rp2040 => 0xE48B_FF56,
+            Family::Rp2350ArmSecure => 0xE48B_FF59,
+            Family::Rp2350RiscV => 0xE48B_FF5A,
+            Family::Rp2350ArmNonSecure => 0xE48B_FF5B,
+            Family::RpAbsolute => 0xE48B_FF57,
+            Family::RpData => 0xE48B_FF58,
+            Family::Other(other) => other.id(),
+        }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Family::Rp2040 => write!(f, "RP2040"),
+            Family::Rp2350ArmSecure => write!(f, "RP2350 ARM (secure)"),
+            Family::Rp2350RiscV => write!(f, "RP2350 RISC-V"),
+            Family::Rp2350ArmNonSecure => write!(f, "RP2350 ARM (non-secure)"),
+            Family::RpAbsolute => write!(f, "Raspberry Pi absolute"),
+            Family::RpData => write!(f, "Raspberry Pi data"),
+            Family::Other(other) => write!(f, "{:#010x}", other.id()),
+        }
+    }
+}
+
+/// Errors returned when reading or validating a UF2 image.
+#[derive(Error, Debug)]
+pub enum Uf2Error {
+    /// The file couldn't be read
+    #[error("Failed to read UF2 file: {0}")]
+    Io(#[from] std::io::Error),
+    /// The file contains no blocks
+    #[error("UF2 file is empty")]
+    Empty,
+    /// The file size isn't a multiple of the block size
+    #[error("UF2 file size {0} is not a multiple of {BLOCK_SIZE} bytes")]
+    Length(usize),
+    /// A block doesn't start or end with the UF2 magic numbers
+    #[error("UF2 block {block} has invalid magic numbers")]
+    Magic {
+        /// Index of the block in the file
+        block: usize,
+    },
+    /// A block claims a payload larger than fits in a block
+    #[error("UF2 block {block} has a payload of {size} bytes, more than {MAX_PAYLOAD_SIZE}")]
+    PayloadSize {
+        /// Index of the block in the file
+        block: usize,
+        /// Payload size the block claims
+        size: u32,
+    },
+    /// A block's payload runs past the end of the 32 bit address space
+    #[error("UF2 block {block} runs past the end of the address space")]
+    AddressOverflow {
+        /// Index of the block in the file
+        block: usize,
+    },
+    /// A block is out of sequence, so blocks are missing, repeated or reordered
+    #[error("UF2 block {block} is numbered {found}, expected {expected}")]
+    BlockNumber {
+        /// Index of the block in the file
+        block: usize,
+        /// Block number expected at this position
+        expected: u32,
+        /// Block number in the block
+        found: u32,
+    },
+    /// A block disagrees with the previous blocks on the number of blocks
+    #[error("UF2 block {block} claims {found} blocks in total, expected {expected}")]
+    BlockCount {
+        /// Index of the block in the file
+        block: usize,
+        /// Block count of the previous blocks
+        expected: u32,
+        /// Block count in the block
+        found: u32,
+    },
+    /// The file ends before the last block of the image
+    #[error("UF2 file ends after {found} of {expected} blocks")]
+    Truncated {
+        /// Block count the blocks claim
+        expected: u32,
+        /// Number of blocks in the last image of the file
+        found: u32,
+    },
+    /// A block's family ID differs from the previous blocks of the same image
+    #[error(
+        "UF2 block {block} is for {}, the blocks before it are for {}",
+        family_name(*.found),
+        family_name(*.expected)
+    )]
+    MixedFamilies {
+        /// Index of the block in the file
+        block: usize,
+        /// Family of the previous blocks
+        expected: Option<Family>,
+        /// Family of the block
+        found: Option<Family>,
+    },
+    /// The image isn't for the expected family
+    #[error("UF2 image is for {}, expected {expected}", family_names(.found))]
+    WrongFamily {
+        /// Family the image was checked against
+        expected: Family,
+        /// Families in the image
+        found: Vec<Family>,
+    },
+    /// A block the bootloader would ignore, because of its target address or payload
+    #[error(
+        "UF2 block {block} at {address:#010x} with {size} bytes is not accepted by the {family} bootloader"
+    )]
+    Unsupported {
+        /// Index of the block in the file
+        block: usize,
+        /// Family the image was checked against
+        family: Family,
+        /// Target address of the block
+        address: u32,
+        /// Payload size of the block
+        size: u32,
+    },
+}
+
+fn family_name(family: Option<Family>) -> String {
+    family.map_or_else(|| "no family".to_string(), |family| family.to_string())
+}
+
+fn family_names(families: &[Family]) -> String {
+    if families.is_empty() {
+        return "no family".to_string();
+    }
+    families
+        .iter()
+        .map(Family::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// One block of a UF2 file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// `FLAG_*` bits
+    pub flags: u32,
+    /// Address the payload is written to
+    pub target_address: u32,
+    /// Sequence number of the block in its image, starting at 0
+    pub block_number: u32,
+    /// Number of blocks in the image
+    pub block_count: u32,
+    /// Family ID, or file size for file containers
+    pub family_id: u32,
+    /// Payload, at most [`MAX_PAYLOAD_SIZE`] bytes
+    pub data: Vec<u8>,
+}
+
+impl Block {
+    /// Parses and checks one block, `index` being its position in the file.
+    fn parse(index: usize, bytes: &[u8]) -> Result<Self, Uf2Error> {
+        let word = |i: usize| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+        let end = BLOCK_SIZE / 4 - 1;
+        if word(0) != MAGIC_START0 || word(1) != MAGIC_START1 || word(end) != MAGIC_END {
+            return Err(Uf2Error::Magic { block: index });
+        }
+        let size = word(4);
+        if size as usize > MAX_PAYLOAD_SIZE {
+            return Err(Uf2Error::PayloadSize { block: index, size });
+        }
+        let target_address = word(3);
+        if target_address.checked_add(size).is_none() {
+            return Err(Uf2Error::AddressOverflow { block: index });
+        }
+        Ok(Block {
+            flags: word(2),
+            target_address,
+            block_number: word(5),
+            block_count: word(6),
+            family_id: word(7),
+            data: bytes[DATA_OFFSET..DATA_OFFSET + size as usize].to_vec(),
+        })
+    }
+
+    /// Family of the block, if it has a family ID.
+    pub fn family(&self) -> Option<Family> {
+        (self.flags & FLAG_FAMILY_ID_PRESENT != 0).then(|| Family::from_id(self.family_id))
+    }
+
+    /// Whether the bootloader writes the payload, rather than skipping a comment or a
+    /// file container entry.
+    pub fn is_flashed(&self) -> bool {
+        self.flags & (FLAG_NOT_MAIN_FLASH | FLAG_FILE_CONTAINER) == 0
+    }
+
+    /// Addresses the payload is written to.
+    pub fn address_range(&self) -> Range<u32> {
+        // Block::parse rejects blocks that overflow
+        self.target_address..self.target_address + self.data.len() as u32
+    }
+
+    /// Encodes the block, with the unused part of the payload zeroed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the payload is longer than [`MAX_PAYLOAD_SIZE`].
+    pub fn to_bytes(&self) -> [u8; BLOCK_SIZE] {
+        assert!(self.data.len() <= MAX_PAYLOAD_SIZE, "UF2 payload too long");
+        let mut bytes = [0; BLOCK_SIZE];
+        let header = [
+            MAGIC_START0,
+            MAGIC_START1,
+            self.flags,
+            self.target_address,
+            self.data.len() as u32,
+            self.block_number,
+            self.block_count,
+            self.family_id,
+        ];
+        for (i, word) in header.into_iter().enumerate() {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        bytes[DATA_OFFSET..DATA_OFFSET + self.data.len()].copy_from_slice(&self.data);
+        bytes[BLOCK_SIZE - 4..].copy_from_slice(&MAGIC_END.to_le_bytes());
+        bytes
+    }
+}
+
+/// A parsed and checked UF2 file.
+///
+/// Parsing checks the magic numbers and payload size of every block, and that the blocks
+/// are numbered 0 to `block_count - 1` with the same block count and family. A file may
+/// hold several such images back to back, like the images for both RP2350 architectures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uf2Image {
+    blocks: Vec<Block>,
+}
+
+impl Uf2Image {
+    /// Parses the contents of a UF2 file.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Uf2Error> {
+        if bytes.is_empty() {
+            return Err(Uf2Error::Empty);
+        }
+        if !bytes.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(Uf2Error::Length(bytes.len()));
+        }
+        let blocks = bytes
+            .chunks_exact(BLOCK_SIZE)
+            .enumerate()
+            .map(|(index, bytes)| Block::parse(index, bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        check_sequence(&blocks)?;
+        Ok(Uf2Image { blocks })
+    }
+
+    /// Reads and parses a UF2 file.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Uf2Error> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// All blocks, in file order.
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Size of the file in bytes.
+    pub fn file_size(&self) -> usize {
+        self.blocks.len() * BLOCK_SIZE
+    }
+
+    /// Families of the blocks that are written, in order of first appearance.
+    pub fn families(&self) -> Vec<Family> {
+        let mut families = Vec::new();
+        for family in self.flashed().filter_map(Block::family) {
+            if !families.contains(&family) {
+                families.push(family);
+            }
+        }
+        families
+    }
+
+    /// Whether the image contains blocks for `family`.
+    pub fn is_for(&self, family: Family) -> bool {
+        self.families().contains(&family)
+    }
+
+    /// Addresses the written blocks cover, sorted and with adjacent or overlapping
+    /// ranges merged.
+    pub fn address_ranges(&self) -> Vec<Range<u32>> {
+        let mut ranges: Vec<_> = self.flashed().map(Block::address_range).collect();
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u32>> = Vec::new();
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    /// Number of bytes written by the bootloader.
+    pub fn payload_size(&self) -> usize {
+        self.flashed().map(|block| block.data.len()).sum()
+    }
+
+    /// Checks that the bootloader of a `family` chip accepts the image.
+    ///
+    /// The image must contain blocks for `family`. For [`Family::Rp2040`] those blocks
+    /// must also have 256 byte payloads at 256 byte aligned addresses in flash or SRAM,
+    /// which is all its bootloader writes.
+    pub fn validate_for(&self, family: Family) -> Result<(), Uf2Error> {
+        if !self.is_for(family) {
+            return Err(Uf2Error::WrongFamily {
+                expected: family,
+                found: self.families(),
+            });
+        }
+        if family == Family::Rp2040 {
+            let accepted = |block: &Block| {
+                let range = block.address_range();
+                block.data.len() as u32 == RP2040_PAGE_SIZE
+                    && range.start.is_multiple_of(RP2040_PAGE_SIZE)
+                    && [RP2040_FLASH, RP2040_SRAM]
+                        .iter()
+                        .any(|memory| memory.start <= range.start && range.end <= memory.end)
+            };
+            let rejected = self
+                .blocks
+                .iter()
+                .enumerate()
+                .filter(|(_, block)| block.is_flashed() && block.family() == Some(family))
+                .find(|(_, block)| !accepted(block));
+            if let Some((index, block)) = rejected {
+                return Err(Uf2Error::Unsupported {
+                    block: index,
+                    family,
+                    address: block.target_address,
+                    size: block.data.len() as u32,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn flashed(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter().filter(|block| block.is_flashed())
+    }
+}
+
+/// Checks that `blocks` are one or more complete, consistently numbered images.
+fn check_sequence(blocks: &[Block]) -> Result<(), Uf2Error> {
+    // First block of the current image
+    let mut first: Option<&Block> = None;
+    let mut expected = 0;
+    for (index, block) in blocks.iter().enumerate() {
+        let Some(start) = first else {
+            if block.block_number != 0 {
+                return Err(Uf2Error::BlockNumber {
+                    block: index,
+                    expected: 0,
+                    found: block.block_number,
+                });
+            }
+            first = Some(block);
+            expected = 1;
+            if block.block_count == 0 {
+                return Err(Uf2Error::BlockCount {
+                    block: index,
+                    expected: 1,
+                    found: 0,
+                });
+            }
+            if block.block_count == 1 {
+                first = None;
+            }
+            continue;
+        };
+        if block.block_number != expected {
+            return Err(Uf2Error::BlockNumber {
+                block: index,
+                expected,
+                found: block.block_number,
+            });
+        }
+        if block.block_count != start.block_count {
+            return Err(Uf2Error::BlockCount {
+                block: index,
+                expected: start.block_count,
+                found: block.block_count,
+            });
+        }
+        if block.family() != start.family() {
+            return Err(Uf2Error::MixedFamilies {
+                block: index,
+                expected: start.family(),
+                found: block.family(),
+            });
+        }
+        expected += 1;
+        if expected == start.block_count {
+            first = None;
+        }
+    }
+    match first {
+        Some(start) => Err(Uf2Error::Truncated {
+            expected: start.block_count,
+            found: expected,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a block with a family ID and `size` bytes of payload.
+    fn block(address: u32, size: u32, number: u32, count: u32, family: Family) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BLOCK_SIZE);
+        for word in [
+            MAGIC_START0,
+            MAGIC_START1,
+            FLAG_FAMILY_ID_PRESENT,
+            address,
+            size,
+            number,
+            count,
+            family.id(),
+        ] {
+            bytes.extend(word.to_le_bytes());
+        }
+        bytes.extend((0..MAX_PAYLOAD_SIZE).map(|i| i as u8));
+        bytes.extend(MAGIC_END.to_le_bytes());
+        bytes
+    }
+
+    /// An RP2040 image of `count` pages starting at the beginning of flash.
+    fn rp2040(count: u32) -> Vec<u8> {
+        (0..count)
+            .flat_map(|i| block(0x1000_0000 + i * 256, 256, i, count, Family::Rp2040))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let image = Uf2Image::parse(&rp2040(3)).unwrap();
+        assert_eq!(image.blocks().len(), 3);
+        assert_eq!(image.file_size(), 3 * BLOCK_SIZE);
+        assert_eq!(image.payload_size(), 3 * 256);
+        assert_eq!(image.families(), [Family::Rp2040]);
+        assert_eq!(image.address_ranges(), vec![0x1000_0000..0x1000_0300]);
+        let block = &image.blocks()[1];
+        assert_eq!(block.target_address, 0x1000_0100);
+        assert_eq!((block.block_number, block.block_count), (1, 3));
+        assert_eq!(block.data.len(), 256);
+        assert_eq!(block.data[..4], [0, 1, 2, 3]);
+        let mut encoded = rp2040(3)[BLOCK_SIZE..BLOCK_SIZE * 2].to_vec();
+        encoded[DATA_OFFSET + 256..BLOCK_SIZE - 4].fill(0);
+        assert_eq!(block.to_bytes().as_slice(), encoded);
+        image.validate_for(Family::Rp2040).unwrap();
+    }
+
+    #[test]
+    fn test_multiple_images() {
+        // Like the universal RP2350 images: ARM and RISC-V images back to back
+        let mut bytes = Vec::new();
+        bytes.extend(block(0x1000_0000, 256, 0, 2, Family::Rp2350ArmSecure));
+        bytes.extend(block(0x1000_0100, 256, 1, 2, Family::Rp2350ArmSecure));
+        bytes.extend(block(0x1000_1000, 256, 0, 1, Family::Rp2350RiscV));
+        let image = Uf2Image::parse(&bytes).unwrap();
+        assert_eq!(
+            image.families(),
+            [Family::Rp2350ArmSecure, Family::Rp2350RiscV]
+        );
+        assert_eq!(
+            image.address_ranges(),
+            [0x1000_0000..0x1000_0200, 0x1000_1000..0x1000_1100]
+        );
+        image.validate_for(Family::Rp2350RiscV).unwrap();
+        let error = image.validate_for(Family::Rp2040).unwrap_err();
+        assert!(matches!(
+            error,
+            Uf2Error::WrongFamily {
+                expected: Family::Rp2040,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "UF2 image is for RP2350 ARM (secure), RP2350 RISC-V, expected RP2040"
+        );
+    }
+
+    #[test]
+    fn test_corrupt() {
+        assert!(matches!(Uf2Image::parse(&[]), Err(Uf2Error::Empty)));
+        let image = rp2040(3);
+        assert!(matches!(
+            Uf2Image::parse(&image[..1000]),
+            Err(Uf2Error::Length(1000))
+        ));
+        assert!(matches!(
+            Uf2Image::parse(&image[..1024]),
+            Err(Uf2Error::Truncated {
+                expected: 3,
+                found: 2
+            })
+        ));
+
+        let mut bad_magic = image.clone();
+        bad_magic[BLOCK_SIZE * 2 - 1] = 0;
+        assert!(matches!(
+            Uf2Image::parse(&bad_magic),
+            Err(Uf2Error::Magic { block: 1 })
+        ));
+
+        let mut reordered = image[BLOCK_SIZE..].to_vec();
+        reordered.extend(&image[..BLOCK_SIZE]);
+        assert!(matches!(
+            Uf2Image::parse(&reordered),
+            Err(Uf2Error::BlockNumber {
+                block: 0,
+                expected: 0,
+                found: 1
+            })
+        ));
+
+        let mut repeated = image[..BLOCK_SIZE * 2].to_vec();
+        repeated.extend(&image[BLOCK_SIZE..]);
+        assert!(matches!(
+            Uf2Image::parse(&repeated),
+            Err(Uf2Error::BlockNumber {
+                block: 2,
+                expected: 2,
+                found: 1
+            })
+        ));
+
+        let mut bytes = block(0x1000_0000, 256, 0, 2, Family::Rp2040);
+        bytes.extend(block(0x1000_0100, 256, 1, 3, Family::Rp2040));
+        assert!(matches!(
+            Uf2Image::parse(&bytes),
+            Err(Uf2Error::BlockCount {
+                block: 1,
+                expected: 2,
+                found: 3
+            })
+        ));
+
+        let mut bytes = block(0x1000_0000, 256, 0, 2, Family::Rp2040);
+        bytes.extend(block(0x1000_0100, 256, 1, 2, Family::from_id(0x1234)));
+        assert!(matches!(
+            Uf2Image::parse(&bytes),
+            Err(Uf2Error::MixedFamilies {
+                block: 1,
+                expected: Some(Family::Rp2040),
+                found: Some(Family::Other(other))
+            }) if other.id() == 0x1234
+        ));
+
+        assert!(matches!(
+            Uf2Image::parse(&block(0x1000_0000, 477, 0, 1, Family::Rp2040)),
+            Err(Uf2Error::PayloadSize {
+                block: 0,
+                size: 477
+            })
+        ));
+        assert!(matches!(
+            Uf2Image::parse(&block(0xFFFF_FF00, 476, 0, 1, Family::Rp2040)),
+            Err(Uf2Error::AddressOverflow { block: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_validate_rp2040() {
+        for (address, size) in [
+            (0x1000_0000, 476),
+            (0x1000_0080, 256),
+            (0x0000_0000, 256),
+            (0x10FF_FF00 + 256, 256),
+        ] {
+            let image = Uf2Image::parse(&block(address, size, 0, 1, Family::Rp2040)).unwrap();
+            assert!(
+                matches!(
+                    image.validate_for(Family::Rp2040),
+                    Err(Uf2Error::Unsupported { block: 0, address: a, size: s, .. })
+                        if a == address && s == size
+                ),
+                "{address:#x} {size}"
+            );
+        }
+        let sram = Uf2Image::parse(&block(0x2000_0000, 256, 0, 1, Family::Rp2040)).unwrap();
+        sram.validate_for(Family::Rp2040).unwrap();
+    }
+
+    #[test]
+    fn test_skipped_blocks() {
+        let mut bytes = rp2040(1);
+        let mut comment = block(0, 100, 0, 1, Family::Rp2040);
+        comment[8..12]
+            .copy_from_slice(&(FLAG_NOT_MAIN_FLASH | FLAG_FAMILY_ID_PRESENT).to_le_bytes());
+        bytes.extend(comment);
+        let image = Uf2Image::parse(&bytes).unwrap();
+        assert!(!image.blocks()[1].is_flashed());
+        assert_eq!(image.address_ranges(), vec![0x1000_0000..0x1000_0100]);
+        assert_eq!(image.payload_size(), 256);
+        image.validate_for(Family::Rp2040).unwrap();
+    }
+
+    #[test]
+    fn test_family() {
+        for family in [
+            Family::Rp2040,
+            Family::Rp2350ArmSecure,
+            Family::Rp2350RiscV,
+            Family::Rp2350ArmNonSecure,
+            Family::RpAbsolute,
+            Family::RpData,
+            Family::from_id(0x1234),
+        ] {
+            assert_eq!(Family::from_id(family.id()), family);
+        }
+        // Known IDs always come back as the named family
+        assert_eq!(Family::from_id(0xE48B_FF56), Family::Rp2040);
+        assert!(matches!(Family::from_id(0x1234), Family::Other(other) if other.id() == 0x1234));
+        assert_eq!(Family::Rp2040.to_string(), "RP2040");
+        assert_eq!(Family::from_id(0xADA5_2840).to_string(), "0xada52840");
+    }
+}
@@ -0,0 +1,246 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use super::Family;
+use crate::{FreeWiliDevice, FreeWiliError, USBDevice, UsbDeviceType};
+
+/// Name of the file describing the bootloader, in the root of its volume.
+pub const INFO_FILE: &str = "INFO_UF2.TXT";
+/// Name of the file redirecting to the board's web page, in the root of its volume.
+pub const INDEX_FILE: &str = "INDEX.HTM";
+
+/// Contents of the `INFO_UF2.TXT` and `INDEX.HTM` files of a UF2 bootloader volume.
+///
+/// The RP2040 bootloader of the FreeWili CPUs writes
+///
+/// ```text
+/// UF2 Bootloader v3.0
+/// Model: Raspberry Pi RP2
+/// Board-ID: RPI-RP2
+/// ```
+///
+/// With the `serde` feature this serializes with the field names below.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BootloaderInfo {
+    /// Bootloader version from the first line, like `v3.0`
+    pub version: String,
+    /// `Model` field, like `Raspberry Pi RP2`
+    pub model: Option<String>,
+    /// `Board-ID` field, like `RPI-RP2`
+    pub board_id: Option<String>,
+    /// Every `Name: value` field in file order, including the model and board ID
+    pub fields: Vec<(String, String)>,
+    /// Page `INDEX.HTM` redirects to, if it exists
+    pub index_url: Option<String>,
+}
+
+/// Errors returned when reading a [`BootloaderInfo`].
+#[derive(Error, Debug)]
+pub enum BootloaderInfoError {
+    /// Reading the device failed, for example because it was unplugged
+    #[error(transparent)]
+    Device(#[from] FreeWiliError),
+    /// The device isn't a UF2 bootloader with a mounted volume
+    #[error("No mounted UF2 bootloader volume")]
+    NoVolume,
+    /// `INFO_UF2.TXT` couldn't be read
+    #[error("Failed to read {}: {source}", .path.display())]
+    Io {
+        /// Path of the file
+        path: PathBuf,
+        /// Error that occurred
+        #[source]
+        source: std::io::Error,
+    },
+    /// `INFO_UF2.TXT` doesn't start with the `UF2 Bootloader` line
+    #[error("{} is not a UF2 bootloader info file", .0.display())]
+    Invalid(PathBuf),
+}
+
+impl BootloaderInfo {
+    /// Parses the contents of `INFO_UF2.TXT` and, if there is one, `INDEX.HTM`.
+    ///
+    /// Returns `None` if the first line isn't `UF2 Bootloader <version>`.
+    pub fn parse(info: &str, index: Option<&str>) -> Option<Self> {
+        let mut lines = info.lines().map(str::trim).filter(|line| !line.is_empty());
+        let version = lines.next()?.strip_prefix("UF2 Bootloader")?.trim();
+        let fields: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        let mut info = BootloaderInfo {
+            version: version.to_string(),
+            model: None,
+            board_id: None,
+            fields,
+            index_url: index.and_then(redirect_url),
+        };
+        info.model = info.field("Model").map(str::to_string);
+        info.board_id = info.field("Board-ID").map(str::to_string);
+        Some(info)
+    }
+
+    /// Reads the files in the root of the bootloader volume mounted at `volume`.
+    pub fn read(volume: impl AsRef<Path>) -> Result<Self, BootloaderInfoError> {
+        let volume = volume.as_ref();
+        let path = volume.join(INFO_FILE);
+        let info = std::fs::read_to_string(&path).map_err(|source| BootloaderInfoError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let index = std::fs::read_to_string(volume.join(INDEX_FILE)).ok();
+        Self::parse(&info, index.as_deref()).ok_or(BootloaderInfoError::Invalid(path))
+    }
+
+    /// Value of the field `name`, compared case-insensitively.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Families of the images the bootloader accepts, from its board ID, or `None` for
+    /// boards other than the Raspberry Pi chips.
+    pub fn families(&self) -> Option<&'static [Family]> {
+        match self.board_id.as_deref()? {
+            "RPI-RP2" => Some(&[Family::Rp2040]),
+            "RP2350" => Some(&[
+                Family::Rp2350ArmSecure,
+                Family::Rp2350RiscV,
+                Family::Rp2350ArmNonSecure,
+            ]),
+            _ => None,
+        }
+    }
+}
+
+/// The URL in the meta refresh or first link of an `INDEX.HTM`.
+fn redirect_url(index: &str) -> Option<String> {
+    let start = ["URL=", "href="]
+        .iter()
+        .find_map(|attribute| Some(index.find(attribute)? + attribute.len()))?;
+    let rest = &index[start..];
+    let (quote, rest) = match rest.chars().next()? {
+        quote @ ('\'' | '"') => (quote, &rest[1..]),
+        _ => (' ', rest),
+    };
+    let end = rest.find([quote, '"', '>']).unwrap_or(rest.len());
+    let url = rest[..end].trim();
+    (!url.is_empty()).then(|| url.to_string())
+}
+
+impl USBDevice {
+    /// Reads the bootloader info from the volume of a
+    /// [`MassStorage`](UsbDeviceType::MassStorage) device.
+    pub fn bootloader_info(&self) -> Result<BootloaderInfo, BootloaderInfoError> {
+        match (&self.kind, &self.path) {
+            (UsbDeviceType::MassStorage, Some(path)) => BootloaderInfo::read(path),
+            _ => Err(BootloaderInfoError::NoVolume),
+        }
+    }
+}
+
+impl FreeWiliDevice {
+    /// Reads the bootloader info of a device in the bootloader, like a
+    /// [`Uf2`](crate::DeviceType::Uf2) device, from its first mounted bootloader volume.
+    ///
+    /// A Free-WiLi with both CPUs in the bootloader has a volume for each, use
+    /// [`USBDevice::bootloader_info`] to read a specific one.
+    pub fn bootloader_info(&self) -> Result<BootloaderInfo, BootloaderInfoError> {
+        self.get_usb_devices()?
+            .iter()
+            .find(|usb_device| {
+                usb_device.kind == UsbDeviceType::MassStorage && usb_device.path.is_some()
+            })
+            .ok_or(BootloaderInfoError::NoVolume)?
+            .bootloader_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceType;
+    use crate::testing::FakeFreeWili;
+
+    const RP2040_INFO: &str =
+        "UF2 Bootloader v3.0\r\nModel: Raspberry Pi RP2\r\nBoard-ID: RPI-RP2\r\n";
+    const RP2350_INFO: &str = "UF2 Bootloader v1.0\nModel: Raspberry Pi RP2350\nBoard-ID: RP2350\n";
+    const RP2350_INDEX: &str = "<html><head><meta http-equiv=\"refresh\" content=\"0;URL='https://raspberrypi.com/device/RP2?version=5A09D5312E24'\"/></head><body>Redirecting to <a href='https://raspberrypi.com/device/RP2?version=5A09D5312E24'>raspberrypi.com</a></body></html>";
+
+    #[test]
+    fn test_parse() {
+        let info = BootloaderInfo::parse(RP2040_INFO, None).unwrap();
+        assert_eq!(info.version, "v3.0");
+        assert_eq!(info.model.as_deref(), Some("Raspberry Pi RP2"));
+        assert_eq!(info.board_id.as_deref(), Some("RPI-RP2"));
+        assert_eq!(info.field("board-id"), Some("RPI-RP2"));
+        assert_eq!(info.index_url, None);
+        assert_eq!(info.families(), Some(&[Family::Rp2040][..]));
+
+        let info = BootloaderInfo::parse(RP2350_INFO, Some(RP2350_INDEX)).unwrap();
+        assert_eq!(info.board_id.as_deref(), Some("RP2350"));
+        assert_eq!(
+            info.index_url.as_deref(),
+            Some("https://raspberrypi.com/device/RP2?version=5A09D5312E24")
+        );
+        assert!(info.families().unwrap().contains(&Family::Rp2350RiscV));
+
+        // Other UF2 bootloaders add fields and put more than a version on the first line
+        let info = BootloaderInfo::parse(
+            "UF2 Bootloader 0.6.0 lib/nrfx (v2.0.0)\nModel: Adafruit Feather nRF52840\nBoard-ID: nRF52840-Feather-revD\nDate: Jun 16 2021\n",
+            Some("<a href=\"https://www.adafruit.com/product/4062\">"),
+        )
+        .unwrap();
+        assert_eq!(info.version, "0.6.0 lib/nrfx (v2.0.0)");
+        assert_eq!(info.field("Date"), Some("Jun 16 2021"));
+        assert_eq!(info.fields.len(), 3);
+        assert_eq!(
+            info.index_url.as_deref(),
+            Some("https://www.adafruit.com/product/4062")
+        );
+        assert_eq!(info.families(), None);
+
+        assert_eq!(BootloaderInfo::parse("", None), None);
+        assert_eq!(BootloaderInfo::parse("Model: RP2\n", None), None);
+    }
+
+    #[test]
+    fn test_read() {
+        let volume = std::env::temp_dir().join(format!("freewili-uf2-info-{}", std::process::id()));
+        std::fs::create_dir_all(&volume).unwrap();
+        std::fs::write(volume.join(INFO_FILE), RP2350_INFO).unwrap();
+        std::fs::write(volume.join(INDEX_FILE), RP2350_INDEX).unwrap();
+        let device = FakeFreeWili::standalone(DeviceType::Uf2, "5A09D5312E24")
+            .with_main_uf2(volume.to_string_lossy())
+            .device();
+        let info = device.bootloader_info();
+        std::fs::write(volume.join(INFO_FILE), "not a bootloader").unwrap();
+        let invalid = BootloaderInfo::read(&volume);
+        std::fs::remove_dir_all(&volume).unwrap();
+        let missing = BootloaderInfo::read(&volume);
+
+        let info = info.unwrap();
+        assert_eq!(info.model.as_deref(), Some("Raspberry Pi RP2350"));
+        assert!(info.index_url.is_some());
+        assert!(
+            matches!(invalid, Err(BootloaderInfoError::Invalid(path)) if path == volume.join(INFO_FILE))
+        );
+        assert!(matches!(missing, Err(BootloaderInfoError::Io { .. })));
+
+        let running = FakeFreeWili::new("FW4037")
+            .with_main("/dev/ttyACM0")
+            .device();
+        assert!(matches!(
+            running.bootloader_info(),
+            Err(BootloaderInfoError::NoVolume)
+        ));
+        assert!(matches!(
+            running.get_main_usb_device().unwrap().bootloader_info(),
+            Err(BootloaderInfoError::NoVolume)
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+//! USB IDs of FreeWili hardware and how USB devices group into FreeWili devices.
+//!
+//! Ported from `usbdef.hpp` and `fwfinder.cpp` of the C++ library, for the backends that
+//! don't call into it.
+
+use crate::{DeviceSnapshot, DeviceType, USBDevice, UsbDeviceType};
+
+pub(crate) const VID_HUB: u16 = 0x0424;
+pub(crate) const PID_HUB: u16 = 0x2513;
+pub(crate) const VID_FTDI: u16 = 0x0403;
+pub(crate) const PID_FTDI: u16 = 0x6014;
+pub(crate) const VID_RPI: u16 = 0x2E8A;
+pub(crate) const PID_RPI_CDC: u16 = 0x000A;
+pub(crate) const PID_RPI_2040_UF2: u16 = 0x0003;
+pub(crate) const PID_RPI_2350_UF2: u16 = 0x000F;
+pub(crate) const VID_ICS: u16 = 0x093C;
+pub(crate) const PID_MAIN_CDC: u16 = 0x2054;
+pub(crate) const PID_DISPLAY_CDC: u16 = 0x2055;
+pub(crate) const PID_WINKY: u16 = 0x2056;
+pub(crate) const PID_DEFCON_2024: u16 = 0x2057;
+pub(crate) const PID_DEFCON_BADGE_2025: u16 = 0x2058;
+
+/// Hub port of the main CPU, display CPU and FPGA on a Free-WiLi.
+pub(crate) const LOCATION_MAIN: u32 = 1;
+pub(crate) const LOCATION_DISPLAY: u32 = 2;
+pub(crate) const LOCATION_FPGA: u32 = 3;
+
+/// USB device with the kind and location derived from its IDs and port chain, and no
+/// name, serial, port or path yet.
+pub(crate) fn usb_device(vid: u16, pid: u16, port_chain: Vec<u32>) -> USBDevice {
+    let location = port_chain.last().copied().unwrap_or_default();
+    let kind = usb_device_type(vid, pid, location);
+    USBDevice {
+        kind,
+        kind_name: kind.name().to_string(),
+        vid,
+        pid,
+        name: String::new(),
+        serial: String::new(),
+        location,
+        port_chain,
+        port: None,
+        path: None,
+    }
+}
+
+/// Builds a device from its USB devices the way `FreeWiliDevice::fromUSBDevices` does.
+pub(crate) fn from_usb_devices(mut usb_devices: Vec<USBDevice>) -> DeviceSnapshot {
+    // A Free-WiLi CPU in its RP2040 bootloader is part of the hub's group
+    let has_hub = usb_devices
+        .iter()
+        .any(|usb_device| usb_device.kind == UsbDeviceType::Hub);
+    let standalone = usb_devices
+        .iter()
+        .find(|usb_device| !has_hub && is_standalone_device(usb_device.vid, usb_device.pid))
+        .cloned();
+
+    let (device_type, name, serial, unique_id) = match &standalone {
+        Some(usb_device) => (
+            standalone_device_type(usb_device.pid),
+            usb_device.name.clone(),
+            usb_device.serial.clone(),
+            unique_id(&usb_device.port_chain),
+        ),
+        None => {
+            let serial = usb_devices
+                .iter()
+                .find(|usb_device| usb_device.kind == UsbDeviceType::Ftdi)
+                .map_or_else(|| "Unknown".to_string(), |ftdi| ftdi.serial.clone());
+            let unique_id = usb_devices
+                .iter()
+                .find(|usb_device| usb_device.kind == UsbDeviceType::Hub)
+                .map_or(0, |hub| unique_id(&hub.port_chain));
+            usb_devices.sort_by_key(|usb_device| {
+                (usb_device.kind == UsbDeviceType::Hub, usb_device.location)
+            });
+            (
+                DeviceType::Freewili,
+                DeviceType::Freewili.name().to_string(),
+                serial,
+                unique_id,
+            )
+        }
+    };
+
+    let at_location = |location: u32| {
+        usb_devices
+            .iter()
+            .find(|usb_device| {
+                usb_device.location == location
+                    && !matches!(usb_device.kind, UsbDeviceType::Hub | UsbDeviceType::Other)
+            })
+            .cloned()
+    };
+    let (main, display, fpga, hub) = if standalone.is_some() {
+        let main = usb_devices
+            .first()
+            .filter(|usb_device| is_standalone_device(usb_device.vid, usb_device.pid))
+            .cloned();
+        (main, None, None, None)
+    } else {
+        (
+            at_location(LOCATION_MAIN),
+            at_location(LOCATION_DISPLAY),
+            at_location(LOCATION_FPGA),
+            usb_devices
+                .iter()
+                .find(|usb_device| usb_device.kind == UsbDeviceType::Hub)
+                .cloned(),
+        )
+    };
+
+    DeviceSnapshot {
+        unique_id,
+        device_type,
+        device_type_name: device_type.name().to_string(),
+        name,
+        serial,
+        standalone: standalone.is_some(),
+        usb_devices,
+        main,
+        display,
+        fpga,
+        hub,
+    }
+}
+
+/// Whether a device with this VID and PID is a FreeWili on its own when it isn't behind a
+/// Free-WiLi hub. Behind one, the UF2 bootloaders are the hub's CPUs instead.
+pub(crate) fn is_standalone_device(vid: u16, pid: u16) -> bool {
+    (vid == VID_RPI && matches!(pid, PID_RPI_2040_UF2 | PID_RPI_2350_UF2))
+        || (vid == VID_ICS && matches!(pid, PID_WINKY | PID_DEFCON_2024 | PID_DEFCON_BADGE_2025))
+}
+
+fn standalone_device_type(pid: u16) -> DeviceType {
+    match pid {
+        PID_DEFCON_2024 => DeviceType::Defcon2024Badge,
+        PID_DEFCON_BADGE_2025 => DeviceType::Defcon2025FwBadge,
+        PID_WINKY => DeviceType::Winky,
+        PID_RPI_2040_UF2 | PID_RPI_2350_UF2 => DeviceType::Uf2,
+        _ => DeviceType::Unknown,
+    }
+}
+
+/// Same mapping as `getUSBDeviceTypeFrom` in the C library.
+pub(crate) fn usb_device_type(vid: u16, pid: u16, location: u32) -> UsbDeviceType {
+    match (vid, pid) {
+        (VID_HUB, PID_HUB) => UsbDeviceType::Hub,
+        (VID_FTDI, PID_FTDI) => UsbDeviceType::Ftdi,
+        // Older firmware uses the generic RP2040 serial PID, the hub port tells them apart
+        (VID_RPI, PID_RPI_CDC) => match location {
+            LOCATION_MAIN => UsbDeviceType::SerialMain,
+            LOCATION_DISPLAY => UsbDeviceType::SerialDisplay,
+            _ => UsbDeviceType::Serial,
+        },
+        (VID_RPI, PID_RPI_2040_UF2 | PID_RPI_2350_UF2) => UsbDeviceType::MassStorage,
+        (VID_ICS, PID_MAIN_CDC | PID_WINKY | PID_DEFCON_2024 | PID_DEFCON_BADGE_2025) => {
+            UsbDeviceType::SerialMain
+        }
+        (VID_ICS, PID_DISPLAY_CDC) => UsbDeviceType::SerialDisplay,
+        _ => UsbDeviceType::Other,
+    }
+}
+
+/// Packs 6 bits per port, root first, like the C library's unique ID.
+pub(crate) fn unique_id(port_chain: &[u32]) -> u64 {
+    port_chain
+        .iter()
+        .take(64 / 6)
+        .enumerate()
+        .fold(0, |unique_id, (i, port)| {
+            unique_id | (u64::from(port & 0x3F) << (6 * i))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usb_device(vid: u16, pid: u16, port_chain: &[u32], serial: &str) -> USBDevice {
+        USBDevice {
+            serial: serial.to_string(),
+            ..super::usb_device(vid, pid, port_chain.to_vec())
+        }
+    }
+
+    #[test]
+    fn test_unique_id() {
+        assert_eq!(unique_id(&[3, 4]), 259);
+        assert_eq!(unique_id(&[1, 3, 2]), 1 | 3 << 6 | 2 << 12);
+        assert_eq!(unique_id(&[65]), 1);
+    }
+
+    #[test]
+    fn test_usb_device_type() {
+        assert_eq!(
+            usb_device_type(0x2E8A, 0x000A, 1),
+            UsbDeviceType::SerialMain
+        );
+        assert_eq!(
+            usb_device_type(0x2E8A, 0x000A, 2),
+            UsbDeviceType::SerialDisplay
+        );
+        assert_eq!(usb_device_type(0x2E8A, 0x000A, 3), UsbDeviceType::Serial);
+        assert_eq!(
+            usb_device_type(0x2E8A, 0x0003, 1),
+            UsbDeviceType::MassStorage
+        );
+        assert_eq!(
+            usb_device_type(0x093C, 0x2056, 4),
+            UsbDeviceType::SerialMain
+        );
+        assert_eq!(usb_device_type(0x1D6B, 0x0002, 1), UsbDeviceType::Other);
+    }
+
+    #[test]
+    fn test_from_usb_devices() {
+        let device = from_usb_devices(vec![
+            usb_device(VID_FTDI, PID_FTDI, &[3, 4, 3], "FW4037"),
+            usb_device(VID_ICS, PID_DISPLAY_CDC, &[3, 4, 2], "D"),
+            usb_device(VID_ICS, PID_MAIN_CDC, &[3, 4, 1], "M"),
+            usb_device(VID_HUB, PID_HUB, &[3, 4], ""),
+        ]);
+        assert_eq!(device.device_type, DeviceType::Freewili);
+        assert_eq!(device.device_type_name, "Free-WiLi");
+        assert_eq!(device.name, "Free-WiLi");
+        assert_eq!(device.serial, "FW4037");
+        assert_eq!(device.unique_id, 259);
+        assert!(!device.standalone);
+        let locations: Vec<u32> = device.usb_devices.iter().map(|d| d.location).collect();
+        assert_eq!(locations, [1, 2, 3, 4]);
+        assert_eq!(device.main.unwrap().serial, "M");
+        assert_eq!(device.display.unwrap().serial, "D");
+        assert_eq!(device.fpga.unwrap().kind, UsbDeviceType::Ftdi);
+        assert_eq!(device.hub.unwrap().kind, UsbDeviceType::Hub);
+
+        let device = from_usb_devices(vec![usb_device(VID_ICS, PID_WINKY, &[1, 2], "WK1")]);
+        assert_eq!(device.device_type, DeviceType::Winky);
+        assert_eq!(device.serial, "WK1");
+        assert_eq!(device.unique_id, 1 | 2 << 6);
+        assert!(device.standalone);
+        assert_eq!(device.main.unwrap().kind, UsbDeviceType::SerialMain);
+        assert_eq!(
+            (device.display, device.fpga, device.hub),
+            (None, None, None)
+        );
+
+        // A standalone RP2040 board in its bootloader, and a Free-WiLi CPU in the same one
+        let device = from_usb_devices(vec![usb_device(
+            VID_RPI,
+            PID_RPI_2040_UF2,
+            &[1, 3],
+            "E66164084319392A",
+        )]);
+        assert_eq!(device.device_type, DeviceType::Uf2);
+        assert_eq!(device.serial, "E66164084319392A");
+        assert!(device.standalone);
+        assert_eq!(device.main.unwrap().kind, UsbDeviceType::MassStorage);
+        let device = from_usb_devices(vec![
+            usb_device(VID_FTDI, PID_FTDI, &[3, 4, 3], "FW4037"),
+            usb_device(VID_RPI, PID_RPI_2040_UF2, &[3, 4, 1], ""),
+            usb_device(VID_HUB, PID_HUB, &[3, 4], ""),
+        ]);
+        assert_eq!(device.device_type, DeviceType::Freewili);
+        assert_eq!(device.serial, "FW4037");
+        assert!(!device.standalone);
+        assert_eq!(device.main.unwrap().kind, UsbDeviceType::MassStorage);
+
+        let device = from_usb_devices(vec![usb_device(VID_HUB, PID_HUB, &[2, 1], "")]);
+        assert_eq!(device.serial, "Unknown");
+        assert_eq!(device.main, None);
+    }
+}
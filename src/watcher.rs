@@ -0,0 +1,402 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::{DeviceSnapshot, DeviceSource, FreeWiliError, Result, SystemDevices};
+
+/// Time to wait for follow-up notifications before rescanning, so the tty and
+/// block nodes of a freshly attached FreeWili exist by the time we enumerate.
+#[cfg(target_os = "linux")]
+const SETTLE_DELAY: Duration = Duration::from_millis(250);
+/// Upper bound on settling when notifications keep arriving.
+#[cfg(target_os = "linux")]
+const MAX_SETTLE: Duration = Duration::from_secs(2);
+
+/// Change in the set of connected FreeWili devices, keyed by `unique_id`.
+///
+/// With the `serde` feature this serializes as `{"event": "attached", "device": {...}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "event", content = "device", rename_all = "lowercase")
+)]
+pub enum DeviceEvent {
+    /// A device with a new unique ID appeared
+    Attached(DeviceSnapshot),
+    /// A previously seen device is gone, carries its last known state
+    Detached(DeviceSnapshot),
+    /// A device is still present but its name, serial or USB devices changed
+    Changed(DeviceSnapshot),
+}
+
+impl DeviceEvent {
+    /// Unique ID of the device this event refers to.
+    pub fn unique_id(&self) -> u64 {
+        self.device().unique_id
+    }
+
+    /// State of the device this event refers to.
+    pub fn device(&self) -> &DeviceSnapshot {
+        match self {
+            DeviceEvent::Attached(device)
+            | DeviceEvent::Detached(device)
+            | DeviceEvent::Changed(device) => device,
+        }
+    }
+}
+
+/// Reports FreeWili devices being plugged in, removed or re-enumerated.
+///
+/// On Linux the watcher listens for udev netlink notifications and rescans as soon as
+/// a USB, tty or block device changes. A full rescan also runs every
+/// [`rescan_interval`](Self::with_rescan_interval), which is the only mechanism on other
+/// platforms or when the netlink socket can't be opened.
+///
+/// Devices already connected are reported as [`DeviceEvent::Attached`] on the first poll.
+/// A scan that fails doesn't end the watcher: it is retried at the rescan interval, and
+/// [`last_error`](Self::last_error) reports the failure until a scan succeeds again.
+///
+/// Note that the C library keeps reporting a device for as long as a
+/// [`FreeWiliDevice`](crate::FreeWiliDevice) referring to it is alive, so drop devices
+/// obtained from [`FreeWiliDevice::find_all`](crate::FreeWiliDevice::find_all) to see
+/// them detach.
+///
+/// ```no_run
+/// use freewili_finder_rs::{DeviceEvent, FreeWiliWatcher};
+///
+/// for event in FreeWiliWatcher::new() {
+///     match event {
+///         DeviceEvent::Attached(device) => println!("+ {device}"),
+///         DeviceEvent::Detached(device) => println!("- {device}"),
+///         DeviceEvent::Changed(device) => println!("~ {device}"),
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct FreeWiliWatcher {
+    source: Box<dyn WatchSource>,
+    tracker: DeviceTracker,
+    pending: VecDeque<DeviceEvent>,
+    rescan_interval: Duration,
+    last_scan: Option<Instant>,
+    last_error: Option<FreeWiliError>,
+    #[cfg(target_os = "linux")]
+    monitor: Option<crate::uevent::UeventMonitor>,
+}
+
+impl Default for FreeWiliWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FreeWiliWatcher {
+    /// Interval between full rescans when no hotplug notification arrives.
+    pub const DEFAULT_RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+    /// Creates a watcher, subscribing to hotplug notifications where available.
+    pub fn new() -> Self {
+        FreeWiliWatcher {
+            source: Box::new(SystemDevices),
+            tracker: DeviceTracker::default(),
+            pending: VecDeque::new(),
+            rescan_interval: Self::DEFAULT_RESCAN_INTERVAL,
+            last_scan: None,
+            last_error: None,
+            #[cfg(target_os = "linux")]
+            monitor: crate::uevent::UeventMonitor::open().ok(),
+        }
+    }
+
+    /// Watches the devices of `source` instead of the connected ones, for example a
+    /// [`FakeSource`](crate::testing::FakeSource).
+    pub fn with_source(mut self, source: impl DeviceSource + fmt::Debug + Send + 'static) -> Self {
+        self.source = Box::new(source);
+        self
+    }
+
+    /// Sets the interval between periodic full rescans.
+    pub fn with_rescan_interval(mut self, interval: Duration) -> Self {
+        self.rescan_interval = interval;
+        self
+    }
+
+    /// Returns `true` if the watcher receives hotplug notifications instead of only polling.
+    pub fn uses_hotplug_notifications(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            self.monitor.is_some()
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            false
+        }
+    }
+
+    /// Devices seen in the most recent scan, ordered by unique ID.
+    pub fn devices(&self) -> impl Iterator<Item = &DeviceSnapshot> {
+        self.tracker.known.values()
+    }
+
+    /// Error of the most recent scan, `None` once a scan succeeds.
+    pub fn last_error(&self) -> Option<&FreeWiliError> {
+        self.last_error.as_ref()
+    }
+
+    /// Enumerates devices now and queues events for any differences.
+    pub fn rescan(&mut self) -> Result<()> {
+        // Counted even if the scan fails, so errors are retried at the rescan interval
+        self.last_scan = Some(Instant::now());
+        match scan(&*self.source) {
+            Ok(devices) => {
+                self.last_error = None;
+                self.pending.extend(self.tracker.update(devices));
+                Ok(())
+            }
+            Err(e) => {
+                self.last_error = Some(e.clone());
+                Err(e)
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the next event.
+    ///
+    /// Returns `None` if nothing changed before the timeout elapsed. Scans that fail
+    /// meanwhile are retried at the rescan interval, see [`last_error`](Self::last_error).
+    pub fn poll(&mut self, timeout: Duration) -> Option<DeviceEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+
+            let now = Instant::now();
+            let next_scan = self
+                .last_scan
+                .map_or(now, |last_scan| last_scan + self.rescan_interval);
+            if next_scan <= now {
+                // Kept in `last_error`
+                let _ = self.rescan();
+                continue;
+            }
+            if now >= deadline {
+                return None;
+            }
+
+            if self.wait_for_hotplug(next_scan.min(deadline) - now) {
+                self.settle();
+                let _ = self.rescan();
+            }
+        }
+    }
+
+    /// Blocks until the next event.
+    pub fn next_event(&mut self) -> DeviceEvent {
+        loop {
+            if let Some(event) = self.poll(Duration::from_secs(3600)) {
+                return event;
+            }
+        }
+    }
+
+    /// Sleeps for up to `timeout`, returning early with `true` on a relevant notification.
+    fn wait_for_hotplug(&mut self, timeout: Duration) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Some(monitor) = &self.monitor {
+            match monitor.wait(timeout) {
+                Ok(triggered) => return triggered,
+                Err(_) => {
+                    // Fall back to periodic rescans only
+                    self.monitor = None;
+                    return false;
+                }
+            }
+        }
+        std::thread::sleep(timeout);
+        false
+    }
+
+    /// Waits for a burst of notifications to quiet down.
+    fn settle(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(monitor) = &self.monitor {
+            let started = Instant::now();
+            while started.elapsed() < MAX_SETTLE {
+                if !matches!(monitor.wait(SETTLE_DELAY), Ok(true)) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for FreeWiliWatcher {
+    type Item = DeviceEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
+
+/// Source a watcher owns, which moves to another thread with it.
+trait WatchSource: DeviceSource + fmt::Debug + Send {}
+
+impl<S: DeviceSource + fmt::Debug + Send> WatchSource for S {}
+
+/// Enumerates all devices and reads each one.
+fn scan<S: DeviceSource + ?Sized>(source: &S) -> Result<Vec<DeviceSnapshot>> {
+    let mut snapshots = Vec::new();
+    for device in source.find_all()? {
+        match source.snapshot(&device) {
+            Ok(snapshot) => snapshots.push(snapshot),
+            // Disconnected between enumeration and reading it back
+            Err(FreeWiliError::InvalidDevice) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(snapshots)
+}
+
+/// Last known device set, turning consecutive scans into events.
+#[derive(Debug, Default)]
+struct DeviceTracker {
+    known: BTreeMap<u64, DeviceSnapshot>,
+}
+
+impl DeviceTracker {
+    fn update(&mut self, devices: Vec<DeviceSnapshot>) -> Vec<DeviceEvent> {
+        let mut current: BTreeMap<u64, DeviceSnapshot> = devices
+            .into_iter()
+            .map(|device| (device.unique_id, device))
+            .collect();
+
+        let mut events = Vec::new();
+        for (unique_id, device) in &self.known {
+            if !current.contains_key(unique_id) {
+                events.push(DeviceEvent::Detached(device.clone()));
+            }
+        }
+        for (unique_id, device) in &current {
+            match self.known.get(unique_id) {
+                None => events.push(DeviceEvent::Attached(device.clone())),
+                Some(previous) if previous != device => {
+                    events.push(DeviceEvent::Changed(device.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+
+        std::mem::swap(&mut self.known, &mut current);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeFreeWili, FakeSource};
+    use crate::{DeviceType, FreeWiliDevice, USBDevice};
+
+    fn snapshot(unique_id: u64, serial: &str) -> DeviceSnapshot {
+        DeviceSnapshot {
+            unique_id,
+            device_type: DeviceType::Freewili,
+            device_type_name: "Free-WiLi".to_string(),
+            name: "Free-WiLi".to_string(),
+            serial: serial.to_string(),
+            standalone: false,
+            usb_devices: Vec::new(),
+            main: None,
+            display: None,
+            fpga: None,
+            hub: None,
+        }
+    }
+
+    #[test]
+    fn test_tracker_events() {
+        let mut tracker = DeviceTracker::default();
+
+        let events = tracker.update(vec![snapshot(1, "FW1"), snapshot(2, "FW2")]);
+        assert_eq!(
+            events,
+            vec![
+                DeviceEvent::Attached(snapshot(1, "FW1")),
+                DeviceEvent::Attached(snapshot(2, "FW2")),
+            ]
+        );
+
+        assert!(
+            tracker
+                .update(vec![snapshot(2, "FW2"), snapshot(1, "FW1")])
+                .is_empty()
+        );
+
+        let events = tracker.update(vec![snapshot(2, "FW3")]);
+        assert_eq!(
+            events,
+            vec![
+                DeviceEvent::Detached(snapshot(1, "FW1")),
+                DeviceEvent::Changed(snapshot(2, "FW3")),
+            ]
+        );
+        assert_eq!(events[1].unique_id(), 2);
+    }
+
+    /// Unplugs a device right after enumerating it, before the scan reads it back.
+    struct DetachingSource {
+        source: FakeSource,
+        serial: &'static str,
+    }
+
+    impl DeviceSource for DetachingSource {
+        fn find_all(&self) -> Result<Vec<FreeWiliDevice>> {
+            let devices = self.source.find_all()?;
+            self.source.detach(self.serial);
+            Ok(devices)
+        }
+
+        fn get_usb_devices(&self, device: &FreeWiliDevice) -> Result<Vec<USBDevice>> {
+            self.source.get_usb_devices(device)
+        }
+    }
+
+    #[test]
+    fn test_scan_skips_detached() {
+        let source = FakeSource::new()
+            .with_device(FakeFreeWili::new("FW1").with_port_chain([1, 1]))
+            .with_device(FakeFreeWili::new("FW2").with_port_chain([1, 2]));
+        let detaching = DetachingSource {
+            source: source.clone(),
+            serial: "FW1",
+        };
+        let devices = scan(&detaching).unwrap();
+        let serials: Vec<&str> = devices.iter().map(|d| d.serial.as_str()).collect();
+        assert_eq!(serials, ["FW2"]);
+
+        // Other errors still fail the scan
+        source.set_error("FW2", Some(FreeWiliError::MemoryError));
+        assert_eq!(scan(&source), Err(FreeWiliError::MemoryError));
+    }
+
+    #[test]
+    fn test_failed_scan_is_retried() {
+        let source = FakeSource::new().with_device(FakeFreeWili::new("FW1"));
+        source.fail_next_find_all(FreeWiliError::InternalError(None));
+        let mut watcher = FreeWiliWatcher::new()
+            .with_source(source)
+            .with_rescan_interval(Duration::from_millis(10));
+
+        assert_eq!(watcher.poll(Duration::ZERO), None);
+        assert_eq!(
+            watcher.last_error(),
+            Some(&FreeWiliError::InternalError(None))
+        );
+
+        let event = watcher.poll(Duration::from_secs(5)).unwrap();
+        assert!(matches!(event, DeviceEvent::Attached(ref device) if device.serial == "FW1"));
+        assert_eq!(watcher.last_error(), None);
+    }
+}